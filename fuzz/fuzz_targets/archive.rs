@@ -0,0 +1,21 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use moiety::mhk::MhkArchive;
+use smol::io::{AsyncReadExt, Cursor};
+
+fuzz_target!(|data: &[u8]| {
+    smol::block_on(async {
+        let arc = match MhkArchive::new(Cursor::new(data.to_owned())).await {
+            Ok(arc) => arc,
+            Err(_) => return,
+        };
+        for (typ, rs) in &arc.resources {
+            for id in rs.keys() {
+                if let Ok(mut handle) = arc.open(typ, *id) {
+                    let mut buf = Vec::new();
+                    let _ = handle.read_to_end(&mut buf).await;
+                }
+            }
+        }
+    });
+});
@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use moiety::riven::deserialize_handlers;
+use smol::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let mut input = Cursor::new(data);
+    let _ = smol::block_on(deserialize_handlers(&mut input, false));
+});
@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use moiety::mhk::testing::parse_bytes;
+use moiety::riven::TSfxe;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_bytes(TSfxe, data);
+});
@@ -0,0 +1,294 @@
+use crate::filesystem::{Filesystem, FilesystemWrite};
+use crate::{
+    Bitmap, Cursor, Format, FormatWrite, PaletteBitmap, RawData, Record,
+};
+
+use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+use smol::io::{AsyncRead, AsyncReadExt};
+
+// compact pre-decoded resources, for caching a whole game on disk
+// this is not meant to be stable between versions, so rebuild caches
+// whenever the resource types change
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BincodeFormat;
+
+// written to the root of a cache, so old caches aren't read as new ones
+const VERSION_NAME: &str = "version";
+
+// every cached file also starts with this and the version, so one stray
+// file from an old cache fails loudly instead of decoding as garbage
+const MAGIC: &[u8; 4] = b"MOIB";
+
+impl BincodeFormat {
+    // bump this whenever the layout of anything below changes
+    pub const VERSION: u32 = 2;
+
+    pub async fn mark_cache<F>(fs: &mut F) -> Result<()>
+    where
+        F: FilesystemWrite,
+    {
+        fs.write(&[VERSION_NAME], Self::VERSION.to_string().as_bytes())
+            .await
+    }
+
+    // fails unless the cache was written with this version
+    pub async fn check_cache<F>(fs: &mut F) -> Result<()>
+    where
+        F: Filesystem,
+    {
+        let mut handle = fs.open(&[VERSION_NAME]).await
+            .map_err(|_| anyhow::anyhow!("cache has no version"))?;
+        let mut data = String::new();
+        handle.read_to_string(&mut data).await?;
+        match data.trim().parse::<u32>() {
+            Ok(v) if v == Self::VERSION => Ok(()),
+            _ => anyhow::bail!("cache is version {}, expected {}",
+                               data.trim(), Self::VERSION),
+        }
+    }
+}
+
+// colors are stored as raw bytes, and paletted bitmaps only keep
+// their palette and indices
+#[derive(Serialize, Deserialize)]
+struct BitmapData {
+    width: u16,
+    height: u16,
+    palette: Option<(Vec<u8>, Vec<u8>, Vec<u8>)>,
+    data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CursorData {
+    width: u16,
+    height: u16,
+    hotspot: (u16, u16),
+    data: Vec<u8>,
+}
+
+// from_raw_slice panics on partial pixels, so check the sizes first
+fn pixels<P>(raw: &[u8], count: Option<usize>) -> Result<Vec<P>>
+where
+    P: palette::Pixel<u8> + Clone,
+{
+    if !raw.len().is_multiple_of(P::CHANNELS) {
+        anyhow::bail!("cached colors are not whole pixels");
+    }
+    if count.is_some_and(|n| n * P::CHANNELS != raw.len()) {
+        anyhow::bail!("cached image is the wrong size");
+    }
+    Ok(P::from_raw_slice(raw).to_owned())
+}
+
+async fn decode<I, T>(input: &mut I) -> Result<T>
+where
+    I: AsyncRead + Unpin,
+    T: for<'a> serde::Deserialize<'a>,
+{
+    let mut contents = Vec::with_capacity(128);
+    input.read_to_end(&mut contents).await?;
+    if contents.get(..4) != Some(&MAGIC[..]) {
+        anyhow::bail!("not a cached resource");
+    }
+    let version = contents.get(4..8)
+        .map(|v| u32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+        .ok_or_else(|| anyhow::anyhow!("not a cached resource"))?;
+    if version != BincodeFormat::VERSION {
+        anyhow::bail!("cached resource is version {}, expected {}",
+                      version, BincodeFormat::VERSION);
+    }
+    Ok(bincode::deserialize(&contents[8..])?)
+}
+
+fn encode<T: serde::Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&BincodeFormat::VERSION.to_le_bytes());
+    bincode::serialize_into(&mut out, value)?;
+    Ok(out)
+}
+
+#[async_trait::async_trait(?Send)]
+impl<R, I, T> Format<R, I, Record<T>> for BincodeFormat
+where
+    I: AsyncRead + Unpin,
+    T: for<'a> serde::Deserialize<'a> + 'static,
+{
+    fn extension(&self, _res: &R) -> Option<&str> {
+        Some(".bin")
+    }
+    async fn parse(&self, _res: &R, input: &mut I) -> Result<Record<T>> {
+        Ok(Record(decode(input).await?))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, R, I, T> FormatWrite<Fi, R, I, Record<T>> for BincodeFormat
+where
+    Fi: Format<R, I, Record<T>>,
+    I: AsyncRead + Unpin,
+    T: serde::Serialize + for<'a> serde::Deserialize<'a> + 'static,
+{
+    async fn convert(&self, fmti: &Fi, res: &R, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        let data = fmti.parse(res, input).await?;
+        encode(&*data)
+    }
+    fn version(&self, _res: &R) -> u32 {
+        Self::VERSION
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<R, I> Format<R, I, Bitmap> for BincodeFormat
+where
+    I: AsyncRead + Unpin,
+{
+    fn extension(&self, _res: &R) -> Option<&str> {
+        Some(".bin")
+    }
+    async fn parse(&self, _res: &R, input: &mut I) -> Result<Bitmap> {
+        let bmp: BitmapData = decode(input).await?;
+        let count = bmp.width as usize * bmp.height as usize;
+        if let Some((palette, image, transparency)) = bmp.palette {
+            if image.len() != count {
+                anyhow::bail!("cached image is the wrong size");
+            }
+            let palette: Vec<palette::Srgb<u8>> = pixels(&palette, None)?;
+            let data = image.iter()
+                .map(|&i| {
+                    palette.get(i as usize)
+                        .cloned()
+                        .unwrap_or(palette::Srgb::new(0, 0, 0))
+                })
+                .collect();
+            Ok(Bitmap {
+                width: bmp.width,
+                height: bmp.height,
+                palette: Some(PaletteBitmap {
+                    palette,
+                    image,
+                    transparency,
+                }),
+                data,
+            })
+        } else {
+            Ok(Bitmap {
+                width: bmp.width,
+                height: bmp.height,
+                palette: None,
+                data: pixels(&bmp.data, Some(count))?,
+            })
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, R, I> FormatWrite<Fi, R, I, Bitmap> for BincodeFormat
+where
+    Fi: Format<R, I, Bitmap>,
+    I: AsyncRead + Unpin,
+{
+    async fn convert(&self, fmti: &Fi, res: &R, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        let bmp = fmti.parse(res, input).await?;
+        let out = match bmp.palette {
+            Some(pal) => BitmapData {
+                width: bmp.width,
+                height: bmp.height,
+                palette: Some((
+                    palette::Pixel::into_raw_slice(&pal.palette).to_owned(),
+                    pal.image,
+                    pal.transparency,
+                )),
+                data: vec![],
+            },
+            None => BitmapData {
+                width: bmp.width,
+                height: bmp.height,
+                palette: None,
+                data: palette::Pixel::into_raw_slice(&bmp.data).to_owned(),
+            },
+        };
+        encode(&out)
+    }
+    fn version(&self, _res: &R) -> u32 {
+        Self::VERSION
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<R, I> Format<R, I, Cursor> for BincodeFormat
+where
+    I: AsyncRead + Unpin,
+{
+    fn extension(&self, _res: &R) -> Option<&str> {
+        Some(".bin")
+    }
+    async fn parse(&self, _res: &R, input: &mut I) -> Result<Cursor> {
+        let cur: CursorData = decode(input).await?;
+        Ok(Cursor {
+            width: cur.width,
+            height: cur.height,
+            hotspot: cur.hotspot,
+            data: pixels(&cur.data,
+                         Some(cur.width as usize * cur.height as usize))?,
+        })
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, R, I> FormatWrite<Fi, R, I, Cursor> for BincodeFormat
+where
+    Fi: Format<R, I, Cursor>,
+    I: AsyncRead + Unpin,
+{
+    async fn convert(&self, fmti: &Fi, res: &R, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        let cur = fmti.parse(res, input).await?;
+        encode(&CursorData {
+            width: cur.width,
+            height: cur.height,
+            hotspot: cur.hotspot,
+            data: palette::Pixel::into_raw_slice(&cur.data).to_owned(),
+        })
+    }
+    fn version(&self, _res: &R) -> u32 {
+        Self::VERSION
+    }
+}
+
+// sounds, movies and unknown types are kept as they are
+#[async_trait::async_trait(?Send)]
+impl<R, I, D> Format<R, I, D> for BincodeFormat
+where
+    I: AsyncRead + Unpin,
+    D: RawData + 'static,
+{
+    fn extension(&self, _res: &R) -> Option<&str> {
+        Some(".bin")
+    }
+    async fn parse(&self, _res: &R, input: &mut I) -> Result<D> {
+        Ok(D::from_bytes(decode(input).await?))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, R, I, D> FormatWrite<Fi, R, I, D> for BincodeFormat
+where
+    Fi: Format<R, I, D>,
+    I: AsyncRead + Unpin,
+    D: RawData + 'static,
+{
+    async fn convert(&self, fmti: &Fi, res: &R, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        encode(&fmti.parse(res, input).await?.bytes())
+    }
+    fn version(&self, _res: &R) -> u32 {
+        Self::VERSION
+    }
+}
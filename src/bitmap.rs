@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+
 #[derive(Debug, Clone)]
 pub struct Bitmap {
     pub width: u16,
@@ -10,6 +13,9 @@ pub struct Bitmap {
 pub struct PaletteBitmap {
     pub palette: Vec<palette::Srgb<u8>>,
     pub image: Vec<u8>,
+    // alpha for the first few palette entries, like a PNG tRNS chunk
+    // empty means fully opaque
+    pub transparency: Vec<u8>,
 }
 
 #[derive(Debug, Clone)]
@@ -19,3 +25,160 @@ pub struct Cursor {
     pub hotspot: (u16, u16),
     pub data: Vec<palette::Srgba<u8>>,
 }
+
+impl Bitmap {
+    // fill in the colors for a paletted image, checking every index
+    pub fn from_palette(width: u16, height: u16, pal: PaletteBitmap)
+                        -> anyhow::Result<Self>
+    {
+        if pal.image.len() != width as usize * height as usize {
+            anyhow::bail!("image is not {}x{}", width, height);
+        }
+        if let Some(&i) = pal.image.iter()
+            .find(|&&i| i as usize >= pal.palette.len())
+        {
+            anyhow::bail!("palette index {} is out of range", i);
+        }
+        Ok(Bitmap {
+            width,
+            height,
+            data: pal.image.iter().map(|&i| pal.palette[i as usize]).collect(),
+            palette: Some(pal),
+        })
+    }
+
+    // reduce to at most max_colors (and never more than 256), keeping the
+    // existing palette if it already fits
+    pub fn to_palette(&self, max_colors: usize) -> PaletteBitmap {
+        let max_colors = max_colors.clamp(1, 256);
+        if let Some(pal) = &self.palette {
+            if pal.palette.len() <= max_colors {
+                return pal.clone();
+            }
+        }
+
+        let mut counts = HashMap::new();
+        for c in &self.data {
+            *counts.entry([c.red, c.green, c.blue]).or_insert(0) += 1;
+        }
+        let mut colors: Vec<([u8; 3], usize)> = counts.into_iter().collect();
+        // hash order is random, but the output shouldn't be
+        colors.sort();
+
+        let palette: Vec<[u8; 3]> = if colors.len() <= max_colors {
+            colors.iter().map(|(c, _)| *c).collect()
+        } else {
+            median_cut(colors, max_colors)
+        };
+
+        let mut lookup = HashMap::new();
+        let image = self.data.iter().map(|c| {
+            let c = [c.red, c.green, c.blue];
+            *lookup.entry(c).or_insert_with(|| nearest(&palette, c))
+        }).collect();
+
+        PaletteBitmap {
+            palette: palette.iter()
+                .map(|c| palette::Srgb::new(c[0], c[1], c[2]))
+                .collect(),
+            image,
+            transparency: vec![],
+        }
+    }
+}
+
+fn median_cut(colors: Vec<([u8; 3], usize)>, n: usize) -> Vec<[u8; 3]> {
+    let mut boxes = vec![colors];
+    while boxes.len() < n {
+        // split the box with the widest channel, at the weighted median
+        let widest = boxes.iter().enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| (widest_channel(b), i))
+            .max();
+        let ((_, chan), i) = match widest {
+            Some(w) => w,
+            None => break,
+        };
+        let mut b = boxes.swap_remove(i);
+        b.sort_by_key(|(c, _)| c[chan]);
+        let total: usize = b.iter().map(|(_, n)| n).sum();
+        let mut seen = 0;
+        let mut split = b.len() - 1;
+        for (j, (_, n)) in b.iter().enumerate() {
+            seen += n;
+            if seen * 2 >= total {
+                split = j + 1;
+                break;
+            }
+        }
+        let rest = b.split_off(split.clamp(1, b.len() - 1));
+        boxes.push(b);
+        boxes.push(rest);
+    }
+
+    boxes.iter().map(|b| {
+        let total: usize = b.iter().map(|(_, n)| n).sum();
+        let mut avg = [0; 3];
+        for (chan, v) in avg.iter_mut().enumerate() {
+            let sum: usize = b.iter().map(|(c, n)| c[chan] as usize * n).sum();
+            *v = ((sum + total / 2) / total) as u8;
+        }
+        avg
+    }).collect()
+}
+
+// returns (range, channel) of the channel with the largest range
+fn widest_channel(colors: &[([u8; 3], usize)]) -> (u8, usize) {
+    (0..3).map(|chan| {
+        let min = colors.iter().map(|(c, _)| c[chan]).min().unwrap_or(0);
+        let max = colors.iter().map(|(c, _)| c[chan]).max().unwrap_or(0);
+        (max - min, chan)
+    }).max().unwrap_or((0, 0))
+}
+
+fn nearest(palette: &[[u8; 3]], c: [u8; 3]) -> u8 {
+    let dist = |p: &[u8; 3]| -> i32 {
+        (0..3).map(|i| {
+            let d = p[i] as i32 - c[i] as i32;
+            d * d
+        }).sum()
+    };
+    palette.iter().enumerate()
+        .min_by_key(|(_, p)| dist(p))
+        .map(|(i, _)| i as u8)
+        .unwrap_or(0)
+}
+
+// image sizes from files are bigger than Bitmap can hold
+pub(crate) fn bitmap_size(width: u32, height: u32)
+                          -> anyhow::Result<(u16, u16)>
+{
+    match (width.try_into(), height.try_into()) {
+        (Ok(w), Ok(h)) => Ok((w, h)),
+        _ => anyhow::bail!("image is too large: {}x{}", width, height),
+    }
+}
+
+// indices packed most significant bits first, as in PNG and BMP rows
+pub(crate) fn unpack_row(row: &[u8], width: usize, bits: usize)
+                         -> impl Iterator<Item = u8> + '_
+{
+    (0..width).map(move |x| {
+        let bit = x * bits;
+        let byte = row[bit / 8];
+        (byte >> (8 - bits - bit % 8)) & ((1u16 << bits) - 1) as u8
+    })
+}
+
+// little-endian fields in file headers
+pub(crate) fn le_u16(data: &[u8], at: usize) -> anyhow::Result<u16> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow::anyhow!("unexpected end of image"))
+}
+
+pub(crate) fn le_u32(data: &[u8], at: usize) -> anyhow::Result<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow::anyhow!("unexpected end of image"))
+}
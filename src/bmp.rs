@@ -0,0 +1,168 @@
+use crate::bitmap::{bitmap_size, le_u16, le_u32, unpack_row};
+use crate::{Bitmap, Format, FormatWrite, PaletteBitmap};
+
+use anyhow::Result;
+use smol::io::{AsyncRead, AsyncReadExt};
+
+const VERSION: u32 = 1;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct BmpFormat;
+
+#[async_trait::async_trait(?Send)]
+impl<R, I> Format<R, I, Bitmap> for BmpFormat
+where
+    I: AsyncRead + Unpin,
+{
+    fn extension(&self, _res: &R) -> Option<&str> {
+        Some(".bmp")
+    }
+    async fn parse(&self, _res: &R, input: &mut I) -> Result<Bitmap> {
+        let mut buf = Vec::with_capacity(1 << 16);
+        input.read_to_end(&mut buf).await?;
+        if !buf.starts_with(b"BM") {
+            anyhow::bail!("not a bmp file");
+        }
+
+        let offset = le_u32(&buf, 10)? as usize;
+        let header_size = le_u32(&buf, 14)? as usize;
+        if header_size < 40 {
+            anyhow::bail!("unsupported bmp header size {}", header_size);
+        }
+        let width = le_u32(&buf, 18)? as i32;
+        let raw_height = le_u32(&buf, 22)? as i32;
+        let bpp = le_u16(&buf, 28)?;
+        let compression = le_u32(&buf, 30)?;
+        let colors_used = le_u32(&buf, 46)? as usize;
+        if compression != 0 {
+            anyhow::bail!("unsupported bmp compression {}", compression);
+        }
+        if width < 0 {
+            anyhow::bail!("bmp has negative width");
+        }
+        // negative heights mean rows are stored top-down
+        let top_down = raw_height < 0;
+        let height = raw_height.unsigned_abs();
+        let (width, height) = bitmap_size(width as u32, height)?;
+
+        let stride = (width as usize * bpp as usize).div_ceil(32) * 4;
+        let end = offset + stride * height as usize;
+        let pixels = buf.get(offset..end)
+            .ok_or_else(|| anyhow::anyhow!("unexpected end of image"))?;
+        let mut rows: Vec<&[u8]> = pixels.chunks(stride.max(1))
+            .take(height as usize)
+            .collect();
+        if !top_down {
+            rows.reverse();
+        }
+        let w = width as usize;
+
+        match bpp {
+            1 | 4 | 8 => {
+                let count = match colors_used {
+                    0 => 1 << bpp,
+                    n => n.min(256),
+                };
+                let start = 14 + header_size;
+                let raw = buf.get(start..start + count * 4).ok_or_else(
+                    || anyhow::anyhow!("unexpected end of image"),
+                )?;
+                let palette = raw.chunks(4)
+                    .map(|c| palette::Srgb::new(c[2], c[1], c[0]))
+                    .collect();
+
+                let bits = bpp as usize;
+                let mut image = Vec::with_capacity(w * height as usize);
+                for row in rows {
+                    image.extend(unpack_row(row, w, bits));
+                }
+                Bitmap::from_palette(width, height, PaletteBitmap {
+                    palette,
+                    image,
+                    transparency: vec![],
+                })
+            }
+            24 | 32 => {
+                let step = bpp as usize / 8;
+                let data = rows.iter()
+                    .flat_map(|row| row[..w * step].chunks(step))
+                    .map(|c| palette::Srgb::new(c[2], c[1], c[0]))
+                    .collect();
+                Ok(Bitmap {
+                    width,
+                    height,
+                    palette: None,
+                    data,
+                })
+            }
+            _ => anyhow::bail!("unsupported bmp depth {}", bpp),
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, R, I> FormatWrite<Fi, R, I, Bitmap> for BmpFormat
+where
+    Fi: Format<R, I, Bitmap>,
+    I: AsyncRead + Unpin,
+{
+    async fn convert(&self, fmti: &Fi, res: &R, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        let bmp = fmti.parse(res, input).await?;
+        let w = bmp.width as usize;
+        let h = bmp.height as usize;
+        let (bpp, colors) = match &bmp.palette {
+            Some(pal) => (8, pal.palette.len()),
+            None => (24, 0),
+        };
+        let stride = (w * bpp).div_ceil(32) * 4;
+        let offset = 14 + 40 + colors * 4;
+        let size = offset + stride * h;
+
+        let mut buf = Vec::with_capacity(size);
+        buf.extend(b"BM");
+        buf.extend(&(size as u32).to_le_bytes());
+        buf.extend(&0u32.to_le_bytes());
+        buf.extend(&(offset as u32).to_le_bytes());
+
+        buf.extend(&40u32.to_le_bytes());
+        buf.extend(&(w as u32).to_le_bytes());
+        buf.extend(&(h as u32).to_le_bytes());
+        buf.extend(&1u16.to_le_bytes());
+        buf.extend(&(bpp as u16).to_le_bytes());
+        buf.extend(&0u32.to_le_bytes());
+        buf.extend(&((stride * h) as u32).to_le_bytes());
+        // 72 dpi
+        buf.extend(&2835u32.to_le_bytes());
+        buf.extend(&2835u32.to_le_bytes());
+        buf.extend(&(colors as u32).to_le_bytes());
+        buf.extend(&0u32.to_le_bytes());
+
+        // rows go bottom-up, each padded to 4 bytes
+        let padding = stride - w * bpp / 8;
+        match &bmp.palette {
+            Some(pal) => {
+                for c in &pal.palette {
+                    buf.extend(&[c.blue, c.green, c.red, 0]);
+                }
+                for row in pal.image.chunks(w.max(1)).rev() {
+                    buf.extend(row);
+                    buf.extend(std::iter::repeat_n(0, padding));
+                }
+            }
+            None => {
+                for row in bmp.data.chunks(w.max(1)).rev() {
+                    for c in row {
+                        buf.extend(&[c.blue, c.green, c.red]);
+                    }
+                    buf.extend(std::iter::repeat_n(0, padding));
+                }
+            }
+        }
+        Ok(buf)
+    }
+    fn version(&self, _res: &R) -> u32 {
+        VERSION
+    }
+}
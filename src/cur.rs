@@ -1,10 +1,28 @@
+use crate::bitmap::le_u32;
 use crate::{Cursor, Format, FormatWrite};
 
 use anyhow::Result;
 use smol::io::{AsyncRead, AsyncReadExt};
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
-pub struct CurFormat;
+const VERSION: u32 = 1;
+
+// reads .cur files and .ani animated cursors, writes single-image .cur
+// files. with several images to choose from, the one closest to size is
+// used, or the biggest if there is no size
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub struct CurFormat {
+    size: Option<(u16, u16)>,
+}
+
+impl CurFormat {
+    pub fn with_size(self, size: Option<(u16, u16)>) -> Self {
+        CurFormat { size }
+    }
+
+    pub fn size(&self) -> Option<(u16, u16)> {
+        self.size
+    }
+}
 
 #[async_trait::async_trait(?Send)]
 impl<R, I> Format<R, I, Cursor> for CurFormat
@@ -17,27 +35,95 @@ where
     async fn parse(&self, _res: &R, input: &mut I) -> Result<Cursor> {
         let mut buf = Vec::with_capacity(1 << 10);
         input.read_to_end(&mut buf).await?;
-        let inp = std::io::Cursor::new(buf);
-
-        let icon_dir = ico::IconDir::read(inp)?;
-        let icons = icon_dir.entries();
-        if icons.len() == 0 {
-            anyhow::bail!("empty cur file");
+        if buf.starts_with(b"RIFF") {
+            // a Cursor has no animation, so take the first step
+            let frame = ani_first_frame(&buf)?;
+            self.parse_cur(frame)
+        } else {
+            self.parse_cur(&buf)
         }
-        let icon = icons[0].decode()?;
+    }
+}
+
+impl CurFormat {
+    fn parse_cur(&self, buf: &[u8]) -> Result<Cursor> {
+        let icon_dir = ico::IconDir::read(std::io::Cursor::new(buf))?;
+        let entry = self.best_entry(icon_dir.entries())
+            .ok_or_else(|| anyhow::anyhow!("empty cur file"))?;
+        let icon = entry.decode()?;
         if let Some(hotspot) = icon.cursor_hotspot() {
             let data = palette::Pixel::from_raw_slice(icon.rgba_data())
-                .iter().cloned().collect();
+                .to_owned();
             Ok(Cursor {
                 width: icon.width() as u16,
                 height: icon.height() as u16,
                 hotspot,
-                data: data,
+                data,
             })
         } else {
             anyhow::bail!("cursor does not have hotspot");
         }
     }
+
+    fn best_entry<'a>(&self, entries: &'a [ico::IconDirEntry])
+                      -> Option<&'a ico::IconDirEntry>
+    {
+        // cur entries don't record their depth, so more data breaks ties
+        entries.iter().max_by_key(|e| {
+            let (w, h) = (e.width() as i64, e.height() as i64);
+            let fit = match self.size {
+                Some((tw, th)) => {
+                    -(w - tw as i64).abs() - (h - th as i64).abs()
+                }
+                None => 0,
+            };
+            (fit, w * h, e.data().len())
+        })
+    }
+}
+
+// pull the icon for the first animation step out of a RIFF ACON file
+fn ani_first_frame(buf: &[u8]) -> Result<&[u8]> {
+    if buf.get(8..12) != Some(b"ACON") {
+        anyhow::bail!("not an ani file");
+    }
+    let mut flags = None;
+    let mut sequence = None;
+    let mut frames = Vec::new();
+    for (id, body) in riff_chunks(buf.get(12..).unwrap_or(&[]))? {
+        match id {
+            b"anih" => flags = Some(le_u32(body, 32)?),
+            b"seq " => sequence = Some(le_u32(body, 0)? as usize),
+            b"LIST" if body.starts_with(b"fram") => {
+                for (id, body) in riff_chunks(&body[4..])? {
+                    if id == b"icon" {
+                        frames.push(body);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    match flags {
+        Some(f) if f & 1 != 0 => {}
+        Some(_) => anyhow::bail!("ani frames without icons are unsupported"),
+        None => anyhow::bail!("ani file has no header"),
+    }
+    frames.get(sequence.unwrap_or(0)).copied()
+        .ok_or_else(|| anyhow::anyhow!("ani file has no frames"))
+}
+
+fn riff_chunks(mut buf: &[u8]) -> Result<Vec<(&[u8], &[u8])>> {
+    let mut chunks = Vec::new();
+    while buf.len() >= 8 {
+        let size = le_u32(buf, 4)? as usize;
+        let body = buf.get(8..8 + size)
+            .ok_or_else(|| anyhow::anyhow!("truncated ani chunk"))?;
+        chunks.push((&buf[..4], body));
+        // chunks are padded to an even size
+        buf = buf.get(8 + size + size % 2..).unwrap_or(&[]);
+    }
+    Ok(chunks)
 }
 
 #[async_trait::async_trait(?Send)]
@@ -64,4 +150,10 @@ where
         icon_dir.write(&mut out)?;
         Ok(out.into_inner())
     }
+    fn version(&self, _res: &R) -> u32 {
+        VERSION
+    }
+    fn identity(&self, _res: &R) -> String {
+        format!("{:?}", self)
+    }
 }
@@ -0,0 +1,138 @@
+use crate::AnyResource;
+
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    // what changed, one line each
+    Changed(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Difference<S> {
+    pub stack: S,
+    pub typ: String,
+    pub id: u16,
+    pub change: Change,
+}
+
+impl AnyResource {
+    // describe how other differs from this, empty if they're the same
+    pub fn diff(&self, other: &Self) -> Vec<String> {
+        let mut out = vec![];
+        match (self, other) {
+            (AnyResource::Record(a), AnyResource::Record(b)) => {
+                diff_json("", a, b, &mut out);
+            }
+            (AnyResource::Bitmap(a), AnyResource::Bitmap(b)) => {
+                let (sa, sb) = ((a.width, a.height), (b.width, b.height));
+                if !diff_size(sa, sb, &mut out) {
+                    diff_pixels(&a.data, &b.data, &mut out);
+                }
+                match (&a.palette, &b.palette) {
+                    (Some(pa), Some(pb)) => {
+                        let entries = pa.palette.iter().zip(&pb.palette)
+                            .filter(|(x, y)| x != y)
+                            .count()
+                            + pa.palette.len().abs_diff(pb.palette.len());
+                        if entries > 0 {
+                            out.push(format!("{} palette entries differ",
+                                             entries));
+                        }
+                    }
+                    (Some(_), None) => out.push("palette removed".to_owned()),
+                    (None, Some(_)) => out.push("palette added".to_owned()),
+                    (None, None) => {}
+                }
+            }
+            (AnyResource::Cursor(a), AnyResource::Cursor(b)) => {
+                let (sa, sb) = ((a.width, a.height), (b.width, b.height));
+                if !diff_size(sa, sb, &mut out) {
+                    diff_pixels(&a.data, &b.data, &mut out);
+                }
+                if a.hotspot != b.hotspot {
+                    out.push(format!("hotspot {:?} -> {:?}",
+                                     a.hotspot, b.hotspot));
+                }
+            }
+            (AnyResource::Sound(a), AnyResource::Sound(b)) => {
+                diff_bytes(&a.0, &b.0, &mut out);
+            }
+            (AnyResource::Movie(a), AnyResource::Movie(b)) => {
+                diff_bytes(&a.0, &b.0, &mut out);
+            }
+            (AnyResource::Raw(a), AnyResource::Raw(b)) => {
+                diff_bytes(&a.0, &b.0, &mut out);
+            }
+            _ => out.push("resource kind differs".to_owned()),
+        }
+        out
+    }
+}
+
+fn diff_size(a: (u16, u16), b: (u16, u16), out: &mut Vec<String>) -> bool {
+    if a != b {
+        out.push(format!("size {}x{} -> {}x{}", a.0, a.1, b.0, b.1));
+        true
+    } else {
+        false
+    }
+}
+
+fn diff_pixels<T: PartialEq>(a: &[T], b: &[T], out: &mut Vec<String>) {
+    let count = a.iter().zip(b).filter(|(x, y)| x != y).count();
+    if count > 0 {
+        out.push(format!("{} of {} pixels differ", count, a.len()));
+    }
+}
+
+// these can't be looked into, so only say how much changed
+fn diff_bytes(a: &[u8], b: &[u8], out: &mut Vec<String>) {
+    if a.len() != b.len() {
+        out.push(format!("{} bytes -> {} bytes", a.len(), b.len()));
+    } else {
+        let count = a.iter().zip(b).filter(|(x, y)| x != y).count();
+        if count > 0 {
+            out.push(format!("{} of {} bytes differ", count, a.len()));
+        }
+    }
+}
+
+// paths look like /cards/3/name, with the root being /
+pub(crate) fn diff_json(path: &str, a: &Value, b: &Value,
+                        out: &mut Vec<String>) {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            for (k, va) in a {
+                let sub = format!("{}/{}", path, k);
+                match b.get(k) {
+                    Some(vb) => diff_json(&sub, va, vb, out),
+                    None => out.push(format!("{}: removed {}", sub, va)),
+                }
+            }
+            for (k, vb) in b {
+                if !a.contains_key(k) {
+                    out.push(format!("{}/{}: added {}", path, k, vb));
+                }
+            }
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            for (i, (va, vb)) in a.iter().zip(b).enumerate() {
+                diff_json(&format!("{}/{}", path, i), va, vb, out);
+            }
+            for (i, va) in a.iter().enumerate().skip(b.len()) {
+                out.push(format!("{}/{}: removed {}", path, i, va));
+            }
+            for (i, vb) in b.iter().enumerate().skip(a.len()) {
+                out.push(format!("{}/{}: added {}", path, i, vb));
+            }
+        }
+        (a, b) if a != b => {
+            let shown = if path.is_empty() { "/" } else { path };
+            out.push(format!("{}: {} -> {}", shown, a, b));
+        }
+        _ => {}
+    }
+}
@@ -1,12 +1,31 @@
 use crate::filesystem::{Filesystem, FilesystemWrite};
-use crate::{ResourceMap, ResourceMapWrite, Stack};
+use crate::{
+    is_not_found, ResourceMap, ResourceMapList, ResourceMapWrite, Stack,
+};
+
+use std::collections::BTreeMap;
 
 use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+use smol::io::AsyncReadExt;
+
+// listing directories is slow, or not possible at all on some
+// filesystems, so keep track of what's been written,
+// stack -> type -> id -> extension
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DirectIndex {
+    stacks: BTreeMap<String, BTreeMap<String, BTreeMap<u16, String>>>,
+}
+
+const INDEX_NAME: &str = "index.json";
 
 #[derive(Debug)]
 pub struct DirectMap<F, Fmt, S> {
     filesystem: F,
     format: Fmt,
+    // loaded on first use
+    index: Option<DirectIndex>,
+    dirty: bool,
     stack: std::marker::PhantomData<S>,
 }
 
@@ -15,11 +34,74 @@ impl<F, Fmt, S> DirectMap<F, Fmt, S> {
         DirectMap {
             filesystem,
             format,
+            index: None,
+            dirty: false,
             stack: std::marker::PhantomData,
         }
     }
 }
 
+impl<F, Fmt, S> DirectMap<F, Fmt, S>
+where
+    F: Filesystem,
+    S: Stack,
+{
+    async fn index(&mut self) -> Result<&mut DirectIndex> {
+        if self.index.is_none() {
+            let index = match self.filesystem.open(&[INDEX_NAME]).await {
+                Ok(mut handle) => {
+                    let mut data = Vec::new();
+                    handle.read_to_end(&mut data).await?;
+                    serde_json::from_slice(&data)?
+                },
+                // written before there was an index, or not at all
+                Err(e) if is_not_found(&e) => self.scan().await?,
+                Err(e) => return Err(e),
+            };
+            self.index = Some(index);
+        }
+        Ok(self.index.as_mut().unwrap())
+    }
+
+    // rebuild the index from the files themselves, named like
+    // stack/type/00001.ext
+    async fn scan(&mut self) -> Result<DirectIndex> {
+        let mut index = DirectIndex::default();
+        for stack in S::all() {
+            let types = match self.filesystem.list(&[stack.name()]).await {
+                Ok(types) => types,
+                Err(e) if is_not_found(&e) => continue,
+                Err(e) => return Err(e.context(
+                    "no index.json, and the directory can't be listed")),
+            };
+            for typ in types {
+                let names = self.filesystem.list(&[stack.name(), &typ])
+                    .await?;
+                for name in names {
+                    let id = match name.get(..5).map(str::parse) {
+                        Some(Ok(id)) => id,
+                        _ => continue,
+                    };
+                    index.stacks.entry(stack.name().to_owned()).or_default()
+                        .entry(typ.clone()).or_default()
+                        .insert(id, name[5..].to_owned());
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    // the extension a resource was written with, if it is in the index
+    pub async fn extension(&mut self, stack: S, typ: &str, id: u16)
+                           -> Result<Option<String>>
+    {
+        Ok(self.index().await?.stacks.get(stack.name())
+           .and_then(|types| types.get(typ))
+           .and_then(|ids| ids.get(&id))
+           .cloned())
+    }
+}
+
 #[async_trait::async_trait(?Send)]
 impl<F, Fmt, S> ResourceMap for DirectMap<F, Fmt, S>
 where
@@ -46,6 +128,28 @@ where
     }
 }
 
+#[async_trait::async_trait(?Send)]
+impl<F, Fmt, S> ResourceMapList for DirectMap<F, Fmt, S>
+where
+    F: Filesystem,
+    S: Stack,
+{
+    async fn list(&mut self, stack: Self::Stack, typ: &str)
+                  -> Result<Vec<u16>>
+    {
+        Ok(self.index().await?.stacks.get(stack.name())
+           .and_then(|types| types.get(typ))
+           .map(|ids| ids.keys().cloned().collect())
+           .unwrap_or_default())
+    }
+
+    async fn types(&mut self, stack: Self::Stack) -> Result<Vec<String>> {
+        Ok(self.index().await?.stacks.get(stack.name())
+           .map(|types| types.keys().cloned().collect())
+           .unwrap_or_default())
+    }
+}
+
 #[async_trait::async_trait(?Send)]
 impl<F, Fmt, S> ResourceMapWrite for DirectMap<F, Fmt, S>
 where
@@ -61,6 +165,21 @@ where
         data: &[u8],
     ) -> Result<()> {
         let fname = [stack.name(), typ, &format!("{:05}{}", id, ext)];
-        self.filesystem.write(&fname, data).await
+        self.filesystem.write(&fname, data).await?;
+        self.index().await?.stacks
+            .entry(stack.name().to_owned()).or_default()
+            .entry(typ.to_owned()).or_default()
+            .insert(id, ext.to_owned());
+        self.dirty = true;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if let (Some(index), true) = (&self.index, self.dirty) {
+            let data = serde_json::to_vec_pretty(index)?;
+            self.filesystem.write(&[INDEX_NAME], &data).await?;
+            self.dirty = false;
+        }
+        Ok(())
     }
 }
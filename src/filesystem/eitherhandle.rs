@@ -14,6 +14,10 @@ impl<A, B> EitherHandle<A, B> {
     pub fn right(b: B) -> Self {
         EitherHandle(either::Right(b))
     }
+
+    pub fn as_mut(&mut self) -> either::Either<&mut A, &mut B> {
+        self.0.as_mut()
+    }
 }
 
 impl<A, B, E> EitherHandle<Result<A, E>, Result<B, E>> {
@@ -25,6 +29,19 @@ impl<A, B, E> EitherHandle<Result<A, E>, Result<B, E>> {
     }
 }
 
+impl<A, B> super::Resident for EitherHandle<A, B>
+where
+    A: super::Resident,
+    B: super::Resident,
+{
+    fn resident_size(&self) -> u64 {
+        match &self.0 {
+            either::Left(a) => a.resident_size(),
+            either::Right(b) => b.resident_size(),
+        }
+    }
+}
+
 impl<A, B> AsyncRead for EitherHandle<A, B>
 where
     A: AsyncRead + Unpin,
@@ -25,6 +25,20 @@ impl super::Filesystem for LocalFilesystem {
         let file = std::fs::File::open(subpath)?;
         Ok(smol::Unblock::new(file))
     }
+
+    async fn list(&mut self, path: &[&str]) -> Result<Vec<String>> {
+        let mut subpath = self.root.clone();
+        for part in path {
+            subpath.push(part);
+        }
+        let mut names = vec![];
+        for entry in std::fs::read_dir(subpath)? {
+            if let Ok(name) = entry?.file_name().into_string() {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
 }
 
 #[async_trait::async_trait(?Send)]
@@ -38,6 +52,10 @@ impl super::FilesystemWrite for LocalFilesystem {
             std::fs::create_dir_all(parent)?;
         }
         let file = std::fs::File::create(subpath)?;
-        Ok(smol::Unblock::new(file).write_all(data).await?)
+        // Unblock finishes writing in the background unless flushed, and
+        // the file may be read again right away
+        let mut file = smol::Unblock::new(file);
+        file.write_all(data).await?;
+        Ok(file.flush().await?)
     }
 }
@@ -31,6 +31,12 @@ where
         println!("opening {}", nicepath);
         self.inner.open(path).await
     }
+
+    async fn list(&mut self, path: &[&str]) -> Result<Vec<String>> {
+        let nicepath = format!("[{}]/{}", self.name, path.join("/"));
+        println!("listing {}", nicepath);
+        self.inner.list(path).await
+    }
 }
 
 #[async_trait::async_trait(?Send)]
@@ -19,10 +19,32 @@ pub use product::*;
 mod sum;
 pub use sum::*;
 
+// how much of a file a handle keeps in memory, for memory budgets
+pub trait Resident {
+    fn resident_size(&self) -> u64;
+}
+
+// read from disk as needed
+impl Resident for smol::Unblock<std::fs::File> {
+    fn resident_size(&self) -> u64 {
+        0
+    }
+}
+
+impl Resident for smol::io::Cursor<Vec<u8>> {
+    fn resident_size(&self) -> u64 {
+        self.get_ref().len() as u64
+    }
+}
+
 #[async_trait::async_trait(?Send)]
 pub trait Filesystem {
-    type Handle: AsyncRead + AsyncSeek + Unpin;
+    type Handle: AsyncRead + AsyncSeek + Resident + Unpin;
     async fn open(&mut self, path: &[&str]) -> Result<Self::Handle>;
+    // names of everything in a directory, where the filesystem can tell
+    async fn list(&mut self, _path: &[&str]) -> Result<Vec<String>> {
+        anyhow::bail!("this filesystem can't list directories")
+    }
 }
 
 #[async_trait::async_trait(?Send)]
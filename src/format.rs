@@ -13,6 +13,22 @@ where
 {
     async fn convert(&self, fmti: &Fi, res: &R, input: &mut I)
                      -> Result<Vec<u8>>;
+    // bump this when the output changes, so old exports are redone
+    fn version(&self, res: &R) -> u32;
+    // tells apart writers whose options change the output, like
+    // pretty-printing, so their exports aren't mixed up
+    fn identity(&self, _res: &R) -> String {
+        std::any::type_name::<Self>().to_owned()
+    }
+}
+
+// which writer made an export, and which version of it
+pub(crate) fn writer_id<Fi, R, I, D, F>(fmt: &F, res: &R) -> String
+where
+    Fi: Format<R, I, D>,
+    F: FormatWrite<Fi, R, I, D>,
+{
+    format!("{} v{}", fmt.identity(res), fmt.version(res))
 }
 
 // wrapper type for generic plain old data records
@@ -32,16 +48,69 @@ impl<T> std::ops::DerefMut for Record<T> {
     }
 }
 
-// mixed-format
+// mixed-format, picking a sub-format by the kind of data. kinds left as
+// () have no format, and resources of that kind can't be used
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub struct MixedFormat<B, C, R> {
+pub struct MixedFormat<B, C, R, S = (), M = (), X = ()> {
     pub bitmap: B,
     pub cursor: C,
     pub record: R,
+    pub sound: S,
+    pub movie: M,
+    pub raw: X,
+}
+
+impl<B, C, R> MixedFormat<B, C, R> {
+    pub fn new(bitmap: B, cursor: C, record: R) -> Self {
+        MixedFormat {
+            bitmap,
+            cursor,
+            record,
+            sound: (),
+            movie: (),
+            raw: (),
+        }
+    }
+}
+
+impl<B, C, R, S, M, X> MixedFormat<B, C, R, S, M, X> {
+    pub fn with_sound<S2>(self, sound: S2) -> MixedFormat<B, C, R, S2, M, X> {
+        MixedFormat {
+            bitmap: self.bitmap,
+            cursor: self.cursor,
+            record: self.record,
+            sound,
+            movie: self.movie,
+            raw: self.raw,
+        }
+    }
+
+    pub fn with_movie<M2>(self, movie: M2) -> MixedFormat<B, C, R, S, M2, X> {
+        MixedFormat {
+            bitmap: self.bitmap,
+            cursor: self.cursor,
+            record: self.record,
+            sound: self.sound,
+            movie,
+            raw: self.raw,
+        }
+    }
+
+    pub fn with_raw<X2>(self, raw: X2) -> MixedFormat<B, C, R, S, M, X2> {
+        MixedFormat {
+            bitmap: self.bitmap,
+            cursor: self.cursor,
+            record: self.record,
+            sound: self.sound,
+            movie: self.movie,
+            raw,
+        }
+    }
 }
 
 #[async_trait::async_trait(?Send)]
-impl<Res, I, B, C, R> Format<Res, I, crate::Bitmap> for MixedFormat<B, C, R>
+impl<Res, I, B, C, R, S, M, X> Format<Res, I, crate::Bitmap>
+    for MixedFormat<B, C, R, S, M, X>
 where
     B: Format<Res, I, crate::Bitmap>,
 {
@@ -54,8 +123,8 @@ where
 }
 
 #[async_trait::async_trait(?Send)]
-impl<Fi, Res, I, B, C, R> FormatWrite<Fi, Res, I, crate::Bitmap>
-    for MixedFormat<B, C, R>
+impl<Fi, Res, I, B, C, R, S, M, X> FormatWrite<Fi, Res, I, crate::Bitmap>
+    for MixedFormat<B, C, R, S, M, X>
 where
     Fi: Format<Res, I, crate::Bitmap>,
     B: FormatWrite<Fi, Res, I, crate::Bitmap>,
@@ -65,10 +134,17 @@ where
     {
         self.bitmap.convert(fmti, res, input).await
     }
+    fn version(&self, res: &Res) -> u32 {
+        self.bitmap.version(res)
+    }
+    fn identity(&self, res: &Res) -> String {
+        self.bitmap.identity(res)
+    }
 }
 
 #[async_trait::async_trait(?Send)]
-impl<Res, I, B, C, R> Format<Res, I, crate::Cursor> for MixedFormat<B, C, R>
+impl<Res, I, B, C, R, S, M, X> Format<Res, I, crate::Cursor>
+    for MixedFormat<B, C, R, S, M, X>
 where
     C: Format<Res, I, crate::Cursor>,
 {
@@ -81,8 +157,8 @@ where
 }
 
 #[async_trait::async_trait(?Send)]
-impl<Fi, Res, I, B, C, R> FormatWrite<Fi, Res, I, crate::Cursor>
-    for MixedFormat<B, C, R>
+impl<Fi, Res, I, B, C, R, S, M, X> FormatWrite<Fi, Res, I, crate::Cursor>
+    for MixedFormat<B, C, R, S, M, X>
 where
     Fi: Format<Res, I, crate::Cursor>,
     C: FormatWrite<Fi, Res, I, crate::Cursor>,
@@ -92,10 +168,17 @@ where
     {
         self.cursor.convert(fmti, res, input).await
     }
+    fn version(&self, res: &Res) -> u32 {
+        self.cursor.version(res)
+    }
+    fn identity(&self, res: &Res) -> String {
+        self.cursor.identity(res)
+    }
 }
 
 #[async_trait::async_trait(?Send)]
-impl<Res, I, B, C, R, T> Format<Res, I, Record<T>> for MixedFormat<B, C, R>
+impl<Res, I, B, C, R, S, M, X, T> Format<Res, I, Record<T>>
+    for MixedFormat<B, C, R, S, M, X>
 where
     R: Format<Res, I, Record<T>>,
     T: 'static,
@@ -109,8 +192,8 @@ where
 }
 
 #[async_trait::async_trait(?Send)]
-impl<Fi, Res, I, B, C, R, T> FormatWrite<Fi, Res, I, Record<T>>
-    for MixedFormat<B, C, R>
+impl<Fi, Res, I, B, C, R, S, M, X, T> FormatWrite<Fi, Res, I, Record<T>>
+    for MixedFormat<B, C, R, S, M, X>
 where
     Fi: Format<Res, I, Record<T>>,
     R: FormatWrite<Fi, Res, I, Record<T>>,
@@ -121,4 +204,112 @@ where
     {
         self.record.convert(fmti, res, input).await
     }
+    fn version(&self, res: &Res) -> u32 {
+        self.record.version(res)
+    }
+    fn identity(&self, res: &Res) -> String {
+        self.record.identity(res)
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Res, I, B, C, R, S, M, X> Format<Res, I, crate::Sound>
+    for MixedFormat<B, C, R, S, M, X>
+where
+    S: Format<Res, I, crate::Sound>,
+{
+    fn extension(&self, res: &Res) -> Option<&str> {
+        self.sound.extension(res)
+    }
+    async fn parse(&self, res: &Res, input: &mut I) -> Result<crate::Sound> {
+        self.sound.parse(res, input).await
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, Res, I, B, C, R, S, M, X> FormatWrite<Fi, Res, I, crate::Sound>
+    for MixedFormat<B, C, R, S, M, X>
+where
+    Fi: Format<Res, I, crate::Sound>,
+    S: FormatWrite<Fi, Res, I, crate::Sound>,
+{
+    async fn convert(&self, fmti: &Fi, res: &Res, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        self.sound.convert(fmti, res, input).await
+    }
+    fn version(&self, res: &Res) -> u32 {
+        self.sound.version(res)
+    }
+    fn identity(&self, res: &Res) -> String {
+        self.sound.identity(res)
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Res, I, B, C, R, S, M, X> Format<Res, I, crate::Movie>
+    for MixedFormat<B, C, R, S, M, X>
+where
+    M: Format<Res, I, crate::Movie>,
+{
+    fn extension(&self, res: &Res) -> Option<&str> {
+        self.movie.extension(res)
+    }
+    async fn parse(&self, res: &Res, input: &mut I) -> Result<crate::Movie> {
+        self.movie.parse(res, input).await
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, Res, I, B, C, R, S, M, X> FormatWrite<Fi, Res, I, crate::Movie>
+    for MixedFormat<B, C, R, S, M, X>
+where
+    Fi: Format<Res, I, crate::Movie>,
+    M: FormatWrite<Fi, Res, I, crate::Movie>,
+{
+    async fn convert(&self, fmti: &Fi, res: &Res, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        self.movie.convert(fmti, res, input).await
+    }
+    fn version(&self, res: &Res) -> u32 {
+        self.movie.version(res)
+    }
+    fn identity(&self, res: &Res) -> String {
+        self.movie.identity(res)
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Res, I, B, C, R, S, M, X> Format<Res, I, crate::Raw>
+    for MixedFormat<B, C, R, S, M, X>
+where
+    X: Format<Res, I, crate::Raw>,
+{
+    fn extension(&self, res: &Res) -> Option<&str> {
+        self.raw.extension(res)
+    }
+    async fn parse(&self, res: &Res, input: &mut I) -> Result<crate::Raw> {
+        self.raw.parse(res, input).await
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, Res, I, B, C, R, S, M, X> FormatWrite<Fi, Res, I, crate::Raw>
+    for MixedFormat<B, C, R, S, M, X>
+where
+    Fi: Format<Res, I, crate::Raw>,
+    X: FormatWrite<Fi, Res, I, crate::Raw>,
+{
+    async fn convert(&self, fmti: &Fi, res: &Res, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        self.raw.convert(fmti, res, input).await
+    }
+    fn version(&self, res: &Res) -> u32 {
+        self.raw.version(res)
+    }
+    fn identity(&self, res: &Res) -> String {
+        self.raw.identity(res)
+    }
 }
@@ -3,6 +3,8 @@ use crate::{Format, FormatWrite, Record};
 use anyhow::Result;
 use smol::io::{AsyncRead, AsyncReadExt};
 
+const VERSION: u32 = 1;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct JsonFormat(pub bool);
 
@@ -39,4 +41,10 @@ where
             Ok(serde_json::to_vec(&*data)?)
         }
     }
+    fn version(&self, _res: &R) -> u32 {
+        VERSION
+    }
+    fn identity(&self, _res: &R) -> String {
+        format!("{:?}", self)
+    }
 }
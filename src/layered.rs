@@ -0,0 +1,156 @@
+use crate::filesystem::EitherHandle;
+use crate::{
+    is_not_found, Format, ResourceMap, ResourceMapList, ResourceMapWrite,
+};
+
+use anyhow::Result;
+
+// reads from `over` when it has the resource, and `base` otherwise,
+// for running off the original archives with some resources replaced
+// writes all go to `over`
+pub struct LayeredMap<A: ResourceMap, B: ResourceMap> {
+    over: A,
+    base: B,
+    format: LayeredFormat<A::Format, B::Format>,
+}
+
+// parses each handle with the format of the map it came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LayeredFormat<A, B> {
+    pub over: A,
+    pub base: B,
+}
+
+impl<A, B> LayeredMap<A, B>
+where
+    A: ResourceMap,
+    B: ResourceMap<Stack = A::Stack>,
+    A::Format: Clone,
+    B::Format: Clone,
+{
+    // the formats are copied here, so configure them on the maps first
+    pub fn new(over: A, base: B) -> Self {
+        let format = LayeredFormat {
+            over: over.format().clone(),
+            base: base.format().clone(),
+        };
+        LayeredMap { over, base, format }
+    }
+
+    pub fn over(&self) -> &A {
+        &self.over
+    }
+
+    pub fn base(&self) -> &B {
+        &self.base
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<A, B> ResourceMap for LayeredMap<A, B>
+where
+    A: ResourceMap,
+    B: ResourceMap<Stack = A::Stack>,
+    A::Stack: Clone,
+{
+    type Handle = EitherHandle<A::Handle, B::Handle>;
+    type Stack = A::Stack;
+    type Format = LayeredFormat<A::Format, B::Format>;
+
+    fn format(&self) -> &Self::Format {
+        &self.format
+    }
+
+    async fn preload(&mut self, stack: Self::Stack) -> Result<()> {
+        self.over.preload(stack.clone()).await?;
+        self.base.preload(stack).await
+    }
+
+    // the extension comes from the override format, archive maps like
+    // MhkMap ignore it anyway
+    async fn open_raw(
+        &mut self,
+        stack: Self::Stack,
+        typ: &str,
+        id: u16,
+        ext: &str,
+    ) -> Result<Self::Handle> {
+        // anything else wrong with the override should be seen, not
+        // quietly replaced with the original
+        match self.over.open_raw(stack.clone(), typ, id, ext).await {
+            Ok(h) => Ok(EitherHandle::left(h)),
+            Err(e) if is_not_found(&e) => Ok(EitherHandle::right(
+                self.base.open_raw(stack, typ, id, ext).await?)),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<A, B> ResourceMapList for LayeredMap<A, B>
+where
+    A: ResourceMapList,
+    B: ResourceMapList<Stack = A::Stack>,
+    A::Stack: Clone,
+{
+    async fn list(&mut self, stack: Self::Stack, typ: &str)
+                  -> Result<Vec<u16>>
+    {
+        let mut ret = self.over.list(stack.clone(), typ).await?;
+        ret.extend(self.base.list(stack, typ).await?);
+        ret.sort();
+        ret.dedup();
+        Ok(ret)
+    }
+
+    async fn types(&mut self, stack: Self::Stack) -> Result<Vec<String>> {
+        let mut ret = self.over.types(stack.clone()).await?;
+        ret.extend(self.base.types(stack).await?);
+        ret.sort();
+        ret.dedup();
+        Ok(ret)
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<A, B> ResourceMapWrite for LayeredMap<A, B>
+where
+    A: ResourceMapWrite,
+    B: ResourceMap<Stack = A::Stack>,
+    A::Stack: Clone,
+{
+    async fn write_raw(
+        &mut self,
+        stack: Self::Stack,
+        typ: &str,
+        id: u16,
+        ext: &str,
+        data: &[u8],
+    ) -> Result<()> {
+        self.over.write_raw(stack, typ, id, ext, data).await
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.over.flush().await
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<R, HA, HB, D, A, B> Format<R, EitherHandle<HA, HB>, D>
+    for LayeredFormat<A, B>
+where
+    A: Format<R, HA, D>,
+    B: Format<R, HB, D>,
+{
+    fn extension(&self, res: &R) -> Option<&str> {
+        self.over.extension(res)
+    }
+    async fn parse(&self, res: &R, input: &mut EitherHandle<HA, HB>)
+                   -> Result<D>
+    {
+        match input.as_mut() {
+            either::Left(h) => self.over.parse(res, h).await,
+            either::Right(h) => self.base.parse(res, h).await,
+        }
+    }
+}
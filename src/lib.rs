@@ -15,23 +15,54 @@ pub use map::*;
 mod format;
 pub use format::*;
 
+mod registry;
+pub use registry::*;
+
+mod manifest;
+pub use manifest::*;
+
+mod verify;
+pub use verify::*;
+
+mod diff;
+pub use diff::*;
+
 mod direct;
 pub use direct::*;
 
+mod layered;
+pub use layered::*;
+
 mod json;
 pub use json::*;
 
+mod media;
+pub use media::*;
+
 mod png;
 pub use crate::png::*;
 
+mod bmp;
+pub use bmp::*;
+
+mod ppm;
+pub use ppm::*;
+
+mod tga;
+pub use tga::*;
+
 mod cur;
 pub use crate::cur::*;
 
+mod bincode;
+pub use crate::bincode::*;
+
 pub mod mhk;
 pub use mhk::{
     MhkError,
     MhkFormat,
     MhkMap,
+    MhkWriter,
 };
 
 mod bitmap;
@@ -1,4 +1,8 @@
 use moiety::filesystem::LocalFilesystem;
+use moiety::{
+    BincodeFormat, CurFormat, DirectMap, JsonFormat, LayeredMap, MixedFormat,
+    PngFormat, RawFormat, ResourceMap,
+};
 use moiety::riven;
 use moiety::sdl;
 
@@ -6,12 +10,64 @@ use anyhow::Result;
 
 fn main() -> Result<()> {
     smol::run(async {
-        let fs = LocalFilesystem::new("/Users/agrif/vault/games/riven/");
-        let map = riven::map_5cd(fs).await?;
-        let game = riven::Riven::new(map).await?;
+        // edited resources, in the same layout vahttool exports. these go
+        // over the cache or the game files, whichever is used
+        let mods = std::path::Path::new("./local/riven-mods/");
+        let mods = if mods.is_dir() {
+            eprintln!("using mods in {}", mods.display());
+            let fmt = MixedFormat::new(PngFormat, CurFormat::default(),
+                                       JsonFormat(false))
+                .with_sound(RawFormat(".wav"))
+                .with_movie(RawFormat(".mov"))
+                .with_raw(RawFormat(".bin"));
+            Some(DirectMap::new(LocalFilesystem::new(mods), fmt))
+        } else {
+            None
+        };
 
-        sdl::Sdl::run(game).await?;
+        // use the pre-decoded cache from vahttool, if there is one. it
+        // skips the memory budget and preloading, it's all on disk anyway
+        let cache = std::path::Path::new("./local/riven-cache/");
+        if cache.is_dir() {
+            let mut fs = LocalFilesystem::new(cache);
+            match BincodeFormat::check_cache(&mut fs).await {
+                Ok(()) => {
+                    eprintln!("using cache in {}", cache.display());
+                    let map = DirectMap::new(fs, BincodeFormat);
+                    return match mods {
+                        Some(over) => {
+                            play(LayeredMap::new(over, map), false).await
+                        }
+                        None => play(map, false).await,
+                    };
+                }
+                Err(e) => eprintln!("not using cache in {}: {}, \
+                                     rebuild it with vahttool cache",
+                                    cache.display(), e),
+            }
+        }
 
-        Ok(())
+        let game_dir = "/Users/agrif/vault/games/riven/";
+        eprintln!("using game files in {}", game_dir);
+        let fs = LocalFilesystem::new(game_dir);
+        let mut map = riven::map_auto(fs).await?;
+        map.set_memory_budget(Some(256 << 20));
+        map.set_lenient_scripts(true);
+
+        match mods {
+            Some(over) => play(LayeredMap::new(over, map), true).await,
+            None => play(map, true).await,
+        }
     })
 }
+
+async fn play<M>(map: M, preload: bool) -> Result<()>
+where
+    M: ResourceMap<Stack = riven::Stack>,
+    M::Format: riven::RivenFormat<M::Handle>,
+{
+    let mut game = riven::Riven::new(map).await?;
+    game.set_preload(preload);
+    sdl::Sdl::run(game).await?;
+    Ok(())
+}
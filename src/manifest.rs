@@ -0,0 +1,82 @@
+use crate::filesystem::{Filesystem, FilesystemWrite};
+use crate::{is_not_found, Stack};
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+use smol::io::AsyncReadExt;
+
+const MANIFEST_NAME: &str = "manifest.json";
+
+// what each exported resource was made from, so re-exports can skip
+// anything whose source and converter haven't changed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    entries: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(data)?)
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(self)?)
+    }
+
+    // the manifest kept alongside an export, or an empty one if there
+    // isn't one yet
+    pub async fn load<F>(fs: &mut F) -> Result<Self>
+    where
+        F: Filesystem,
+    {
+        match fs.open(&[MANIFEST_NAME]).await {
+            Ok(mut handle) => {
+                let mut data = Vec::new();
+                handle.read_to_end(&mut data).await?;
+                Self::from_json(&data)
+            },
+            Err(e) if is_not_found(&e) => Ok(Self::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn save<F>(&self, fs: &mut F) -> Result<()>
+    where
+        F: FilesystemWrite,
+    {
+        fs.write(&[MANIFEST_NAME], &self.to_json()?).await
+    }
+
+    fn key<S: Stack>(stack: &S, typ: &str, id: u16) -> String {
+        format!("{}/{}/{}", stack.name(), typ, id)
+    }
+
+    pub fn get<S: Stack>(&self, stack: &S, typ: &str, id: u16)
+                         -> Option<&str>
+    {
+        self.entries.get(&Self::key(stack, typ, id)).map(|s| s.as_str())
+    }
+
+    pub fn insert<S: Stack>(&mut self, stack: &S, typ: &str, id: u16,
+                            fingerprint: String) {
+        self.entries.insert(Self::key(stack, typ, id), fingerprint);
+    }
+
+    pub fn remove<S: Stack>(&mut self, stack: &S, typ: &str, id: u16) {
+        self.entries.remove(&Self::key(stack, typ, id));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
@@ -1,4 +1,4 @@
-use crate::Stack;
+use crate::{MhkError, Stack};
 
 use anyhow::Result;
 use smol::io::{AsyncRead, AsyncSeek};
@@ -9,6 +9,9 @@ pub trait ResourceMap {
     type Stack: Stack;
     type Format;
     fn format(&self) -> &Self::Format;
+    async fn preload(&mut self, _stack: Self::Stack) -> Result<()> {
+        Ok(())
+    }
     async fn open_raw(
         &mut self,
         stack: Self::Stack,
@@ -16,11 +19,26 @@ pub trait ResourceMap {
         id: u16,
         ext: &str,
     ) -> Result<Self::Handle>;
+    // something that changes whenever the raw resource does, if the map
+    // can tell cheaply, for skipping unchanged resources in exports
+    async fn fingerprint(
+        &mut self,
+        _stack: Self::Stack,
+        _typ: &str,
+        _id: u16,
+    ) -> Result<Option<String>> {
+        Ok(None)
+    }
 }
 
 #[async_trait::async_trait(?Send)]
 pub trait ResourceMapList: ResourceMap {
     async fn list(&mut self, stack: Self::Stack, typ: &str) -> Result<Vec<u16>>;
+    // every type in a stack, if the map can tell, so exports can find
+    // types nothing knows how to decode
+    async fn types(&mut self, _stack: Self::Stack) -> Result<Vec<String>> {
+        Ok(vec![])
+    }
 }
 
 #[async_trait::async_trait(?Send)]
@@ -33,4 +51,28 @@ pub trait ResourceMapWrite: ResourceMap {
         ext: &str,
         data: &[u8],
     ) -> Result<()>;
+    // for maps that hold on to writes, or to an index of them
+    async fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// a stable hash of a resource's bytes, for fingerprints. this is 64-bit
+// fnv-1a, which is plenty to notice a resource has changed
+pub fn content_hash(data: &[u8]) -> String {
+    let hash = data.iter().fold(0xcbf2_9ce4_8422_2325u64, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+// whether an error from a map means the resource simply isn't there, as
+// opposed to being there and unreadable
+pub fn is_not_found(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| {
+        matches!(e.downcast_ref::<MhkError>(),
+                 Some(MhkError::ResourceNotFound(..)))
+            || e.downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+    })
 }
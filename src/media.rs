@@ -0,0 +1,154 @@
+use crate::mhk::MhkFormat;
+use crate::{Format, FormatWrite, ResourceType};
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use smol::io::{AsyncRead, AsyncReadExt};
+
+const VERSION: u32 = 1;
+
+// sounds, movies and anything else we don't decode yet, kept in whatever
+// encoding the archive uses
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sound(pub Vec<u8>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie(pub Vec<u8>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Raw(pub Vec<u8>);
+
+pub trait RawData {
+    fn from_bytes(data: Vec<u8>) -> Self;
+    fn bytes(&self) -> &[u8];
+}
+
+impl RawData for Sound {
+    fn from_bytes(data: Vec<u8>) -> Self {
+        Sound(data)
+    }
+    fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl RawData for Movie {
+    fn from_bytes(data: Vec<u8>) -> Self {
+        Movie(data)
+    }
+    fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl RawData for Raw {
+    fn from_bytes(data: Vec<u8>) -> Self {
+        Raw(data)
+    }
+    fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+// a resource type known only by its tag, for passing through types
+// nothing understands
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RawResource(pub &'static str);
+
+impl RawResource {
+    // for tags only found at runtime. there are only a handful of types
+    // in a game, so each name is kept for good the first time it's seen
+    pub fn new(name: &str) -> Self {
+        static NAMES: Mutex<BTreeSet<&'static str>> =
+            Mutex::new(BTreeSet::new());
+        let mut names = NAMES.lock().unwrap();
+        if let Some(known) = names.get(name) {
+            return RawResource(known);
+        }
+        let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+        names.insert(name);
+        RawResource(name)
+    }
+}
+
+impl ResourceType for RawResource {
+    type Data = Raw;
+    fn name(&self) -> &str {
+        self.0
+    }
+}
+
+// copies the bytes through untouched, under the given extension
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RawFormat(pub &'static str);
+
+async fn read_raw<I, D>(input: &mut I) -> Result<D>
+where
+    I: AsyncRead + Unpin,
+    D: RawData,
+{
+    let mut data = Vec::with_capacity(1 << 16);
+    input.read_to_end(&mut data).await?;
+    Ok(D::from_bytes(data))
+}
+
+#[async_trait::async_trait(?Send)]
+impl<R, I, D> Format<R, I, D> for RawFormat
+where
+    I: AsyncRead + Unpin,
+    D: RawData + 'static,
+{
+    fn extension(&self, _res: &R) -> Option<&str> {
+        Some(self.0)
+    }
+    async fn parse(&self, _res: &R, input: &mut I) -> Result<D> {
+        read_raw(input).await
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, R, I, D> FormatWrite<Fi, R, I, D> for RawFormat
+where
+    Fi: Format<R, I, D>,
+    I: AsyncRead + Unpin,
+    D: RawData + 'static,
+{
+    async fn convert(&self, fmti: &Fi, res: &R, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        Ok(fmti.parse(res, input).await?.bytes().to_owned())
+    }
+    fn version(&self, _res: &R) -> u32 {
+        VERSION
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<R, I, D> Format<R, I, D> for MhkFormat
+where
+    I: AsyncRead + Unpin,
+    D: RawData + 'static,
+{
+    async fn parse(&self, _res: &R, input: &mut I) -> Result<D> {
+        read_raw(input).await
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, R, I, D> FormatWrite<Fi, R, I, D> for MhkFormat
+where
+    Fi: Format<R, I, D>,
+    I: AsyncRead + Unpin,
+    D: RawData + 'static,
+{
+    async fn convert(&self, fmti: &Fi, res: &R, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        Ok(fmti.parse(res, input).await?.bytes().to_owned())
+    }
+    fn version(&self, _res: &R) -> u32 {
+        VERSION
+    }
+}
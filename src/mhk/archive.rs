@@ -1,5 +1,5 @@
 use super::chunks::{
-    MHWK, RSRC, TypeTableEntry, ResourceTableEntry,
+    MHWK, RSRC, TypeTableEntry, NameTableEntry, ResourceTableEntry,
     FileTableEntry,
 };
 use super::utility::{
@@ -12,27 +12,76 @@ use std::collections::HashMap;
 
 use anyhow::Result;
 use smol::io::{
-    AsyncRead, AsyncSeek, AsyncSeekExt, SeekFrom, BufReader,
+    AsyncRead, AsyncBufReadExt, AsyncSeek, AsyncSeekExt, SeekFrom, BufReader,
 };
 
 #[derive(Debug)]
 pub struct MhkArchive<R: AsyncRead> {
     pub handle: std::rc::Rc<std::cell::RefCell<(u64, BufReader<R>)>>,
+    pub mhwk: MHWK,
+    pub rsrc: RSRC,
+    pub type_table: Vec<TypeTableEntry>,
     pub files: Vec<FileInfo>,
     pub resources: HashMap<String, HashMap<u16, ResourceInfo>>,
+    // only ever non-empty from new_lenient
+    pub problems: Vec<ArchiveProblem>,
+}
+
+// damage that new rejects, but new_lenient loads around
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveProblem {
+    // the resource is left out
+    BadFileIndex { ty: String, id: u16, index: u16 },
+    // the first one is kept
+    DuplicateId { ty: String, id: u16 },
+    // the type's names are left out
+    BadNameTable { ty: String },
+}
+
+impl ArchiveProblem {
+    // damaged names only lose names, the archive is fine otherwise
+    fn is_fatal(&self) -> bool {
+        !matches!(self, ArchiveProblem::BadNameTable { .. })
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            ArchiveProblem::BadFileIndex { .. } => "bad file table index",
+            ArchiveProblem::DuplicateId { .. } => "duplicate resource id",
+            ArchiveProblem::BadNameTable { .. } => "bad name table",
+        }
+    }
+}
+
+impl std::fmt::Display for ArchiveProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ArchiveProblem::BadFileIndex { ty, id, index } => {
+                write!(f, "{} {} has bad file table index {}", ty, id, index)
+            }
+            ArchiveProblem::DuplicateId { ty, id } => {
+                write!(f, "{} {} appears more than once", ty, id)
+            }
+            ArchiveProblem::BadNameTable { ty } => {
+                write!(f, "{} name table can't be read", ty)
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct FileInfo {
-    offset: u64,
-    size: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub flags: u8,
 }
 
 #[derive(Debug)]
 pub struct ResourceInfo {
-    ty: String,
-    id: u16,
-    file_table_index: usize,
+    pub ty: String,
+    pub id: u16,
+    pub name: Option<String>,
+    pub file_table_index: usize,
 }
 
 impl<R> MhkArchive<R>
@@ -40,6 +89,16 @@ where
     R: AsyncRead + AsyncSeek + Unpin,
 {
     pub async fn new(unbuffered_handle: R) -> Result<Self> {
+        let arc = Self::new_lenient(unbuffered_handle).await?;
+        if let Some(p) = arc.problems.iter().find(|p| p.is_fatal()) {
+            anyhow::bail!(MhkError::InvalidFormat(p.describe()));
+        }
+        Ok(arc)
+    }
+
+    // loads what it can of a damaged archive, noting what is wrong in
+    // problems instead of failing, for inspecting it
+    pub async fn new_lenient(unbuffered_handle: R) -> Result<Self> {
         let mut handle = BufReader::new(unbuffered_handle);
 
         // do some sanity checks and load in the basic info
@@ -57,15 +116,15 @@ where
         handle.seek(SeekFrom::Start(rsrc.resource_dir_offset as u64)).await?;
 
         // this one is a weirdo, right at the beginning of the resource dir
-        let _name_list_offset: u16 = deserialize_from(&mut handle).await?;
+        let name_list_offset: u16 = deserialize_from(&mut handle).await?;
 
         // read in the type table
         let type_table: Vec<TypeTableEntry> =
             deserialize_u16_table_from(&mut handle).await?;
 
         // go and read the name and resource tables for each type
-        // let mut name_tables: Vec<Vec<NameTableEntry>> =
-        //     Vec::with_capacity(type_table.len());
+        let mut name_tables: Vec<Option<Vec<NameTableEntry>>> =
+            Vec::with_capacity(type_table.len());
         let mut resource_tables: Vec<Vec<ResourceTableEntry>> =
             Vec::with_capacity(type_table.len());
         for entry in &type_table {
@@ -75,11 +134,15 @@ where
             )).await?;
             resource_tables
                 .push(deserialize_u16_table_from(&mut handle).await?);
-            // handle.seek(SeekFrom::Start(
-            //     rsrc.resource_dir_offset as u64 +
-            //         entry.name_table_offset as u64
-            // )).await?;
-            // name_tables.push(deserialize_u16_table_from(&mut handle).await?);
+            // names are nice to have, so a bad table only loses them
+            let names = async {
+                handle.seek(SeekFrom::Start(
+                    rsrc.resource_dir_offset as u64 +
+                        entry.name_table_offset as u64
+                )).await?;
+                deserialize_u16_table_from(&mut handle).await
+            };
+            name_tables.push(names.await.ok());
         }
 
         // go to the file table and read it in
@@ -97,30 +160,75 @@ where
                 FileInfo {
                     offset: e.offset as u64,
                     size: (e.size_high as u64) << 16 | e.size_low as u64,
+                    flags: e.flags,
                 }
             })
             .collect();
 
         // ok, now we need to construct the resource type / id / names maps
         let mut resources = HashMap::with_capacity(type_table.len());
+        let mut problems = vec![];
         for (i, entry) in type_table.iter().enumerate() {
             let ty = std::str::from_utf8(&entry.resource_type)?.to_owned();
             let resource_table = &resource_tables[i];
-            // let name_table = &name_tables[i];
+            let name_table = match &name_tables[i] {
+                Some(table) => &table[..],
+                None => {
+                    problems.push(ArchiveProblem::BadNameTable {
+                        ty: ty.clone(),
+                    });
+                    &[]
+                }
+            };
             let mut ids = HashMap::with_capacity(resource_table.len());
 
             for rentry in resource_table {
-                if rentry.file_table_index as usize - 1 >= files.len() {
-                    anyhow::bail!(MhkError::InvalidFormat(
-                        "bad file table index",
-                    ));
+                // file table indices are 1-based
+                if rentry.file_table_index == 0
+                    || rentry.file_table_index as usize > files.len()
+                {
+                    problems.push(ArchiveProblem::BadFileIndex {
+                        ty: ty.clone(),
+                        id: rentry.resource_id,
+                        index: rentry.file_table_index,
+                    });
+                    continue;
+                }
+                if ids.contains_key(&rentry.resource_id) {
+                    problems.push(ArchiveProblem::DuplicateId {
+                        ty: ty.clone(),
+                        id: rentry.resource_id,
+                    });
+                    continue;
                 }
 
-                // normally here we'd look for a name, but... no. just no.
+                // names are keyed on file table index, not resource id
+                let mut name = None;
+                for nentry in name_table {
+                    if nentry.file_table_index == rentry.file_table_index {
+                        let pos = rsrc.resource_dir_offset as u64
+                            + name_list_offset as u64
+                            + nentry.name_offset as u64;
+                        let mut buf = Vec::new();
+                        let read = async {
+                            handle.seek(SeekFrom::Start(pos)).await?;
+                            handle.read_until(0, &mut buf).await
+                        };
+                        if read.await.is_ok() {
+                            if buf.last() == Some(&0) {
+                                buf.pop();
+                            }
+                            name = Some(
+                                String::from_utf8_lossy(&buf).into_owned());
+                        }
+                        break;
+                    }
+                }
 
                 let info = ResourceInfo {
                     ty: ty.clone(),
                     id: rentry.resource_id,
+                    name,
                     file_table_index: rentry.file_table_index as usize - 1,
                 };
                 ids.insert(rentry.resource_id, info);
@@ -133,8 +241,12 @@ where
 
         Ok(MhkArchive {
             handle: std::rc::Rc::new(std::cell::RefCell::new((pos, handle))),
+            mhwk,
+            rsrc,
+            type_table,
             files,
             resources,
+            problems,
         })
     }
 
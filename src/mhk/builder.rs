@@ -0,0 +1,253 @@
+use super::MhkError;
+
+use anyhow::Result;
+
+#[derive(Debug, Default, Clone)]
+pub struct MhkBuilder {
+    files: Vec<(Vec<u8>, u8)>,
+    // type, id, 1-based file table index
+    resources: Vec<([u8; 4], u16, u16)>,
+    // type, name, 1-based file table index
+    names: Vec<([u8; 4], String, u16)>,
+}
+
+impl MhkBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // returns the 1-based file table index of the new file
+    pub fn add_file(&mut self, data: &[u8], flags: u8) -> u16 {
+        self.files.push((data.to_owned(), flags));
+        self.files.len() as u16
+    }
+
+    pub fn add_resource(&mut self, typ: &str, id: u16, file_index: u16) {
+        self.resources.push((type_tag(typ), id, file_index));
+    }
+
+    pub fn add_name(&mut self, typ: &str, name: &str, file_index: u16) {
+        self.names.push((type_tag(typ), name.to_owned(), file_index));
+    }
+
+    pub fn get(&self, typ: &str, id: u16) -> Option<&[u8]> {
+        let tag = type_tag(typ);
+        self.resources.iter()
+            .find(|(t, i, _)| *t == tag && *i == id)
+            .and_then(|(_, _, index)| {
+                self.files.get((*index as usize).checked_sub(1)?)
+            })
+            .map(|(data, _)| data.as_slice())
+    }
+
+    pub fn ids(&self, typ: &str) -> Vec<u16> {
+        let tag = type_tag(typ);
+        self.resources.iter()
+            .filter(|(t, _, _)| *t == tag)
+            .map(|(_, id, _)| *id)
+            .collect()
+    }
+
+    pub fn types(&self) -> Vec<String> {
+        let mut tags: Vec<[u8; 4]> =
+            self.resources.iter().map(|(t, _, _)| *t).collect();
+        tags.sort();
+        tags.dedup();
+        tags.iter()
+            .map(|t| {
+                let len = t.iter().position(|&b| b == 0).unwrap_or(4);
+                String::from_utf8_lossy(&t[..len]).into_owned()
+            })
+            .collect()
+    }
+
+    // add a resource, or replace the data of one that already exists
+    pub fn set(&mut self, typ: &str, id: u16, data: &[u8]) -> u16 {
+        let tag = type_tag(typ);
+        let existing = self.resources.iter()
+            .find(|(t, i, _)| *t == tag && *i == id)
+            .map(|(_, _, index)| *index);
+        if let Some(index) = existing {
+            if let Some(file) = (index as usize).checked_sub(1)
+                .and_then(|i| self.files.get_mut(i))
+            {
+                file.0 = data.to_owned();
+                return index;
+            }
+        }
+        self.add(typ, id, data)
+    }
+
+    pub fn add(&mut self, typ: &str, id: u16, data: &[u8]) -> u16 {
+        let index = self.add_file(data, 0);
+        self.add_resource(typ, id, index);
+        index
+    }
+
+    pub fn add_named(&mut self, typ: &str, id: u16, name: &str, data: &[u8])
+                     -> u16
+    {
+        let index = self.add(typ, id, data);
+        self.add_name(typ, name, index);
+        index
+    }
+
+    pub fn build(&self) -> Result<Vec<u8>> {
+        let header_size = 28;
+        let too_large = || MhkError::InvalidFormat("archive too large");
+
+        // file data comes first, right after the headers
+        let mut data = Vec::new();
+        let mut offsets = Vec::with_capacity(self.files.len());
+        for (f, _) in &self.files {
+            if f.len() >= 1 << 24 {
+                anyhow::bail!(too_large());
+            }
+            offsets.push(header_size + data.len() as u32);
+            data.extend_from_slice(f);
+        }
+        let resource_dir_offset = header_size + data.len() as u32;
+
+        let mut types: Vec<[u8; 4]> = Vec::new();
+        for (t, _, _) in &self.resources {
+            if !types.contains(t) {
+                types.push(*t);
+            }
+        }
+        for (t, _, _) in &self.names {
+            if !types.contains(t) {
+                types.push(*t);
+            }
+        }
+
+        // resource dir: name list offset, type table, then the tables
+        let type_table_size = 2 + 8 * types.len();
+        let mut tables = ResourceBuilder::new();
+        let mut type_table = ResourceBuilder::new();
+        let mut name_list = Vec::new();
+        type_table.u16(types.len() as u16);
+        for t in &types {
+            let resources: Vec<_> = self.resources.iter()
+                .filter(|(rt, _, _)| rt == t).collect();
+            let names: Vec<_> = self.names.iter()
+                .filter(|(nt, _, _)| nt == t).collect();
+
+            let resource_table_offset = 2 + type_table_size + tables.len();
+            tables.u16(resources.len() as u16);
+            for (_, id, index) in resources {
+                tables.u16(*id).u16(*index);
+            }
+
+            let name_table_offset = 2 + type_table_size + tables.len();
+            tables.u16(names.len() as u16);
+            for (_, name, index) in names {
+                tables.u16(name_list.len() as u16).u16(*index);
+                name_list.extend_from_slice(name.as_bytes());
+                name_list.push(0);
+            }
+
+            type_table.bytes(t);
+            type_table.u16(resource_table_offset as u16);
+            type_table.u16(name_table_offset as u16);
+        }
+
+        let file_table_offset = 2 + type_table_size + tables.len();
+        let mut file_table = ResourceBuilder::new();
+        file_table.u32(self.files.len() as u32);
+        for ((f, flags), offset) in self.files.iter().zip(offsets) {
+            file_table.u32(offset);
+            file_table.u16(f.len() as u16);
+            file_table.u8((f.len() >> 16) as u8);
+            file_table.u8(*flags);
+            file_table.u16(0);
+        }
+        let name_list_offset = file_table_offset + file_table.len();
+        // everything in the resource dir is addressed with 16 bits
+        if name_list_offset + name_list.len() > u16::MAX as usize {
+            anyhow::bail!(too_large());
+        }
+
+        let mut dir = ResourceBuilder::new();
+        dir.u16(name_list_offset as u16);
+        dir.bytes(&type_table.finish());
+        dir.bytes(&tables.finish());
+        dir.bytes(&file_table.finish());
+        dir.bytes(&name_list);
+        let dir = dir.finish();
+
+        let total = resource_dir_offset + dir.len() as u32;
+        let mut out = ResourceBuilder::new();
+        out.bytes(b"MHWK").u32(total - 8);
+        out.bytes(b"RSRC").u16(0x100).u16(1).u32(total);
+        out.u32(resource_dir_offset);
+        out.u16(file_table_offset as u16);
+        out.u16((4 + 10 * self.files.len()) as u16);
+        out.bytes(&data);
+        out.bytes(&dir);
+        Ok(out.finish())
+    }
+}
+
+fn type_tag(typ: &str) -> [u8; 4] {
+    let mut tag = [0; 4];
+    for (t, b) in tag.iter_mut().zip(typ.bytes()) {
+        *t = b;
+    }
+    tag
+}
+
+// big-endian writer for building resource bodies by hand
+#[derive(Debug, Default, Clone)]
+pub struct ResourceBuilder(Vec<u8>);
+
+impl ResourceBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn u8(&mut self, v: u8) -> &mut Self {
+        self.0.push(v);
+        self
+    }
+
+    pub fn u16(&mut self, v: u16) -> &mut Self {
+        self.0.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    pub fn i16(&mut self, v: i16) -> &mut Self {
+        self.0.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, v: u32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    pub fn bytes(&mut self, v: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(v);
+        self
+    }
+
+    // a u16 count followed by that many u16 values
+    pub fn u16_table(&mut self, v: &[u16]) -> &mut Self {
+        self.u16(v.len() as u16);
+        for x in v {
+            self.u16(*x);
+        }
+        self
+    }
+
+    pub fn finish(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+}
@@ -4,4 +4,10 @@ pub enum MhkError {
     InvalidFormat(&'static str),
     #[error("Resource does not exist: {0:?} {1} {2}")]
     ResourceNotFound(Option<String>, String, u16),
+    #[error("Unknown stack in layout: {0}")]
+    UnknownStack(String),
+    #[error("Could not find a known file layout")]
+    UnknownLayout,
+    #[error("Layout extends unknown layout {0:?}")]
+    UnknownLayoutBase(String),
 }
@@ -1,23 +1,35 @@
-use crate::{Format, FormatWrite};
-
 use anyhow::Result;
 use smol::io::{AsyncRead, AsyncReadExt};
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
-pub struct MhkFormat;
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub struct MhkFormat {
+    // keep script commands that can't be decoded, instead of failing
+    lenient_scripts: bool,
+}
+
+impl MhkFormat {
+    pub fn with_lenient_scripts(self, lenient_scripts: bool) -> Self {
+        MhkFormat { lenient_scripts }
+    }
+
+    pub fn lenient_scripts(&self) -> bool {
+        self.lenient_scripts
+    }
 
-#[async_trait::async_trait(?Send)]
-impl<R, I, D> FormatWrite<MhkFormat, R, I, D> for MhkFormat
-where
-    Self: Format<R, I, D>,
-    D: 'static,
-    I: AsyncRead + Unpin,
-{
-    async fn convert(&self, _fmti: &MhkFormat, _res: &R, input: &mut I)
-                     -> Result<Vec<u8>>
+    // converting from one archive to another copies the bytes as they
+    // are, rather than parsing and re-encoding them. gives None, without
+    // reading anything, when fmti is some other format
+    pub(crate) async fn passthrough<Fi, I>(fmti: &Fi, input: &mut I)
+                                           -> Result<Option<Vec<u8>>>
+    where
+        Fi: 'static,
+        I: AsyncRead + Unpin,
     {
+        if !(fmti as &dyn std::any::Any).is::<MhkFormat>() {
+            return Ok(None);
+        }
         let mut data = Vec::new();
         input.read_to_end(&mut data).await?;
-        Ok(data)
+        Ok(Some(data))
     }
 }
@@ -0,0 +1,125 @@
+use super::{MhkError, MhkMap};
+use crate::filesystem::{Filesystem, ZArchive};
+use crate::Stack;
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde_derive::{Deserialize, Serialize};
+
+// describes where each stack's archives live for one edition of a game
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MhkLayout {
+    pub name: String,
+    // another layout whose stacks and optional files are used when this
+    // one doesn't list its own
+    #[serde(default)]
+    pub extends: Option<String>,
+    // installshield archive that is searched before the filesystem
+    #[serde(default)]
+    pub archive: Option<String>,
+    // files that must all exist for this layout to be chosen
+    #[serde(default)]
+    pub probe: Vec<String>,
+    // files that are skipped, rather than an error, if missing
+    #[serde(default)]
+    pub optional: Vec<String>,
+    #[serde(default)]
+    pub stacks: HashMap<String, Vec<String>>,
+}
+
+pub type LayoutFilesystem<F> =
+    either::Either<(ZArchive<<F as Filesystem>::Handle>, F), F>;
+
+impl MhkLayout {
+    pub fn from_json(data: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(data)?)
+    }
+
+    // parse several layouts, each of which may extend an earlier one
+    pub fn from_json_list(data: &[&[u8]]) -> Result<Vec<Self>> {
+        let mut layouts: Vec<Self> = Vec::with_capacity(data.len());
+        for d in data {
+            let mut layout = Self::from_json(d)?;
+            layout.inherit(&layouts)?;
+            layouts.push(layout);
+        }
+        Ok(layouts)
+    }
+
+    // fill in whatever this layout leaves out from the one it extends
+    pub fn inherit(&mut self, layouts: &[MhkLayout]) -> Result<()> {
+        let base = match &self.extends {
+            Some(name) => layouts.iter().find(|l| &l.name == name)
+                .ok_or_else(|| MhkError::UnknownLayoutBase(name.clone()))?,
+            None => return Ok(()),
+        };
+        if self.stacks.is_empty() {
+            self.stacks = base.stacks.clone();
+        }
+        if self.optional.is_empty() {
+            self.optional = base.optional.clone();
+        }
+        Ok(())
+    }
+
+    pub async fn probe<F>(&self, fs: &mut F) -> bool
+    where
+        F: Filesystem,
+    {
+        for name in self.probe.iter().chain(self.archive.iter()) {
+            if fs.open(&[name]).await.is_err() {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub async fn detect<'a, F>(layouts: &'a [MhkLayout], fs: &mut F)
+                               -> Result<&'a MhkLayout>
+    where
+        F: Filesystem,
+    {
+        for layout in layouts {
+            if layout.probe(fs).await {
+                return Ok(layout);
+            }
+        }
+        anyhow::bail!(MhkError::UnknownLayout);
+    }
+
+    pub fn stack_files<S>(&self) -> Result<HashMap<S, Vec<&str>>>
+    where
+        S: Stack + Copy,
+    {
+        let mut ret = HashMap::with_capacity(self.stacks.len());
+        for (name, files) in &self.stacks {
+            let stack = S::from_name(name)
+                .ok_or_else(|| MhkError::UnknownStack(name.clone()))?;
+            ret.insert(stack, files.iter().map(|s| s.as_str()).collect());
+        }
+        Ok(ret)
+    }
+
+    pub async fn open<F, S>(&self, mut fs: F)
+                            -> Result<MhkMap<LayoutFilesystem<F>, S>>
+    where
+        F: Filesystem,
+        S: Stack + Copy,
+    {
+        let stackfiles = self.stack_files()?;
+        let fs = if let Some(archive) = &self.archive {
+            let handle = fs.open(&[archive]).await?;
+            let z = ZArchive::new(handle).await?;
+            either::Left((z, fs))
+        } else {
+            either::Right(fs)
+        };
+        let mut map = MhkMap::new(fs, stackfiles);
+        for name in &self.optional {
+            map.add_optional_file(name);
+        }
+        Ok(map)
+    }
+}
@@ -1,20 +1,35 @@
-use super::{MhkArchive, MhkError, MhkFormat, Narrow};
+use super::{
+    FileInfo, MhkArchive, MhkError, MhkFormat, Narrow, ResourceInfo,
+    TypeTableEntry,
+};
 use super::ownedpe::OwnedPe32;
-use crate::filesystem::{Filesystem, EitherHandle};
+use crate::filesystem::{Filesystem, EitherHandle, Resident};
 
 use anyhow::Result;
 use smol::io::{AsyncRead, AsyncReadExt, BufReader, Cursor};
 
-use crate::{ResourceMap, ResourceMapList, Stack};
-use std::collections::HashMap;
+use crate::{content_hash, ResourceMap, ResourceMapList, Stack};
+use std::collections::{HashMap, HashSet};
 
 pub struct MhkMap<F, S>
 where
     F: Filesystem,
 {
     filesystem: F,
+    format: MhkFormat,
     stackfiles: HashMap<S, Vec<String>>,
-    stacks: HashMap<S, Vec<Archive<F::Handle>>>,
+    optional: HashSet<String>,
+    stacks: HashMap<S, LoadedStack<F::Handle>>,
+    pinned: HashSet<S>,
+    budget: Option<u64>,
+    clock: u64,
+}
+
+struct LoadedStack<H: AsyncRead> {
+    // in load order, so later archives override earlier ones
+    archives: Vec<(String, Archive<H>)>,
+    size: u64,
+    last_used: u64,
 }
 
 enum Archive<H: AsyncRead> {
@@ -22,6 +37,68 @@ enum Archive<H: AsyncRead> {
     Pe32(OwnedPe32),
 }
 
+impl<H: AsyncRead> Archive<H> {
+    // only what is held in memory. mhk resources are read from the
+    // handle as needed, so that's the tables, plus whatever the handle
+    // itself holds, like a whole file unpacked from an installer archive
+    fn size(&self) -> u64
+    where
+        H: Resident,
+    {
+        use std::mem::size_of;
+        match self {
+            Archive::Mhk(marc) => {
+                let types = marc.type_table.len()
+                    * size_of::<TypeTableEntry>();
+                let files = marc.files.len() * size_of::<FileInfo>();
+                let resources: usize = marc.resources.values()
+                    .flat_map(|rs| rs.values())
+                    .map(|r| size_of::<ResourceInfo>() + r.ty.len()
+                         + r.name.as_ref().map_or(0, |n| n.len()))
+                    .sum();
+                let handle = marc.handle.borrow().1.get_ref()
+                    .resident_size();
+                (types + files + resources) as u64 + handle
+            },
+            Archive::Pe32(parc) => parc.size() as u64,
+        }
+    }
+
+    fn contains(&self, typ: &str, id: u16) -> bool {
+        match self {
+            Archive::Mhk(marc) => marc.resources.get(typ)
+                .map(|rs| rs.contains_key(&id))
+                .unwrap_or(false),
+            Archive::Pe32(parc) => parc.get(typ)
+                .map(|rs| rs.contains(&id))
+                .unwrap_or(false),
+        }
+    }
+
+    fn ids(&self, typ: &str) -> Vec<u16> {
+        match self {
+            Archive::Mhk(marc) => marc.resources.get(typ)
+                .map(|rs| rs.keys().cloned().collect())
+                .unwrap_or_default(),
+            Archive::Pe32(parc) => parc.get(typ).unwrap_or_default(),
+        }
+    }
+
+    fn types(&self) -> Vec<String> {
+        match self {
+            Archive::Mhk(marc) => marc.resources.keys().cloned().collect(),
+            // cursors are the only thing read out of executables
+            Archive::Pe32(_) => vec!["tCUR".to_owned()],
+        }
+    }
+}
+
+impl<H: AsyncRead> LoadedStack<H> {
+    fn find(&self, typ: &str, id: u16) -> Option<&(String, Archive<H>)> {
+        self.archives.iter().rev().find(|(_, arc)| arc.contains(typ, id))
+    }
+}
+
 impl<F, S> MhkMap<F, S>
 where
     F: Filesystem,
@@ -30,14 +107,96 @@ where
     pub fn new(filesystem: F, stackfiles: HashMap<S, Vec<&str>>) -> Self {
         MhkMap {
             filesystem,
+            format: MhkFormat::default(),
             stackfiles: stackfiles
                 .iter()
                 .map(|(k, v)| (k.clone(), v.iter().map(|s| (*s).to_owned()).collect()))
                 .collect(),
+            optional: HashSet::new(),
             stacks: HashMap::with_capacity(S::all().len()),
+            pinned: HashSet::new(),
+            budget: None,
+            clock: 0,
+        }
+    }
+
+    pub fn set_lenient_scripts(&mut self, lenient: bool) {
+        self.format = self.format.with_lenient_scripts(lenient);
+    }
+
+    // limit the total size of loaded stacks, in bytes
+    // the least recently used stacks are dropped to stay under it
+    pub fn set_memory_budget(&mut self, budget: Option<u64>) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    // keep a stack loaded no matter the budget, once it has been loaded
+    pub fn pin_stack(&mut self, stack: S) {
+        self.pinned.insert(stack);
+    }
+
+    pub fn memory_used(&self) -> u64 {
+        self.stacks.values().map(|s| s.size).sum()
+    }
+
+    // add an archive that overrides all the others already in this stack
+    pub fn add_patch_file(&mut self, stack: S, name: &str) {
+        let mut names = self.stack_file_names(stack);
+        names.push(name.to_owned());
+        self.stackfiles.insert(stack, names);
+        // reload on next use, so the patch is in the right place
+        self.stacks.remove(&stack);
+    }
+
+    // find the name of the archive a resource will be read from
+    pub async fn source(&mut self, stack: S, typ: &str, id: u16)
+                        -> Result<String>
+    {
+        self.ensure_stack(stack).await?;
+        self.stacks.get(&stack).unwrap().find(typ, id)
+            .map(|(name, _)| name.clone())
+            .ok_or_else(|| MhkError::ResourceNotFound(
+                Some(stack.name().to_owned()),
+                typ.to_owned(),
+                id,
+            ).into())
+    }
+
+    pub fn loaded_stacks(&self) -> Vec<S> {
+        self.stacks.keys().cloned().collect()
+    }
+
+    pub fn unload_stack(&mut self, stack: S) {
+        self.stacks.remove(&stack);
+    }
+
+    fn evict(&mut self) {
+        let budget = match self.budget {
+            Some(b) => b,
+            None => return,
+        };
+        // never evict the most recently used stack, it is the one in use
+        let current = self.stacks.iter()
+            .max_by_key(|(_, s)| s.last_used)
+            .map(|(k, _)| *k);
+        while self.memory_used() > budget {
+            let oldest = self.stacks.iter()
+                .filter(|(k, _)| Some(**k) != current)
+                .filter(|(k, _)| !self.pinned.contains(k))
+                .min_by_key(|(_, s)| s.last_used)
+                .map(|(k, _)| *k);
+            match oldest {
+                Some(k) => self.stacks.remove(&k),
+                None => break,
+            };
         }
     }
 
+    pub fn add_optional_file(&mut self, name: &str) {
+        self.optional.insert(name.to_owned());
+    }
+
     fn stack_file_names(&self, stack: S) -> Vec<String> {
         if let Some(names) = self.stackfiles.get(&stack) {
             names.clone()
@@ -47,22 +206,49 @@ where
     }
 
     async fn ensure_stack(&mut self, stack: S) -> Result<()> {
+        self.clock += 1;
+        self.load_stack(stack, self.clock).await
+    }
+
+    async fn load_stack(&mut self, stack: S, last_used: u64) -> Result<()> {
+        if let Some(loaded) = self.stacks.get_mut(&stack) {
+            loaded.last_used = loaded.last_used.max(last_used);
+        }
+
         // make sure this stack is loaded
         if !self.stacks.contains_key(&stack) {
             let names = self.stack_file_names(stack);
             let mut archives = Vec::with_capacity(names.len());
             for n in names.iter() {
                 let path = &[n.as_ref()];
-                let mut handle = self.filesystem.open(path).await?;
-                if n.ends_with(".exe") {
+                let mut handle = match self.filesystem.open(path).await {
+                    Ok(h) => h,
+                    Err(_) if self.optional.contains(n) => continue,
+                    Err(e) => return Err(e),
+                };
+                // cursors come out of an executable, or a dll
+                let lower = n.to_ascii_lowercase();
+                if lower.ends_with(".exe") || lower.ends_with(".dll") {
                     let mut data = Vec::with_capacity(600000);
                     handle.read_to_end(&mut data).await?;
-                    archives.push(Archive::Pe32(OwnedPe32::new(data)?));
+                    archives.push((
+                        n.clone(),
+                        Archive::Pe32(OwnedPe32::new(data)?),
+                    ));
                 } else {
-                    archives.push(Archive::Mhk(MhkArchive::new(handle).await?));
+                    archives.push((
+                        n.clone(),
+                        Archive::Mhk(MhkArchive::new(handle).await?),
+                    ));
                 }
             }
-            self.stacks.insert(stack, archives);
+            let size = archives.iter().map(|(_, a)| a.size()).sum();
+            self.stacks.insert(stack, LoadedStack {
+                archives,
+                size,
+                last_used,
+            });
+            self.evict();
         }
         Ok(())
     }
@@ -79,7 +265,13 @@ where
     type Format = MhkFormat;
 
     fn format(&self) -> &Self::Format {
-        &MhkFormat
+        &self.format
+    }
+
+    // preloaded stacks count as used longest ago, so they are the first
+    // to go and never push out a stack that is actually in use
+    async fn preload(&mut self, stack: Self::Stack) -> Result<()> {
+        self.load_stack(stack, 0).await
     }
 
     async fn open_raw(
@@ -90,15 +282,12 @@ where
         _ext: &str,
     ) -> Result<Self::Handle> {
         self.ensure_stack(stack).await?;
-        for arc in self.stacks.get(&stack).unwrap() {
+        if let Some((_, arc)) = self.stacks.get(&stack).unwrap().find(typ, id) {
             let rsrc = match arc {
                 Archive::Mhk(marc) => EitherHandle::left(marc.open(typ, id)),
                 Archive::Pe32(parc) => EitherHandle::right(parc.open(typ, id)),
             };
-            match rsrc.factor_error() {
-                Ok(r) => return Ok(r),
-                Err(_) => (), // we should only ignore if ResourceNotFound, but
-            }
+            return rsrc.factor_error();
         }
 
         anyhow::bail!(MhkError::ResourceNotFound(
@@ -107,6 +296,41 @@ where
             id,
         ));
     }
+
+    // patching a resource moves or resizes it, so which archive it's in,
+    // that archive's size and where the resource sits in it are enough,
+    // without reading anything. executables only hold small cursors, and
+    // those are simply hashed
+    async fn fingerprint(
+        &mut self,
+        stack: Self::Stack,
+        typ: &str,
+        id: u16,
+    ) -> Result<Option<String>> {
+        self.ensure_stack(stack).await?;
+        let found = self.stacks.get(&stack).unwrap().find(typ, id);
+        let file = match found {
+            Some((name, Archive::Mhk(marc))) => marc.resources.get(typ)
+                .and_then(|rs| rs.get(&id))
+                .and_then(|r| marc.files.get(r.file_table_index))
+                .map(|f| (name, marc.mhwk.file_size, f.offset, f.size)),
+            Some((_, Archive::Pe32(parc))) => {
+                let mut data = Vec::new();
+                parc.open(typ, id)?.read_to_end(&mut data).await?;
+                return Ok(Some(content_hash(&data)));
+            },
+            None => None,
+        };
+        match file {
+            Some((name, archive_size, offset, size)) => Ok(Some(
+                format!("{}:{}:{}:{}", name, archive_size, offset, size))),
+            None => anyhow::bail!(MhkError::ResourceNotFound(
+                Some(stack.name().to_owned()),
+                typ.to_owned(),
+                id,
+            )),
+        }
+    }
 }
 
 #[async_trait::async_trait(?Send)]
@@ -118,25 +342,25 @@ where
     async fn list(&mut self, stack: <Self as ResourceMap>::Stack, typ: &str) -> Result<Vec<u16>> {
         self.ensure_stack(stack).await?;
         let mut ret = vec![];
-        for arc in self.stacks.get(&stack).unwrap() {
-            match arc {
-                Archive::Mhk(marc) => {
-                    if let Some(rs) = marc.resources.get(typ) {
-                        for (id, _) in rs {
-                            ret.push(*id);
-                        }
-                    }
-                }
-                Archive::Pe32(parc) => {
-                    if let Some(rs) = parc.get(typ) {
-                        for id in rs {
-                            ret.push(id);
-                        }
-                    }
-                }
-            }
+        for (_, arc) in &self.stacks.get(&stack).unwrap().archives {
+            ret.extend(arc.ids(typ));
+        }
+        // overridden resources show up in more than one archive
+        ret.sort();
+        ret.dedup();
+        Ok(ret)
+    }
+
+    async fn types(&mut self, stack: <Self as ResourceMap>::Stack)
+                   -> Result<Vec<String>>
+    {
+        self.ensure_stack(stack).await?;
+        let mut ret = vec![];
+        for (_, arc) in &self.stacks.get(&stack).unwrap().archives {
+            ret.extend(arc.types());
         }
         ret.sort();
+        ret.dedup();
         Ok(ret)
     }
 }
@@ -1,6 +1,8 @@
-mod chunks;
 mod ownedpe;
 
+mod chunks;
+pub use chunks::*;
+
 mod utility;
 pub use utility::*;
 
@@ -16,5 +18,17 @@ pub use error::MhkError;
 mod map;
 pub use map::MhkMap;
 
+mod writer;
+pub use writer::MhkWriter;
+
+mod layout;
+pub use layout::*;
+
+mod builder;
+pub use builder::*;
+
 mod format;
 pub use format::MhkFormat;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
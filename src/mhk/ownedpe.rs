@@ -1,10 +1,12 @@
+use super::MhkError;
+
 use anyhow::Result;
 use pelite::pe32::Pe;
 use pelite::resources::Name;
 use smol::io::Cursor;
 
 pub struct OwnedPe32 {
-    _data: Vec<u8>,
+    data: Vec<u8>,
     pe_unsafe: pelite::pe32::PeFile<'static>,
 }
 
@@ -21,10 +23,14 @@ impl OwnedPe32 {
         };
         Ok(OwnedPe32 {
             pe_unsafe: pelite::pe32::PeFile::from_bytes(buf)?,
-            _data: data,
+            data,
         })
     }
 
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
     pub fn pe<'a>(&'a self) -> &'a pelite::pe32::PeFile<'a> {
         &self.pe_unsafe
     }
@@ -57,7 +63,9 @@ impl OwnedPe32 {
             let cur = cur?;
             if let Name::Id(testid) = cur.0 {
                 if id as u32 == testid {
-                    let groupid = cur.1.entries()[0].nId;
+                    let groupid = cur.1.entries().first()
+                        .ok_or(MhkError::InvalidFormat("empty cursor group"))?
+                        .nId;
                     let dataref = cur.1.image(groupid)?;
                     let mut data = Vec::with_capacity(dataref.len());
                     data.extend_from_slice(dataref);
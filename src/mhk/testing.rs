@@ -0,0 +1,54 @@
+// helpers for building Mohawk archives and resources in tests and fuzzers
+
+use super::MhkFormat;
+use crate::{Format, FormatWrite, ResourceType};
+
+use anyhow::Result;
+
+pub use super::builder::{MhkBuilder, ResourceBuilder};
+
+// parse a resource directly out of a byte slice
+pub fn parse_bytes<R>(res: R, data: &[u8]) -> Result<R::Data>
+where
+    R: ResourceType,
+    MhkFormat: Format<R, smol::io::Cursor<Vec<u8>>, R::Data>,
+{
+    let mut input = smol::io::Cursor::new(data.to_owned());
+    smol::block_on(MhkFormat::default().parse(&res, &mut input))
+}
+
+// reads like MhkFormat, but the writers don't take it for an archive,
+// so converting from it can't just copy the bytes
+pub struct Reparse;
+
+#[async_trait::async_trait(?Send)]
+impl<R, I, D> Format<R, I, D> for Reparse
+where
+    MhkFormat: Format<R, I, D>,
+{
+    async fn parse(&self, res: &R, input: &mut I) -> Result<D> {
+        MhkFormat::default().parse(res, input).await
+    }
+}
+
+// parse a resource and serialize it back into Mohawk layout
+pub fn roundtrip_bytes<R>(res: R, data: &[u8]) -> Result<Vec<u8>>
+where
+    R: ResourceType,
+    MhkFormat: FormatWrite<Reparse, R, smol::io::Cursor<Vec<u8>>, R::Data>,
+{
+    let mut input = smol::io::Cursor::new(data.to_owned());
+    smol::block_on(MhkFormat::default().convert(&Reparse, &res, &mut input))
+}
+
+// convert a resource from one archive to another, the way an export
+// between two MHK maps does
+pub fn copy_bytes<R>(res: R, data: &[u8]) -> Result<Vec<u8>>
+where
+    R: ResourceType,
+    MhkFormat: FormatWrite<MhkFormat, R, smol::io::Cursor<Vec<u8>>, R::Data>,
+{
+    let mut input = smol::io::Cursor::new(data.to_owned());
+    let fmt = MhkFormat::default();
+    smol::block_on(fmt.convert(&fmt, &res, &mut input))
+}
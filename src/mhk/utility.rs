@@ -1,15 +1,45 @@
+use super::MhkError;
+
 use anyhow::Result;
 use bincode::Options;
 use smol::io::{AsyncRead, AsyncReadExt};
 
+// size_of can include padding, so find the real encoded size by decoding
+// zeroes and seeing how many bytes were used. this is done once per type
+fn encoded_size<T>() -> Result<usize>
+where
+    T: serde::de::DeserializeOwned + 'static,
+{
+    use std::any::TypeId;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    thread_local! {
+        static SIZES: RefCell<HashMap<TypeId, usize>> =
+            RefCell::new(HashMap::new());
+    }
+
+    let key = TypeId::of::<T>();
+    if let Some(size) = SIZES.with(|s| s.borrow().get(&key).cloned()) {
+        return Ok(size);
+    }
+    let zeroes = vec![0u8; std::mem::size_of::<T>()];
+    let mut cursor = std::io::Cursor::new(zeroes);
+    let _: T = bincode::options().with_fixint_encoding()
+        .deserialize_from(&mut cursor)?;
+    let size = cursor.position() as usize;
+    SIZES.with(|s| s.borrow_mut().insert(key, size));
+    Ok(size)
+}
+
 pub async fn deserialize_from<'a, R, T>(
     reader: &'a mut R
 ) -> Result<T>
 where
     R: AsyncRead + Unpin,
-    T: serde::de::DeserializeOwned,
+    T: serde::de::DeserializeOwned + 'static,
 {
-    let size = std::mem::size_of::<T>();
+    let size = encoded_size::<T>()?;
     let mut buf = vec![0u8; size];
     reader.read_exact(&mut buf).await?;
     let data = bincode::options().with_big_endian().with_fixint_encoding()
@@ -22,9 +52,9 @@ pub async fn deserialize_le_from<'a, R, T>(
 ) -> Result<T>
 where
     R: AsyncRead + Unpin,
-    T: serde::de::DeserializeOwned,
+    T: serde::de::DeserializeOwned + 'static,
 {
-    let size = std::mem::size_of::<T>();
+    let size = encoded_size::<T>()?;
     let mut buf = vec![0u8; size];
     reader.read_exact(&mut buf).await?;
     let data = bincode::options().with_little_endian().with_fixint_encoding()
@@ -38,11 +68,17 @@ pub async fn deserialize_vec_from<'a, R, T>(
 ) -> Result<Vec<T>>
 where
     R: AsyncRead + Unpin,
-    T: serde::de::DeserializeOwned,
+    T: serde::de::DeserializeOwned + 'static,
 {
-    let size = std::mem::size_of::<T>();
-    let mut buf = vec![0u8; count * size];
-    reader.read_exact(&mut buf).await?;
+    let size = encoded_size::<T>()?;
+    let total = count.checked_mul(size)
+        .ok_or(MhkError::InvalidFormat("table too large"))?;
+    // read incrementally, so a bad count can't allocate everything up front
+    let mut buf = Vec::with_capacity(total.min(1 << 16));
+    reader.take(total as u64).read_to_end(&mut buf).await?;
+    if buf.len() != total {
+        anyhow::bail!(MhkError::InvalidFormat("table truncated"));
+    }
     let mut cursor = std::io::Cursor::new(buf);
     let mut ret = Vec::with_capacity(count);
     for _ in 0..count {
@@ -57,7 +93,7 @@ pub async fn deserialize_u16_table_from<'a, R, T>(
 ) -> Result<Vec<T>>
 where
     R: AsyncRead + Unpin,
-    T: serde::de::DeserializeOwned,
+    T: serde::de::DeserializeOwned + 'static,
 {
     deserialize_table_from::<u16, R, T>(reader).await
 }
@@ -67,7 +103,7 @@ pub async fn deserialize_u32_table_from<'a, R, T>(
 ) -> Result<Vec<T>>
 where
     R: AsyncRead + Unpin,
-    T: serde::de::DeserializeOwned,
+    T: serde::de::DeserializeOwned + 'static,
 {
     deserialize_table_from::<u32, R, T>(reader).await
 }
@@ -76,10 +112,50 @@ pub async fn deserialize_table_from<'a, S, R, T>(
     reader: &'a mut R,
 ) -> Result<Vec<T>>
 where
-    S: Into<u64> + serde::de::DeserializeOwned,
+    S: Into<u64> + serde::de::DeserializeOwned + 'static,
     R: AsyncRead + Unpin,
-    T: serde::de::DeserializeOwned,
+    T: serde::de::DeserializeOwned + 'static,
 {
     let count: S = deserialize_from(reader).await?;
     deserialize_vec_from(reader, count.into() as usize).await
 }
+
+pub fn serialize_into<T>(out: &mut Vec<u8>, value: &T) -> Result<()>
+where
+    T: serde::Serialize,
+{
+    bincode::options().with_big_endian().with_fixint_encoding()
+        .serialize_into(out, value)?;
+    Ok(())
+}
+
+pub fn serialize_le_into<T>(out: &mut Vec<u8>, value: &T) -> Result<()>
+where
+    T: serde::Serialize,
+{
+    bincode::options().with_little_endian().with_fixint_encoding()
+        .serialize_into(out, value)?;
+    Ok(())
+}
+
+pub fn serialize_vec_into<T>(out: &mut Vec<u8>, values: &[T]) -> Result<()>
+where
+    T: serde::Serialize,
+{
+    for v in values {
+        serialize_into(out, v)?;
+    }
+    Ok(())
+}
+
+pub fn serialize_u16_table_into<T>(out: &mut Vec<u8>, values: &[T])
+                                   -> Result<()>
+where
+    T: serde::Serialize,
+{
+    if values.len() > u16::MAX as usize {
+        anyhow::bail!(MhkError::InvalidFormat("table too large"));
+    }
+    serialize_into(out, &(values.len() as u16))?;
+    serialize_vec_into(out, values)
+}
@@ -0,0 +1,126 @@
+use super::{MhkBuilder, MhkError, MhkFormat};
+use crate::filesystem::FilesystemWrite;
+use crate::{ResourceMap, ResourceMapList, ResourceMapWrite, Stack};
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use smol::io::Cursor;
+
+// collects resources in memory, then writes one {stack}.MHK per stack,
+// which is the name MhkMap looks for when it has no file list. nothing
+// reaches the filesystem until finish or flush, so call one of them
+// before dropping it, or whatever was added since is lost
+pub struct MhkWriter<F, S> {
+    filesystem: F,
+    format: MhkFormat,
+    stacks: HashMap<S, MhkBuilder>,
+    // written to since the last finish
+    dirty: bool,
+}
+
+impl<F, S> MhkWriter<F, S>
+where
+    F: FilesystemWrite,
+    S: Stack,
+{
+    pub fn new(filesystem: F) -> Self {
+        MhkWriter {
+            filesystem,
+            format: MhkFormat::default(),
+            stacks: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    pub async fn finish(&mut self) -> Result<()> {
+        for (stack, builder) in &self.stacks {
+            let name = format!("{}.MHK", stack.name());
+            self.filesystem.write(&[&name], &builder.build()?).await?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    // whether there are resources that finish hasn't written out yet
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<F, S> ResourceMap for MhkWriter<F, S>
+where
+    S: Stack,
+{
+    type Handle = Cursor<Vec<u8>>;
+    type Stack = S;
+    type Format = MhkFormat;
+
+    fn format(&self) -> &Self::Format {
+        &self.format
+    }
+
+    async fn open_raw(
+        &mut self,
+        stack: Self::Stack,
+        typ: &str,
+        id: u16,
+        _ext: &str,
+    ) -> Result<Self::Handle> {
+        self.stacks.get(&stack)
+            .and_then(|b| b.get(typ, id))
+            .map(|data| Cursor::new(data.to_owned()))
+            .ok_or_else(|| MhkError::ResourceNotFound(
+                Some(stack.name().to_owned()),
+                typ.to_owned(),
+                id,
+            ).into())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<F, S> ResourceMapList for MhkWriter<F, S>
+where
+    S: Stack,
+{
+    async fn list(&mut self, stack: Self::Stack, typ: &str)
+                  -> Result<Vec<u16>>
+    {
+        let mut ret = self.stacks.get(&stack)
+            .map(|b| b.ids(typ))
+            .unwrap_or_default();
+        ret.sort();
+        Ok(ret)
+    }
+
+    async fn types(&mut self, stack: Self::Stack) -> Result<Vec<String>> {
+        Ok(self.stacks.get(&stack)
+           .map(|b| b.types())
+           .unwrap_or_default())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<F, S> ResourceMapWrite for MhkWriter<F, S>
+where
+    F: FilesystemWrite,
+    S: Stack,
+{
+    async fn write_raw(
+        &mut self,
+        stack: Self::Stack,
+        typ: &str,
+        id: u16,
+        _ext: &str,
+        data: &[u8],
+    ) -> Result<()> {
+        self.stacks.entry(stack).or_default().set(typ, id, data);
+        self.dirty = true;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.finish().await
+    }
+}
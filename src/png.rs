@@ -1,8 +1,13 @@
-use crate::{Bitmap, Format, FormatWrite};
+use crate::bitmap::{bitmap_size, unpack_row};
+use crate::{Bitmap, Cursor, Format, FormatWrite, PaletteBitmap};
 
 use anyhow::Result;
 use smol::io::{AsyncRead, AsyncReadExt};
 
+// bitmaps got tRNS chunks for palette transparency in 2
+const BITMAP_VERSION: u32 = 2;
+const CURSOR_VERSION: u32 = 1;
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct PngFormat;
 
@@ -17,28 +22,77 @@ where
     async fn parse(&self, _res: &R, input: &mut I) -> Result<Bitmap> {
         let mut buf = Vec::with_capacity(1 << 16);
         input.read_to_end(&mut buf).await?;
-        let mut dec = png::Decoder::new(std::io::Cursor::new(buf));
+
+        // look at the header first, indexed images are read as-is
+        let mut dec = png::Decoder::new(std::io::Cursor::new(&buf));
+        dec.set_transformations(png::Transformations::IDENTITY);
+        let (info, reader) = dec.read_info()?;
+        bitmap_size(info.width, info.height)?;
+        if info.color_type == png::ColorType::Indexed {
+            return parse_indexed(info, reader);
+        }
+
+        let mut dec = png::Decoder::new(std::io::Cursor::new(&buf));
         dec.set_transformations(
             png::Transformations::EXPAND
+                | png::Transformations::STRIP_16
                 | png::Transformations::STRIP_ALPHA,
         );
         let (info, mut reader) = dec.read_info()?;
-        let framesize = (info.width * info.height * 3) as usize;
-        let mut data = Vec::with_capacity(framesize);
-        unsafe {
-            data.set_len(framesize);
-            reader.next_frame(&mut data)?;
-        }
+        let mut data = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut data)?;
+        // alpha from tRNS chunks can survive STRIP_ALPHA
+        let data = match reader.output_color_type().0 {
+            png::ColorType::RGB => data,
+            png::ColorType::RGBA => data.chunks(4)
+                .flat_map(|c| c[..3].to_owned()).collect(),
+            png::ColorType::Grayscale => data.iter()
+                .flat_map(|&v| vec![v, v, v]).collect(),
+            png::ColorType::GrayscaleAlpha => data.chunks(2)
+                .flat_map(|c| vec![c[0], c[0], c[0]]).collect(),
+            c => anyhow::bail!("unexpected png color type {:?}", c),
+        };
+        let size = info.width as usize * info.height as usize;
 
         Ok(Bitmap {
             width: info.width as u16,
             height: info.height as u16,
             palette: None,
-            data: palette::Pixel::from_raw_slice(&data).to_owned(),
+            data: palette::Pixel::from_raw_slice(&data[..size * 3])
+                .to_owned(),
         })
     }
 }
 
+fn parse_indexed<T>(info: png::OutputInfo, mut reader: png::Reader<T>)
+                    -> Result<Bitmap>
+where
+    T: std::io::Read,
+{
+    let mut data = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut data)?;
+    let raw = reader.info().palette.clone().unwrap_or_default();
+    let palette: Vec<palette::Srgb<u8>> =
+        palette::Pixel::from_raw_slice(&raw[..raw.len() / 3 * 3])
+        .to_owned();
+    let transparency = reader.info().trns.clone().unwrap_or_default();
+
+    // rows are packed when there are fewer than 8 bits per pixel
+    let bits = info.bit_depth as usize;
+    let width = info.width as usize;
+    let mut image = Vec::with_capacity(width * info.height as usize);
+    for row in data.chunks(info.line_size).take(info.height as usize) {
+        image.extend(unpack_row(row, width, bits));
+    }
+
+    let (width, height) = bitmap_size(info.width, info.height)?;
+    Bitmap::from_palette(width, height, PaletteBitmap {
+        palette,
+        image,
+        transparency,
+    })
+}
+
 #[async_trait::async_trait(?Send)]
 impl<Fi, R, I> FormatWrite<Fi, R, I, Bitmap> for PngFormat
 where
@@ -63,6 +117,9 @@ where
                 enc.set_color(png::ColorType::Indexed);
                 enc.set_palette(palette::Pixel::into_raw_slice(&pal.palette)
                                 .to_owned());
+                if !pal.transparency.is_empty() {
+                    enc.set_trns(pal.transparency);
+                }
                 let mut writer = enc.write_header()?;
                 writer.write_image_data(&pal.image[..])?;
             } else {
@@ -75,4 +132,119 @@ where
         }
         Ok(buf.into_inner())
     }
+    fn version(&self, _res: &R) -> u32 {
+        BITMAP_VERSION
+    }
+}
+
+// RGBA png with the hotspot kept in a tEXt chunk as "hotspot" -> "x,y"
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct PngCursorFormat;
+
+#[async_trait::async_trait(?Send)]
+impl<R, I> Format<R, I, Cursor> for PngCursorFormat
+where
+    I: AsyncRead + Unpin,
+{
+    fn extension(&self, _res: &R) -> Option<&str> {
+        Some(".png")
+    }
+    async fn parse(&self, _res: &R, input: &mut I) -> Result<Cursor> {
+        let mut buf = Vec::with_capacity(1 << 10);
+        input.read_to_end(&mut buf).await?;
+        let hotspot = text_chunk(&buf, "hotspot")
+            .ok_or_else(|| anyhow::anyhow!("png cursor has no hotspot"))?;
+        let hotspot = match hotspot.split_once(',') {
+            Some((x, y)) => (x.trim().parse()?, y.trim().parse()?),
+            None => anyhow::bail!("bad png cursor hotspot {:?}", hotspot),
+        };
+
+        let mut dec = png::Decoder::new(std::io::Cursor::new(&buf));
+        dec.set_transformations(
+            png::Transformations::EXPAND | png::Transformations::STRIP_16,
+        );
+        let (info, mut reader) = dec.read_info()?;
+        let (width, height) = bitmap_size(info.width, info.height)?;
+        if hotspot.0 >= width || hotspot.1 >= height {
+            anyhow::bail!("png cursor hotspot {:?} is outside the image",
+                          hotspot);
+        }
+        let mut data = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut data)?;
+        let data: Vec<u8> = match reader.output_color_type().0 {
+            png::ColorType::RGBA => data,
+            png::ColorType::RGB => data.chunks(3)
+                .flat_map(|c| vec![c[0], c[1], c[2], 255]).collect(),
+            png::ColorType::Grayscale => data.iter()
+                .flat_map(|&v| vec![v, v, v, 255]).collect(),
+            png::ColorType::GrayscaleAlpha => data.chunks(2)
+                .flat_map(|c| vec![c[0], c[0], c[0], c[1]]).collect(),
+            c => anyhow::bail!("unexpected png color type {:?}", c),
+        };
+        let size = width as usize * height as usize;
+
+        Ok(Cursor {
+            width,
+            height,
+            hotspot,
+            data: palette::Pixel::from_raw_slice(&data[..size * 4])
+                .to_owned(),
+        })
+    }
+}
+
+// the png crate doesn't hand text chunks back, so look for them directly
+fn text_chunk(buf: &[u8], keyword: &str) -> Option<String> {
+    let mut pos = 8;
+    while let Some(header) = buf.get(pos..pos + 8) {
+        let size = u32::from_be_bytes([header[0], header[1], header[2],
+                                       header[3]]) as usize;
+        let body = buf.get(pos + 8..pos + 8 + size)?;
+        if &header[4..] == b"tEXt" {
+            let mut parts = body.splitn(2, |&b| b == 0);
+            if parts.next() == Some(keyword.as_bytes()) {
+                // tEXt is latin-1
+                return parts.next()
+                    .map(|t| t.iter().map(|&c| c as char).collect());
+            }
+        }
+        // skip the crc too
+        pos += 12 + size;
+    }
+    None
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, R, I> FormatWrite<Fi, R, I, Cursor> for PngCursorFormat
+where
+    Fi: Format<R, I, Cursor>,
+    I: AsyncRead + Unpin,
+{
+    async fn convert(&self, fmti: &Fi, res: &R, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        let cur = fmti.parse(res, input).await?;
+        let mut buf = std::io::Cursor::new(Vec::with_capacity(
+            cur.width as usize * cur.height as usize * 4,
+        ));
+        {
+            let mut enc = png::Encoder::new(
+                &mut buf,
+                cur.width as u32,
+                cur.height as u32,
+            );
+            enc.set_color(png::ColorType::RGBA);
+            enc.set_depth(png::BitDepth::Eight);
+            let mut writer = enc.write_header()?;
+            let text = format!("hotspot\0{},{}", cur.hotspot.0, cur.hotspot.1);
+            writer.write_chunk(*b"tEXt", text.as_bytes())?;
+            writer.write_image_data(palette::Pixel::into_raw_slice(
+                &cur.data,
+            ))?;
+        }
+        Ok(buf.into_inner())
+    }
+    fn version(&self, _res: &R) -> u32 {
+        CURSOR_VERSION
+    }
 }
@@ -0,0 +1,119 @@
+use crate::bitmap::bitmap_size;
+use crate::{Bitmap, Format, FormatWrite};
+
+use anyhow::Result;
+use smol::io::{AsyncRead, AsyncReadExt};
+
+const VERSION: u32 = 1;
+
+// binary netpbm, P6 (color) or P5 (gray). there is no palette, so paletted
+// bitmaps are written as plain color
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct PpmFormat;
+
+#[async_trait::async_trait(?Send)]
+impl<R, I> Format<R, I, Bitmap> for PpmFormat
+where
+    I: AsyncRead + Unpin,
+{
+    fn extension(&self, _res: &R) -> Option<&str> {
+        Some(".ppm")
+    }
+    async fn parse(&self, _res: &R, input: &mut I) -> Result<Bitmap> {
+        let mut buf = Vec::with_capacity(1 << 16);
+        input.read_to_end(&mut buf).await?;
+        let channels = match buf.get(..2) {
+            Some(b"P6") => 3,
+            Some(b"P5") => 1,
+            _ => anyhow::bail!("not a binary ppm or pgm file"),
+        };
+
+        let mut pos = 2;
+        let mut fields = [0u32; 3];
+        for field in fields.iter_mut() {
+            *field = header_field(&buf, &mut pos)?;
+        }
+        let [width, height, maxval] = fields;
+        if maxval == 0 || maxval > 65535 {
+            anyhow::bail!("bad ppm maxval {}", maxval);
+        }
+        let (width, height) = bitmap_size(width, height)?;
+        // exactly one whitespace byte before the samples
+        pos += 1;
+
+        let wide = maxval > 255;
+        let count = width as usize * height as usize * channels;
+        let size = if wide { count * 2 } else { count };
+        let raw = buf.get(pos..pos + size)
+            .ok_or_else(|| anyhow::anyhow!("unexpected end of image"))?;
+        let samples: Vec<u8> = if wide {
+            raw.chunks(2)
+                .map(|c| scale(u16::from_be_bytes([c[0], c[1]]), maxval))
+                .collect()
+        } else if maxval != 255 {
+            raw.iter().map(|&v| scale(v as u16, maxval)).collect()
+        } else {
+            raw.to_owned()
+        };
+
+        let data = samples.chunks(channels)
+            .map(|c| match c {
+                [r, g, b] => palette::Srgb::new(*r, *g, *b),
+                _ => palette::Srgb::new(c[0], c[0], c[0]),
+            })
+            .collect();
+        Ok(Bitmap {
+            width,
+            height,
+            palette: None,
+            data,
+        })
+    }
+}
+
+fn header_field(buf: &[u8], pos: &mut usize) -> Result<u32> {
+    // skip whitespace and comments, which run to the end of the line
+    loop {
+        match buf.get(*pos) {
+            Some(b'#') => {
+                while !matches!(buf.get(*pos), Some(b'\n') | None) {
+                    *pos += 1;
+                }
+            }
+            Some(c) if c.is_ascii_whitespace() => *pos += 1,
+            _ => break,
+        }
+    }
+    let start = *pos;
+    while buf.get(*pos).is_some_and(u8::is_ascii_digit) {
+        *pos += 1;
+    }
+    std::str::from_utf8(&buf[start..*pos])?
+        .parse()
+        .map_err(|_| anyhow::anyhow!("bad ppm header"))
+}
+
+fn scale(v: u16, maxval: u32) -> u8 {
+    let v = (v as u32).min(maxval);
+    ((v * 255 + maxval / 2) / maxval) as u8
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, R, I> FormatWrite<Fi, R, I, Bitmap> for PpmFormat
+where
+    Fi: Format<R, I, Bitmap>,
+    I: AsyncRead + Unpin,
+{
+    async fn convert(&self, fmti: &Fi, res: &R, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        let bmp = fmti.parse(res, input).await?;
+        let mut buf = format!("P6\n{} {}\n255\n", bmp.width, bmp.height)
+            .into_bytes();
+        buf.extend(palette::Pixel::into_raw_slice(&bmp.data));
+        Ok(buf)
+    }
+    fn version(&self, _res: &R) -> u32 {
+        VERSION
+    }
+}
@@ -0,0 +1,461 @@
+use crate::{
+    Bitmap, CacheSize, Change, Compare, Cursor, Difference, Format,
+    FormatWrite, Mismatch, Movie, Raw, RawResource, Record, ResourceMap,
+    ResourceMapList, ResourceMapWrite, ResourceType, Resources, Sound, Stack,
+};
+use crate::format::writer_id;
+
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use anyhow::Result;
+
+// any resource, for when the type is only known at runtime
+#[derive(Debug, Clone)]
+pub enum AnyResource {
+    Bitmap(Bitmap),
+    Cursor(Cursor),
+    Record(serde_json::Value),
+    Sound(Sound),
+    Movie(Movie),
+    Raw(Raw),
+}
+
+// the kinds of data an AnyResource can hold
+pub trait IntoAnyResource {
+    fn into_any(data: Rc<Self>) -> Result<AnyResource>;
+}
+
+impl<T: serde::Serialize> IntoAnyResource for Record<T> {
+    fn into_any(data: Rc<Self>) -> Result<AnyResource> {
+        Ok(AnyResource::Record(serde_json::to_value(&data.0)?))
+    }
+}
+
+impl IntoAnyResource for Bitmap {
+    fn into_any(data: Rc<Self>) -> Result<AnyResource> {
+        Ok(AnyResource::Bitmap(Rc::unwrap_or_clone(data)))
+    }
+}
+
+impl IntoAnyResource for Cursor {
+    fn into_any(data: Rc<Self>) -> Result<AnyResource> {
+        Ok(AnyResource::Cursor(Rc::unwrap_or_clone(data)))
+    }
+}
+
+impl IntoAnyResource for Sound {
+    fn into_any(data: Rc<Self>) -> Result<AnyResource> {
+        Ok(AnyResource::Sound(Rc::unwrap_or_clone(data)))
+    }
+}
+
+impl IntoAnyResource for Movie {
+    fn into_any(data: Rc<Self>) -> Result<AnyResource> {
+        Ok(AnyResource::Movie(Rc::unwrap_or_clone(data)))
+    }
+}
+
+impl IntoAnyResource for Raw {
+    fn into_any(data: Rc<Self>) -> Result<AnyResource> {
+        Ok(AnyResource::Raw(Rc::unwrap_or_clone(data)))
+    }
+}
+
+type Opener<M> = Box<
+    dyn for<'a> Fn(
+        &'a mut Resources<M>,
+        <M as ResourceMap>::Stack,
+        u16,
+    ) -> Pin<Box<dyn Future<Output = Result<AnyResource>> + 'a>>,
+>;
+
+// opens resources by their type tag, like "CARD"
+pub struct Registry<M: ResourceMap> {
+    openers: HashMap<String, Opener<M>>,
+}
+
+impl<M> Default for Registry<M>
+where
+    M: ResourceMap,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M> Registry<M>
+where
+    M: ResourceMap,
+{
+    pub fn new() -> Self {
+        Registry {
+            openers: HashMap::new(),
+        }
+    }
+
+    pub fn add<R>(&mut self, res: R)
+    where
+        R: ResourceType + 'static,
+        R::Data: IntoAnyResource + CacheSize + 'static,
+        M::Format: Format<R, M::Handle, R::Data>,
+        M::Stack: Clone,
+    {
+        self.openers.insert(res.name().to_owned(), Box::new(
+            move |rs, stack, id| Box::pin(async move {
+                IntoAnyResource::into_any(rs.open(stack, res, id).await?)
+            })
+        ));
+    }
+
+    pub fn types(&self) -> Vec<&str> {
+        let mut types: Vec<&str> =
+            self.openers.keys().map(|k| k.as_str()).collect();
+        types.sort();
+        types
+    }
+
+    pub fn contains(&self, typ: &str) -> bool {
+        self.openers.contains_key(typ)
+    }
+
+    pub async fn open(
+        &self,
+        resources: &mut Resources<M>,
+        stack: M::Stack,
+        typ: &str,
+        id: u16,
+    ) -> Result<AnyResource> {
+        match self.openers.get(typ) {
+            Some(opener) => opener(resources, stack, id).await,
+            None => anyhow::bail!("unknown resource type: {}", typ),
+        }
+    }
+
+    pub async fn list(
+        &self,
+        resources: &mut Resources<M>,
+        stack: M::Stack,
+        typ: &str,
+    ) -> Result<Vec<u16>>
+    where
+        M: ResourceMapList,
+    {
+        if !self.contains(typ) {
+            anyhow::bail!("unknown resource type: {}", typ);
+        }
+        resources.list(stack, typ).await
+    }
+
+    // everything added, removed or changed going from resources to other,
+    // by stack then type. resources that fail to open count as changed
+    pub async fn diff<B, P>(
+        &self,
+        resources: &mut Resources<M>,
+        other_registry: &Registry<B>,
+        other: &mut Resources<B>,
+        mut progress: P,
+    ) -> Result<Vec<Difference<M::Stack>>>
+    where
+        M: ResourceMapList,
+        M::Stack: Clone,
+        B: ResourceMapList<Stack = M::Stack>,
+        P: FnMut(usize, usize),
+    {
+        let mut jobs = vec![];
+        for stack in M::Stack::all() {
+            for typ in self.types() {
+                if !other_registry.contains(typ) {
+                    anyhow::bail!("unknown resource type: {}", typ);
+                }
+                let ours = resources.list(stack.clone(), typ).await?;
+                let theirs = other.list(stack.clone(), typ).await?;
+                let ours: BTreeSet<u16> = ours.into_iter().collect();
+                let theirs: BTreeSet<u16> = theirs.into_iter().collect();
+                for id in ours.union(&theirs) {
+                    jobs.push((stack.clone(), typ, *id, ours.contains(id),
+                               theirs.contains(id)));
+                }
+            }
+        }
+
+        let jobs_len = jobs.len();
+        let mut differences = vec![];
+        for (i, (stack, typ, id, in_ours, in_theirs)) in
+            jobs.into_iter().enumerate()
+        {
+            let change = match (in_ours, in_theirs) {
+                (true, false) => Some(Change::Removed),
+                (false, true) => Some(Change::Added),
+                _ => {
+                    let a = self.open(resources, stack.clone(), typ, id)
+                        .await;
+                    let b = other_registry.open(other, stack.clone(), typ, id)
+                        .await;
+                    let changes = match (a, b) {
+                        (Ok(a), Ok(b)) => a.diff(&b),
+                        (a, b) => a.err().into_iter()
+                            .map(|e| format!("old failed to open: {:#}", e))
+                            .chain(b.err().into_iter().map(
+                                |e| format!("new failed to open: {:#}", e)))
+                            .collect(),
+                    };
+                    if changes.is_empty() {
+                        None
+                    } else {
+                        Some(Change::Changed(changes))
+                    }
+                }
+            };
+            if let Some(change) = change {
+                differences.push(Difference {
+                    stack,
+                    typ: typ.to_owned(),
+                    id,
+                    change,
+                });
+            }
+            progress(i + 1, jobs_len);
+        }
+        Ok(differences)
+    }
+}
+
+// conversions run on a thread pool, so they get the resource's bytes
+// rather than a handle into the map
+type Bytes = smol::io::Cursor<Vec<u8>>;
+
+type Converter<M, Mw> = Arc<
+    dyn Fn(
+        &<M as ResourceMap>::Format,
+        &<Mw as ResourceMap>::Format,
+        Bytes,
+    ) -> Result<Vec<u8>> + Send + Sync,
+>;
+
+type Extensions<M, Mw> = Box<
+    dyn Fn(
+        &<M as ResourceMap>::Format,
+        &<Mw as ResourceMap>::Format,
+    ) -> (String, String),
+>;
+
+type Writer<Mw> = Box<dyn Fn(&<Mw as ResourceMap>::Format) -> String>;
+
+pub(crate) struct WriteEntry<M: ResourceMap, Mw: ResourceMap> {
+    pub(crate) name: String,
+    // extensions for the input and output maps
+    pub(crate) extensions: Extensions<M, Mw>,
+    // which converter, and which version of it, writes the output
+    pub(crate) writer: Writer<Mw>,
+    pub(crate) convert: Converter<M, Mw>,
+}
+
+type Fallback<M, Mw> = Box<dyn Fn(&str) -> WriteEntry<M, Mw>>;
+
+// every resource type a game has, and how to copy each from one map
+// to another, for bulk exports
+pub struct WriteRegistry<M: ResourceMap, Mw: ResourceMap> {
+    pub(crate) entries: Vec<WriteEntry<M, Mw>>,
+    // for types found in the map that no entry is for
+    pub(crate) fallback: Option<Fallback<M, Mw>>,
+}
+
+impl<M, Mw> Default for WriteRegistry<M, Mw>
+where
+    M: ResourceMap,
+    Mw: ResourceMap,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M, Mw> WriteRegistry<M, Mw>
+where
+    M: ResourceMap,
+    Mw: ResourceMap,
+{
+    pub fn new() -> Self {
+        WriteRegistry {
+            entries: Vec::new(),
+            fallback: None,
+        }
+    }
+
+    pub fn add<R>(&mut self, res: R)
+    where
+        R: ResourceType + Send + Sync + 'static,
+        Mw::Format: FormatWrite<M::Format, R, Bytes, R::Data>,
+        M::Format: Format<R, Bytes, R::Data>,
+    {
+        self.entries.push(Self::entry(res));
+    }
+
+    // copy any type not added here through untouched, as RawResource
+    pub fn add_raw_fallback(&mut self)
+    where
+        Mw::Format: FormatWrite<M::Format, RawResource, Bytes, Raw>,
+        M::Format: Format<RawResource, Bytes, Raw>,
+    {
+        self.fallback = Some(Box::new(
+            |typ| Self::entry(RawResource::new(typ))));
+    }
+
+    fn entry<R>(res: R) -> WriteEntry<M, Mw>
+    where
+        R: ResourceType + Send + Sync + 'static,
+        Mw::Format: FormatWrite<M::Format, R, Bytes, R::Data>,
+        M::Format: Format<R, Bytes, R::Data>,
+    {
+        WriteEntry {
+            name: res.name().to_owned(),
+            extensions: Box::new(move |fmti, fmto| {
+                type D<R> = <R as ResourceType>::Data;
+                let i = Format::<R, Bytes, D<R>>::extension(fmti, &res);
+                let o = Format::<R, Bytes, D<R>>::extension(fmto, &res);
+                (
+                    i.unwrap_or("").to_owned(),
+                    o.unwrap_or("").to_owned(),
+                )
+            }),
+            writer: Box::new(move |fmto| {
+                type D<R> = <R as ResourceType>::Data;
+                writer_id::<M::Format, R, Bytes, D<R>, _>(fmto, &res)
+            }),
+            convert: Arc::new(move |fmti, fmto, mut input| smol::block_on(
+                fmto.convert(fmti, &res, &mut input)
+            )),
+        }
+    }
+
+    // in the order they were added
+    pub fn types(&self) -> Vec<&str> {
+        self.entries.iter().map(|e| e.name.as_str()).collect()
+    }
+
+    pub fn contains(&self, typ: &str) -> bool {
+        self.entries.iter().any(|e| e.name == typ)
+    }
+
+    pub async fn write(
+        &self,
+        resources: &mut Resources<M>,
+        other: &mut Resources<Mw>,
+        stack: M::Stack,
+        typ: &str,
+        id: u16,
+    ) -> Result<()>
+    where
+        Mw: ResourceMapWrite<Stack = M::Stack>,
+        M::Stack: Clone,
+    {
+        match (self.entries.iter().find(|e| e.name == typ), &self.fallback) {
+            (Some(entry), _) => resources.write_entry_to(other, entry, stack,
+                                                          id).await,
+            (None, Some(fallback)) => resources.write_entry_to(
+                other, &fallback(typ), stack, id).await,
+            (None, None) => anyhow::bail!("unknown resource type: {}", typ),
+        }
+    }
+}
+
+type Verifier<M, F> = Box<
+    dyn for<'a> Fn(
+        &'a mut Resources<M>,
+        &'a F,
+        <M as ResourceMap>::Stack,
+        u16,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<String>>> + 'a>>,
+>;
+
+// every resource type a game has, for checking that a format can hold
+// them all without losing anything
+pub struct VerifyRegistry<M: ResourceMap, F> {
+    verifiers: Vec<(String, Verifier<M, F>)>,
+}
+
+impl<M, F> Default for VerifyRegistry<M, F>
+where
+    M: ResourceMap,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M, F> VerifyRegistry<M, F>
+where
+    M: ResourceMap,
+{
+    pub fn new() -> Self {
+        VerifyRegistry {
+            verifiers: Vec::new(),
+        }
+    }
+
+    pub fn add<R>(&mut self, res: R)
+    where
+        R: ResourceType + 'static,
+        R::Data: Compare,
+        M::Format: Format<R, M::Handle, R::Data>,
+        F: FormatWrite<M::Format, R, M::Handle, R::Data>
+            + Format<R, smol::io::Cursor<Vec<u8>>, R::Data>,
+        M::Stack: Clone,
+    {
+        self.verifiers.push((res.name().to_owned(), Box::new(
+            move |rs, fmt, stack, id| Box::pin(async move {
+                rs.verify(fmt, stack, res, id).await
+            })
+        )));
+    }
+
+    pub fn types(&self) -> Vec<&str> {
+        self.verifiers.iter().map(|(k, _)| k.as_str()).collect()
+    }
+
+    // resources that fail to convert or parse are mismatches too
+    pub async fn verify_all<P>(
+        &self,
+        resources: &mut Resources<M>,
+        fmt: &F,
+        mut progress: P,
+    ) -> Result<Vec<Mismatch<M::Stack>>>
+    where
+        M: ResourceMapList,
+        M::Stack: Clone,
+        P: FnMut(usize, usize),
+    {
+        let mut jobs = vec![];
+        for (typ, verifier) in &self.verifiers {
+            for stack in M::Stack::all() {
+                for id in resources.list(stack.clone(), typ).await? {
+                    jobs.push((typ, verifier, stack.clone(), id));
+                }
+            }
+        }
+
+        let mut mismatches = vec![];
+        for (i, (typ, verifier, stack, id)) in jobs.iter().enumerate() {
+            let problem = match verifier(resources, fmt, stack.clone(), *id)
+                .await
+            {
+                Ok(problem) => problem,
+                Err(e) => Some(format!("{:#}", e)),
+            };
+            if let Some(problem) = problem {
+                mismatches.push(Mismatch {
+                    stack: stack.clone(),
+                    typ: typ.to_string(),
+                    id: *id,
+                    problem,
+                });
+            }
+            progress(i + 1, jobs.len());
+        }
+        Ok(mismatches)
+    }
+}
@@ -1,20 +1,164 @@
 use crate::{
-    Format, FormatWrite,
-    ResourceMap, ResourceMapList, ResourceMapWrite,
-    ResourceType, Stack,
+    Bitmap, Compare, Cursor, Format, FormatWrite, Movie, Raw, Record,
+    Manifest, ResourceMap, ResourceMapList, ResourceMapWrite,
+    ResourceType, Sound, Stack, WriteEntry, WriteRegistry,
 };
+use crate::filesystem::Filesystem;
+use crate::format::writer_id;
+use crate::mhk::MhkMap;
+
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::rc::Rc;
+use std::task::Poll;
+
+use smol::future::poll_fn;
+use smol::io::AsyncReadExt;
 
 use anyhow::Result;
 
-pub struct Resources<M> {
+// roughly how much memory a parsed resource holds on to, for the cache
+pub trait CacheSize {
+    fn cache_size(&self) -> usize;
+}
+
+// records own strings and lists, so go by their encoded size
+impl<T: serde::Serialize> CacheSize for Record<T> {
+    fn cache_size(&self) -> usize {
+        let encoded = bincode::serialized_size(&self.0).unwrap_or(0);
+        std::mem::size_of::<T>() + encoded as usize
+    }
+}
+
+impl CacheSize for Bitmap {
+    fn cache_size(&self) -> usize {
+        let pal = self.palette.as_ref()
+            .map(|p| p.palette.len() * 3 + p.image.len())
+            .unwrap_or(0);
+        std::mem::size_of::<Self>() + self.data.len() * 3 + pal
+    }
+}
+
+impl CacheSize for Cursor {
+    fn cache_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.data.len() * 4
+    }
+}
+
+impl CacheSize for Sound {
+    fn cache_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.0.len()
+    }
+}
+
+impl CacheSize for Movie {
+    fn cache_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.0.len()
+    }
+}
+
+impl CacheSize for Raw {
+    fn cache_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.0.len()
+    }
+}
+
+pub struct Resources<M: ResourceMap> {
     map: M,
+    cache: HashMap<(M::Stack, String, u16), CacheEntry>,
+    budget: Option<usize>,
+    clock: u64,
+    manifest: Option<Manifest>,
+}
+
+struct CacheEntry {
+    data: Box<dyn Any>,
+    size: usize,
+    last_used: u64,
 }
 
 impl<M> Resources<M> where M: ResourceMap {
     pub fn new(map: M) -> Self {
         Resources {
             map,
+            cache: HashMap::new(),
+            budget: None,
+            clock: 0,
+            manifest: None,
+        }
+    }
+
+    // when writing into these resources, skip anything the manifest says
+    // is already there and up to date, and record everything written
+    pub fn set_manifest(&mut self, manifest: Option<Manifest>) {
+        self.manifest = manifest;
+    }
+
+    pub fn manifest(&self) -> Option<&Manifest> {
+        self.manifest.as_ref()
+    }
+
+    // keep parsed resources around, up to about this many bytes
+    // the least recently used are dropped first, and None turns it off
+    pub fn set_cache_budget(&mut self, budget: Option<usize>) {
+        self.budget = budget;
+        if budget.is_none() {
+            self.cache.clear();
         }
+        self.evict();
+    }
+
+    pub fn cache_used(&self) -> usize {
+        self.cache.values().map(|e| e.size).sum()
+    }
+
+    // drop a cached resource, for when the map underneath has changed
+    pub fn invalidate(&mut self, stack: M::Stack, typ: &str, id: u16) {
+        self.cache.remove(&(stack, typ.to_owned(), id));
+    }
+
+    pub fn invalidate_stack(&mut self, stack: &M::Stack) {
+        self.cache.retain(|(s, _, _), _| s != stack);
+    }
+
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+
+    fn evict(&mut self) {
+        let budget = match self.budget {
+            Some(b) => b,
+            None => return,
+        };
+        while self.cache_used() > budget {
+            // every open ticks the clock, so last_used is unique
+            let oldest = match self.cache.values().map(|e| e.last_used).min() {
+                Some(t) => t,
+                None => break,
+            };
+            self.cache.retain(|_, e| e.last_used != oldest);
+        }
+    }
+
+    pub async fn preload(&mut self, stack: M::Stack) -> Result<()> {
+        self.map.preload(stack).await
+    }
+
+    pub async fn list(&mut self, stack: M::Stack, typ: &str)
+                      -> Result<Vec<u16>>
+    where
+        M: ResourceMapList,
+    {
+        self.map.list(stack, typ).await
+    }
+
+    // finish any writes the map is holding on to
+    pub async fn flush(&mut self) -> Result<()>
+    where
+        M: ResourceMapWrite,
+    {
+        self.map.flush().await
     }
 
     pub async fn open_raw<R>(&mut self, stack: M::Stack, typ: R, id: u16)
@@ -29,17 +173,117 @@ impl<M> Resources<M> where M: ResourceMap {
         Ok(handle)
     }
 
+    // cached resources are shared, so this hands out an Rc either way
     pub async fn open<R>(&mut self, stack: M::Stack, typ: R, id: u16)
-                         -> Result<R::Data>
+                         -> Result<Rc<R::Data>>
     where
         R: ResourceType,
+        R::Data: CacheSize + 'static,
         M::Format: Format<R, M::Handle, R::Data>,
+        M::Stack: Clone,
     {
+        if self.budget.is_none() {
+            let mut handle = self.open_raw(stack, typ, id).await?;
+            let res = self.map.format().parse(&typ, &mut handle).await?;
+            return Ok(Rc::new(res));
+        }
+
+        self.clock += 1;
+        let key = (stack.clone(), typ.name().to_owned(), id);
+        if let Some(entry) = self.cache.get_mut(&key) {
+            if let Some(data) = entry.data.downcast_ref::<Rc<R::Data>>() {
+                entry.last_used = self.clock;
+                return Ok(data.clone());
+            }
+        }
+
         let mut handle = self.open_raw(stack, typ, id).await?;
-        let res = self.map.format().parse(&typ, &mut handle).await?;
+        let res = Rc::new(self.map.format().parse(&typ, &mut handle).await?);
+        self.cache.insert(key, CacheEntry {
+            data: Box::new(res.clone()),
+            size: res.cache_size(),
+            last_used: self.clock,
+        });
+        self.evict();
         Ok(res)
     }
 
+    async fn export_fingerprint<Mw>(
+        &mut self,
+        other: &Resources<Mw>,
+        stack: M::Stack,
+        typ: &str,
+        id: u16,
+        outext: &str,
+        writer: &str,
+    ) -> Result<Option<String>>
+    where
+        Mw: ResourceMap,
+    {
+        if other.manifest.is_none() {
+            return Ok(None);
+        }
+        Ok(self.map.fingerprint(stack, typ, id).await?
+           .map(|fp| format!("{} -> {} {}", fp, outext, writer)))
+    }
+
+    // already written from the same source, by the same converter?
+    async fn is_current(
+        &mut self,
+        stack: M::Stack,
+        typ: &str,
+        id: u16,
+        ext: &str,
+        fingerprint: &Option<String>,
+    ) -> bool {
+        let (manifest, fp) = match (&self.manifest, fingerprint) {
+            (Some(m), Some(fp)) => (m, fp),
+            _ => return false,
+        };
+        if manifest.get(&stack, typ, id) != Some(fp.as_str()) {
+            return false;
+        }
+        // the output may have been deleted since
+        self.map.open_raw(stack, typ, id, ext).await.is_ok()
+    }
+
+    fn record(&mut self, stack: &M::Stack, typ: &str, id: u16,
+              fingerprint: Option<String>) {
+        if let Some(manifest) = &mut self.manifest {
+            match fingerprint {
+                Some(fp) => manifest.insert(stack, typ, id, fp),
+                None => manifest.remove(stack, typ, id),
+            }
+        }
+    }
+
+    // convert a resource with fmt, parse it back, and describe anything
+    // that came back different
+    pub async fn verify<R, F>(
+        &mut self,
+        fmt: &F,
+        stack: M::Stack,
+        typ: R,
+        id: u16,
+    ) -> Result<Option<String>>
+    where
+        R: ResourceType,
+        R::Data: Compare,
+        M::Format: Format<R, M::Handle, R::Data>,
+        F: FormatWrite<M::Format, R, M::Handle, R::Data>
+            + Format<R, smol::io::Cursor<Vec<u8>>, R::Data>,
+        M::Stack: Clone,
+    {
+        let mut handle = self.open_raw(stack.clone(), typ, id).await?;
+        let original = self.map.format().parse(&typ, &mut handle).await?;
+        let mut handle = self.open_raw(stack, typ, id).await?;
+        let data = fmt.convert(self.map.format(), &typ, &mut handle).await?;
+        let back = fmt.parse(&typ, &mut smol::io::Cursor::new(data)).await?;
+        Ok(original.compare(&back))
+    }
+
+    // the other map is flushed afterwards, so the resource can be found
+    // there right away
     pub async fn write_resource_to<R, Mw>(
         &mut self,
         other: &mut Resources<Mw>,
@@ -54,13 +298,42 @@ impl<M> Resources<M> where M: ResourceMap {
         M::Format: Format<R, M::Handle, R::Data>,
         M::Stack: Clone,
     {
+        self.write_one_to(other, stack, typ, id).await?;
+        other.flush().await
+    }
+
+    async fn write_one_to<R, Mw>(
+        &mut self,
+        other: &mut Resources<Mw>,
+        stack: M::Stack,
+        typ: R,
+        id: u16
+    ) -> Result<()>
+    where
+        R: ResourceType,
+        Mw: ResourceMapWrite<Stack=M::Stack>,
+        Mw::Format: FormatWrite<M::Format, R, M::Handle, R::Data>,
+        M::Format: Format<R, M::Handle, R::Data>,
+        M::Stack: Clone,
+    {
+        let extension = other.map.format().extension(&typ)
+            .unwrap_or("").to_owned();
+        let writer = writer_id(other.map.format(), &typ);
+        let fp = self.export_fingerprint(
+            other, stack.clone(), typ.name(), id, &extension, &writer).await?;
+        if other.is_current(stack.clone(), typ.name(), id, &extension, &fp)
+            .await
+        {
+            return Ok(());
+        }
+
         let mut handle = self.open_raw(stack.clone(), typ, id).await?;
         let data = other.map.format().convert(
             self.map.format(), &typ, &mut handle).await?;
-        let extension =other.map.format().extension(&typ)
-            .unwrap_or("").to_owned();
         other.map.write_raw(
             stack.clone(), typ.name(), id, &extension, &data).await?;
+        other.invalidate(stack.clone(), typ.name(), id);
+        other.record(&stack, typ.name(), id, fp);
         Ok(())
     }
 
@@ -79,9 +352,9 @@ impl<M> Resources<M> where M: ResourceMap {
         M::Stack: Clone,
     {
         for id in self.map.list(stack.clone(), typ.name()).await? {
-            self.write_resource_to(other, stack.clone(), typ, id).await?;
+            self.write_one_to(other, stack.clone(), typ, id).await?;
         }
-        Ok(())
+        other.flush().await
     }
 
     pub async fn write_to<R, Mw>(
@@ -102,4 +375,268 @@ impl<M> Resources<M> where M: ResourceMap {
         }
         Ok(())
     }
+
+    pub(crate) async fn write_entry_to<Mw>(
+        &mut self,
+        other: &mut Resources<Mw>,
+        entry: &WriteEntry<M, Mw>,
+        stack: M::Stack,
+        id: u16,
+    ) -> Result<()>
+    where
+        Mw: ResourceMapWrite<Stack=M::Stack>,
+        M::Stack: Clone,
+    {
+        let (inext, outext) =
+            (entry.extensions)(self.map.format(), other.map.format());
+        let writer = (entry.writer)(other.map.format());
+        let fp = self.export_fingerprint(
+            other, stack.clone(), &entry.name, id, &outext, &writer).await?;
+        if other.is_current(stack.clone(), &entry.name, id, &outext, &fp)
+            .await
+        {
+            return Ok(());
+        }
+
+        let input = self.read_raw(stack.clone(), &entry.name, id, &inext)
+            .await?;
+        let input = smol::io::Cursor::new(input);
+        let data = (entry.convert)(
+            self.map.format(), other.map.format(), input)?;
+        other.map.write_raw(
+            stack.clone(), &entry.name, id, &outext, &data).await?;
+        other.invalidate(stack.clone(), &entry.name, id);
+        other.record(&stack, &entry.name, id, fp);
+        other.flush().await
+    }
+
+    async fn read_raw(&mut self, stack: M::Stack, typ: &str, id: u16,
+                      ext: &str) -> Result<Vec<u8>>
+    {
+        let mut handle = self.map.open_raw(stack, typ, id, ext).await?;
+        let mut data = vec![];
+        handle.read_to_end(&mut data).await?;
+        Ok(data)
+    }
+
+    // copy every resource of every type the registry knows about, and of
+    // every other type in the map if it has a fallback.
+    // failures are collected in the report instead of stopping the export.
+    // resources are read here, but converted on a thread pool, with up to
+    // `concurrency` conversions running at once
+    pub async fn write_all_to<Mw, P>(
+        &mut self,
+        other: &mut Resources<Mw>,
+        registry: &WriteRegistry<M, Mw>,
+        concurrency: usize,
+        mut progress: P,
+    ) -> Result<ExportReport<M::Stack>>
+    where
+        M: ResourceMapList,
+        Mw: ResourceMapWrite<Stack=M::Stack>,
+        M::Format: Clone + Send + 'static,
+        Mw::Format: Clone + Send + 'static,
+        M::Stack: Clone,
+        P: FnMut(&ExportProgress<M::Stack>),
+    {
+        let mut unknown: Vec<WriteEntry<M, Mw>> = vec![];
+        if let Some(fallback) = &registry.fallback {
+            for stack in M::Stack::all() {
+                for typ in self.map.types(stack).await? {
+                    if !registry.contains(&typ)
+                        && unknown.iter().all(|e| e.name != typ)
+                    {
+                        unknown.push(fallback(&typ));
+                    }
+                }
+            }
+        }
+
+        // list everything first, so progress has a total
+        let mut pending = VecDeque::new();
+        for entry in registry.entries.iter().chain(&unknown) {
+            for stack in M::Stack::all() {
+                for id in self.map.list(stack.clone(), &entry.name).await? {
+                    pending.push_back((entry, stack.clone(), id));
+                }
+            }
+        }
+
+        // conversions get their own copies, to take to another thread
+        let fmti = self.map.format().clone();
+        let fmto = other.map.format().clone();
+        let mut report = ExportReport {
+            total: pending.len(),
+            written: 0,
+            skipped: 0,
+            errors: vec![],
+        };
+        let mut running = Vec::with_capacity(concurrency);
+        loop {
+            while running.len() < concurrency.max(1) {
+                let (entry, stack, id) = match pending.pop_front() {
+                    Some(job) => job,
+                    None => break,
+                };
+                let (inext, outext) = (entry.extensions)(&fmti, &fmto);
+                let writer = (entry.writer)(&fmto);
+                let fp = match self.export_fingerprint(
+                    other, stack.clone(), &entry.name, id, &outext, &writer,
+                ).await {
+                    Ok(fp) => fp,
+                    Err(e) => {
+                        report.finish(&mut progress, stack, &entry.name, id,
+                                      Err(e));
+                        continue;
+                    },
+                };
+                if other.is_current(stack.clone(), &entry.name, id, &outext,
+                                    &fp).await
+                {
+                    report.finish(&mut progress, stack, &entry.name, id,
+                                  Ok(ExportStatus::Skipped));
+                    continue;
+                }
+
+                let read = self.read_raw(
+                    stack.clone(), &entry.name, id, &inext).await;
+                match read {
+                    Ok(input) => {
+                        let convert = entry.convert.clone();
+                        let (fi, fo) = (fmti.clone(), fmto.clone());
+                        let task = Box::pin(smol::unblock(move || {
+                            convert(&fi, &fo, smol::io::Cursor::new(input))
+                        }));
+                        running.push(((entry, stack, id, outext, fp), task));
+                    },
+                    Err(e) => {
+                        report.finish(&mut progress, stack, &entry.name, id,
+                                      Err(e));
+                    },
+                }
+            }
+            if running.is_empty() {
+                break;
+            }
+
+            // wait for whichever conversion finishes first
+            let ((entry, stack, id, outext, fp), converted) = poll_fn(|cx| {
+                for i in 0..running.len() {
+                    if let Poll::Ready(r) = running[i].1.as_mut().poll(cx) {
+                        return Poll::Ready((running.swap_remove(i).0, r));
+                    }
+                }
+                Poll::Pending
+            }).await;
+
+            let result = match converted {
+                Ok(data) => other.map.write_raw(
+                    stack.clone(), &entry.name, id, &outext, &data).await,
+                Err(e) => Err(e),
+            };
+            other.invalidate(stack.clone(), &entry.name, id);
+            let result = match result {
+                Ok(()) => {
+                    other.record(&stack, &entry.name, id, fp);
+                    Ok(ExportStatus::Written)
+                },
+                Err(e) => {
+                    other.record(&stack, &entry.name, id, None);
+                    Err(e)
+                },
+            };
+            report.finish(&mut progress, stack, &entry.name, id, result);
+        }
+        Ok(report)
+    }
+}
+
+impl<F, S> Resources<MhkMap<F, S>>
+where
+    F: Filesystem,
+    S: Stack + Copy,
+{
+    // patches change where resources in the stack come from, so anything
+    // cached from it is stale
+    pub fn add_patch_file(&mut self, stack: S, name: &str) {
+        self.map.add_patch_file(stack, name);
+        self.invalidate_stack(&stack);
+    }
+}
+
+#[derive(Debug)]
+pub struct ExportError<S> {
+    pub stack: S,
+    pub typ: String,
+    pub id: u16,
+    pub error: anyhow::Error,
+}
+
+#[derive(Debug)]
+pub struct ExportReport<S> {
+    pub total: usize,
+    pub written: usize,
+    // already up to date, according to the manifest
+    pub skipped: usize,
+    pub errors: Vec<ExportError<S>>,
+}
+
+impl<S> ExportReport<S> {
+    fn finish<P>(
+        &mut self,
+        progress: &mut P,
+        stack: S,
+        typ: &str,
+        id: u16,
+        result: Result<ExportStatus>,
+    )
+    where
+        P: FnMut(&ExportProgress<S>),
+    {
+        let status = match &result {
+            Ok(status) => *status,
+            Err(_) => ExportStatus::Failed,
+        };
+        match status {
+            ExportStatus::Written => self.written += 1,
+            ExportStatus::Skipped => self.skipped += 1,
+            ExportStatus::Failed => {},
+        }
+        // this one isn't in errors yet
+        let failed = result.is_err() as usize;
+        progress(&ExportProgress {
+            done: self.written + self.skipped + self.errors.len() + failed,
+            total: self.total,
+            stack: &stack,
+            typ,
+            id,
+            status,
+        });
+        if let Err(error) = result {
+            self.errors.push(ExportError {
+                stack,
+                typ: typ.to_owned(),
+                id,
+                error,
+            });
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExportStatus {
+    Written,
+    Skipped,
+    Failed,
+}
+
+// sent after each resource is written, skipped, or fails
+#[derive(Debug)]
+pub struct ExportProgress<'a, S> {
+    pub done: usize,
+    pub total: usize,
+    pub stack: &'a S,
+    pub typ: &'a str,
+    pub id: u16,
+    pub status: ExportStatus,
 }
@@ -0,0 +1,590 @@
+// a plain text form of scripts, one command per line
+//
+//   on load-card
+//       activate-plst 1
+//       switch acathstate
+//           case 0
+//               goto-card 12
+//       end
+//
+// indentation is only for reading, blocks are closed by keywords, and
+// everything after a # is a comment. commands are written as their
+// kebab-case name followed by their fields in binary order, except:
+//
+//   activate-inline-slst FADE LOOPING VOLUME U0 U1 (ID VOLUME BALANCE U2)...
+//   call EXTERNAL ARGS...
+//   transition (left|right|top|bottom) [new-move] [old-move] [L T R B]
+//   transition blend [L T R B]
+//   transition CODE [L T R B]
+//   unknown CMD ARGS...
+//
+// variables, externals and stacks are written by name when the NAME
+// tables know them, and either a name or a number is accepted back.
+
+use super::{
+    Command, Event, InlineSlst, Name, TName, TransitionCode,
+    TransitionDirection,
+};
+use crate::{is_not_found, Format, Record, ResourceMap, Resources};
+
+use anyhow::Result;
+use indexmap::IndexMap;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScriptNames {
+    // NAME 3
+    pub externals: Vec<String>,
+    // NAME 4
+    pub variables: Vec<String>,
+    // NAME 5
+    pub stacks: Vec<String>,
+}
+
+impl ScriptNames {
+    // missing tables are left empty, and their symbols show up as numbers
+    pub async fn load<M>(resources: &mut Resources<M>, stack: M::Stack)
+                         -> Result<Self>
+    where
+        M: ResourceMap,
+        M::Format: Format<TName, M::Handle, Record<Vec<Name>>>,
+        M::Stack: Clone,
+    {
+        let mut tables = Vec::with_capacity(3);
+        for id in 3..=5 {
+            let names = match resources.open(stack.clone(), TName, id).await {
+                Ok(r) => r.iter().map(|n| n.name.clone()).collect(),
+                Err(e) if is_not_found(&e) => vec![],
+                Err(e) => return Err(e),
+            };
+            tables.push(names);
+        }
+        let stacks = tables.pop().unwrap();
+        let variables = tables.pop().unwrap();
+        let externals = tables.pop().unwrap();
+        Ok(ScriptNames { externals, variables, stacks })
+    }
+}
+
+fn event_name(event: &Event) -> &'static str {
+    match event {
+        Event::MouseDown => "mouse-down",
+        Event::MouseStillDown => "mouse-still-down",
+        Event::MouseUp => "mouse-up",
+        Event::MouseEnter => "mouse-enter",
+        Event::MouseWithin => "mouse-within",
+        Event::MouseLeave => "mouse-leave",
+        Event::LoadCard => "load-card",
+        Event::CloseCard => "close-card",
+        Event::OpenCard => "open-card",
+        Event::DisplayUpdate => "display-update",
+    }
+}
+
+// names are only used if they read back as the same index
+fn symbol(table: &[String], index: u16) -> String {
+    if let Some(name) = table.get(index as usize) {
+        let plain = name.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        let first = table.iter().position(|n| n == name);
+        if plain && first == Some(index as usize)
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && !matches!(name.as_str(), "on" | "switch" | "case" | "end")
+        {
+            return name.clone();
+        }
+    }
+    index.to_string()
+}
+
+fn line(out: &mut String, depth: usize, words: &[String]) {
+    for _ in 0..depth {
+        out.push_str("    ");
+    }
+    out.push_str(&words.join(" "));
+    out.push('\n');
+}
+
+pub fn disassemble(
+    handlers: &IndexMap<Event, Vec<Command>>,
+    names: &ScriptNames,
+) -> String {
+    let mut out = String::new();
+    for (event, commands) in handlers {
+        line(&mut out, 0, &["on".to_owned(), event_name(event).to_owned()]);
+        disassemble_into(&mut out, commands, names, 1);
+    }
+    out
+}
+
+pub fn disassemble_commands(commands: &[Command], names: &ScriptNames)
+                            -> String
+{
+    let mut out = String::new();
+    disassemble_into(&mut out, commands, names, 0);
+    out
+}
+
+fn disassemble_into(
+    out: &mut String,
+    commands: &[Command],
+    names: &ScriptNames,
+    depth: usize,
+) {
+    use Command::*;
+
+    fn words(name: &str, args: &[u16]) -> Vec<String> {
+        let mut words = vec![name.to_owned()];
+        words.extend(args.iter().map(|a| a.to_string()));
+        words
+    }
+
+    for command in commands {
+        let w = match command {
+            DrawBmp { tbmp_id, left, top, right, bottom, u0, u1, u2, u3 } => {
+                words("draw-bmp", &[*tbmp_id, *left, *top, *right, *bottom,
+                                    *u0, *u1, *u2, *u3])
+            },
+            GotoCard { id } => words("goto-card", &[*id]),
+            ActivateInlineSlst {
+                sounds, fade_flags, looping, volume, u0, u1,
+            } => {
+                let mut w = words("activate-inline-slst", &[
+                    *fade_flags, *looping, *volume, *u0, *u1,
+                ]);
+                for s in sounds {
+                    w.push(format!("({} {} {} {})",
+                                   s.id, s.volume, s.balance, s.u2));
+                }
+                w
+            },
+            PlayWav { id, volume, u1 } => {
+                words("play-wav", &[*id, *volume, *u1])
+            },
+            SetVariable { var, value } => vec![
+                "set-variable".to_owned(),
+                symbol(&names.variables, *var),
+                value.to_string(),
+            ],
+            Conditional { var, branches } => {
+                line(out, depth, &[
+                    "switch".to_owned(),
+                    symbol(&names.variables, *var),
+                ]);
+                for (value, commands) in branches {
+                    line(out, depth + 1, &words("case", &[*value]));
+                    disassemble_into(out, commands, names, depth + 2);
+                }
+                line(out, depth, &["end".to_owned()]);
+                continue;
+            },
+            EnableHotspot { hotspot_id } => {
+                words("enable-hotspot", &[*hotspot_id])
+            },
+            DisableHotspot { hotspot_id } => {
+                words("disable-hotspot", &[*hotspot_id])
+            },
+            StopSound { flags } => words("stop-sound", &[*flags]),
+            SetCursor { cursor } => words("set-cursor", &[*cursor]),
+            Pause { ms, u0 } => words("pause", &[*ms, *u0]),
+            Call { cmd, args } => {
+                let mut w = vec![
+                    "call".to_owned(),
+                    symbol(&names.externals, *cmd),
+                ];
+                w.extend(args.iter().map(|a| a.to_string()));
+                w
+            },
+            Transition { code, rect } => {
+                let mut w = vec!["transition".to_owned()];
+                match code {
+                    TransitionCode::Direction {
+                        direction, new_move, old_move,
+                    } => {
+                        w.push(match direction {
+                            TransitionDirection::Left => "left",
+                            TransitionDirection::Right => "right",
+                            TransitionDirection::Top => "top",
+                            TransitionDirection::Bottom => "bottom",
+                        }.to_owned());
+                        if *new_move {
+                            w.push("new-move".to_owned());
+                        }
+                        if *old_move {
+                            w.push("old-move".to_owned());
+                        }
+                    },
+                    TransitionCode::Blend => w.push("blend".to_owned()),
+                    TransitionCode::Unknown { code } => {
+                        w.push(code.to_string())
+                    },
+                }
+                if let Some((left, top, right, bottom)) = rect {
+                    w.extend([left, top, right, bottom].iter()
+                             .map(|a| a.to_string()));
+                }
+                w
+            },
+            ReloadCard => words("reload-card", &[]),
+            DisableScreenUpdate => words("disable-screen-update", &[]),
+            EnableScreenUpdate => words("enable-screen-update", &[]),
+            IncrementVariable { var, value } => vec![
+                "increment-variable".to_owned(),
+                symbol(&names.variables, *var),
+                value.to_string(),
+            ],
+            GotoStack { stack_name, code } => vec![
+                "goto-stack".to_owned(),
+                symbol(&names.stacks, *stack_name),
+                code.to_string(),
+            ],
+            DisableMovie { code } => words("disable-movie", &[*code]),
+            DisableAllMovies => words("disable-all-movies", &[]),
+            EnableMovie { code } => words("enable-movie", &[*code]),
+            PlayForegroundMovie { code } => {
+                words("play-foreground-movie", &[*code])
+            },
+            PlayBackgroundMovie { code } => {
+                words("play-background-movie", &[*code])
+            },
+            StopMovie { code } => words("stop-movie", &[*code]),
+            Nop => words("nop", &[]),
+            FadeAmbientSounds => words("fade-ambient-sounds", &[]),
+            ScheduleMovieCommand { code, time, cmd, arg } => vec![
+                "schedule-movie-command".to_owned(),
+                code.to_string(),
+                time.to_string(),
+                cmd.to_string(),
+                arg.to_string(),
+            ],
+            ActivatePlst { record } => words("activate-plst", &[*record]),
+            ActivateSlst { record } => words("activate-slst", &[*record]),
+            ActivateMlstAndPlay { record } => {
+                words("activate-mlst-and-play", &[*record])
+            },
+            ActivateBlst { record } => words("activate-blst", &[*record]),
+            ActivateFlst { record } => words("activate-flst", &[*record]),
+            ZipMode => words("zip-mode", &[]),
+            ActivateMlst { record, u0 } => {
+                words("activate-mlst", &[*record, *u0])
+            },
+            Unknown { cmd, args } => {
+                let mut w = words("unknown", &[*cmd]);
+                w.extend(args.iter().map(|a| a.to_string()));
+                w
+            },
+        };
+        line(out, depth, &w);
+    }
+}
+
+// the words of one line, with its line number
+struct Line {
+    number: usize,
+    words: Vec<String>,
+}
+
+fn split_lines(text: &str) -> Vec<Line> {
+    text.lines().enumerate().filter_map(|(i, l)| {
+        let l = l.split('#').next().unwrap_or("");
+        let l = l.replace('(', " ( ").replace(')', " ) ");
+        let words: Vec<String> =
+            l.split_whitespace().map(|w| w.to_owned()).collect();
+        if words.is_empty() {
+            None
+        } else {
+            Some(Line { number: i + 1, words })
+        }
+    }).collect()
+}
+
+struct Args<'a> {
+    number: usize,
+    words: std::iter::Peekable<std::slice::Iter<'a, String>>,
+}
+
+impl<'a> Args<'a> {
+    fn error(&self, msg: &str) -> anyhow::Error {
+        anyhow::anyhow!("line {}: {}", self.number, msg)
+    }
+
+    fn peek(&mut self) -> Option<&str> {
+        self.words.peek().map(|w| w.as_str())
+    }
+
+    fn word(&mut self) -> Result<&'a str> {
+        match self.words.next() {
+            Some(w) => Ok(w.as_str()),
+            None => Err(self.error("missing argument")),
+        }
+    }
+
+    fn num<T: std::str::FromStr>(&mut self) -> Result<T> {
+        let w = self.word()?;
+        w.parse().map_err(|_| self.error(&format!("bad number: {}", w)))
+    }
+
+    fn symbol(&mut self, table: &[String]) -> Result<u16> {
+        let w = self.word()?;
+        if let Ok(n) = w.parse() {
+            return Ok(n);
+        }
+        match table.iter().position(|n| n == w) {
+            Some(i) => Ok(i as u16),
+            None => Err(self.error(&format!("unknown name: {}", w))),
+        }
+    }
+
+    fn rest(&mut self) -> Result<Vec<u16>> {
+        let mut ret = Vec::new();
+        while self.peek().is_some() {
+            ret.push(self.num()?);
+        }
+        Ok(ret)
+    }
+
+    fn finish<T>(&mut self, value: T) -> Result<T> {
+        match self.peek() {
+            None => Ok(value),
+            Some(w) => {
+                let msg = format!("unexpected argument: {}", w);
+                Err(self.error(&msg))
+            },
+        }
+    }
+}
+
+pub fn assemble(text: &str, names: &ScriptNames)
+                -> Result<IndexMap<Event, Vec<Command>>>
+{
+    let lines = split_lines(text);
+    let mut pos = 0;
+    let mut handlers = IndexMap::new();
+    while let Some(l) = lines.get(pos) {
+        let event = match l.words.as_slice() {
+            [on, name] if on == "on" => (0..=10)
+                .filter_map(Event::from_code)
+                .find(|e| event_name(e) == name)
+                .ok_or_else(|| anyhow::anyhow!(
+                    "line {}: unknown event: {}", l.number, name))?,
+            _ => anyhow::bail!("line {}: expected on EVENT", l.number),
+        };
+        pos += 1;
+        let commands = assemble_block(&lines, &mut pos, names)?;
+        if handlers.insert(event, commands).is_some() {
+            anyhow::bail!("line {}: duplicate handler", l.number);
+        }
+    }
+    Ok(handlers)
+}
+
+pub fn assemble_commands(text: &str, names: &ScriptNames)
+                         -> Result<Vec<Command>>
+{
+    let lines = split_lines(text);
+    let mut pos = 0;
+    let commands = assemble_block(&lines, &mut pos, names)?;
+    if let Some(l) = lines.get(pos) {
+        anyhow::bail!("line {}: unexpected {}", l.number, l.words[0]);
+    }
+    Ok(commands)
+}
+
+// reads commands up to the next on, case or end
+fn assemble_block(lines: &[Line], pos: &mut usize, names: &ScriptNames)
+                  -> Result<Vec<Command>>
+{
+    let mut commands = Vec::new();
+    while let Some(l) = lines.get(*pos) {
+        match l.words[0].as_str() {
+            "on" | "case" | "end" => break,
+            "switch" => {
+                let mut args = Args {
+                    number: l.number,
+                    words: l.words[1..].iter().peekable(),
+                };
+                let var = args.symbol(&names.variables)?;
+                args.finish(())?;
+                *pos += 1;
+
+                let mut branches = IndexMap::new();
+                loop {
+                    let c = match lines.get(*pos) {
+                        Some(c) => c,
+                        None => anyhow::bail!(
+                            "line {}: switch without end", l.number),
+                    };
+                    match c.words[0].as_str() {
+                        "case" => {
+                            let mut args = Args {
+                                number: c.number,
+                                words: c.words[1..].iter().peekable(),
+                            };
+                            let value: u16 = args.num()?;
+                            args.finish(())?;
+                            *pos += 1;
+                            let commands =
+                                assemble_block(lines, pos, names)?;
+                            if branches.insert(value, commands).is_some() {
+                                anyhow::bail!(
+                                    "line {}: duplicate case", c.number);
+                            }
+                        },
+                        "end" => {
+                            *pos += 1;
+                            break;
+                        },
+                        _ => anyhow::bail!(
+                            "line {}: switch without end", l.number),
+                    }
+                }
+                commands.push(Command::Conditional { var, branches });
+            },
+            _ => {
+                commands.push(assemble_command(l, names)?);
+                *pos += 1;
+            },
+        }
+    }
+    Ok(commands)
+}
+
+fn assemble_command(l: &Line, names: &ScriptNames) -> Result<Command> {
+    use Command::*;
+
+    let mut a = Args {
+        number: l.number,
+        words: l.words[1..].iter().peekable(),
+    };
+    let command = match l.words[0].as_str() {
+        "draw-bmp" => DrawBmp {
+            tbmp_id: a.num()?,
+            left: a.num()?,
+            top: a.num()?,
+            right: a.num()?,
+            bottom: a.num()?,
+            u0: a.num()?,
+            u1: a.num()?,
+            u2: a.num()?,
+            u3: a.num()?,
+        },
+        "goto-card" => GotoCard { id: a.num()? },
+        "activate-inline-slst" => {
+            let fade_flags = a.num()?;
+            let looping = a.num()?;
+            let volume = a.num()?;
+            let u0 = a.num()?;
+            let u1 = a.num()?;
+            let mut sounds = Vec::new();
+            while a.peek() == Some("(") {
+                a.word()?;
+                sounds.push(InlineSlst {
+                    id: a.num()?,
+                    volume: a.num()?,
+                    balance: a.num()?,
+                    u2: a.num()?,
+                });
+                if a.word()? != ")" {
+                    return Err(a.error("expected )"));
+                }
+            }
+            ActivateInlineSlst {
+                sounds, fade_flags, looping, volume, u0, u1,
+            }
+        },
+        "play-wav" => PlayWav {
+            id: a.num()?,
+            volume: a.num()?,
+            u1: a.num()?,
+        },
+        "set-variable" => SetVariable {
+            var: a.symbol(&names.variables)?,
+            value: a.num()?,
+        },
+        "enable-hotspot" => EnableHotspot { hotspot_id: a.num()? },
+        "disable-hotspot" => DisableHotspot { hotspot_id: a.num()? },
+        "stop-sound" => StopSound { flags: a.num()? },
+        "set-cursor" => SetCursor { cursor: a.num()? },
+        "pause" => Pause { ms: a.num()?, u0: a.num()? },
+        "call" => Call {
+            cmd: a.symbol(&names.externals)?,
+            args: a.rest()?,
+        },
+        "transition" => {
+            let w = a.word()?;
+            let direction = match w {
+                "left" => Some(TransitionDirection::Left),
+                "right" => Some(TransitionDirection::Right),
+                "top" => Some(TransitionDirection::Top),
+                "bottom" => Some(TransitionDirection::Bottom),
+                _ => None,
+            };
+            let code = match direction {
+                Some(direction) => {
+                    let new_move = a.peek() == Some("new-move");
+                    if new_move {
+                        a.word()?;
+                    }
+                    let old_move = a.peek() == Some("old-move");
+                    if old_move {
+                        a.word()?;
+                    }
+                    TransitionCode::Direction { direction, new_move, old_move }
+                },
+                None if w == "blend" => TransitionCode::Blend,
+                None => {
+                    let code = w.parse().map_err(|_| {
+                        a.error(&format!("bad transition: {}", w))
+                    })?;
+                    TransitionCode::from_code(code)
+                },
+            };
+            let rect = if a.peek().is_some() {
+                Some((a.num()?, a.num()?, a.num()?, a.num()?))
+            } else {
+                None
+            };
+            Transition { code, rect }
+        },
+        "reload-card" => ReloadCard,
+        "disable-screen-update" => DisableScreenUpdate,
+        "enable-screen-update" => EnableScreenUpdate,
+        "increment-variable" => IncrementVariable {
+            var: a.symbol(&names.variables)?,
+            value: a.num()?,
+        },
+        "goto-stack" => GotoStack {
+            stack_name: a.symbol(&names.stacks)?,
+            code: a.num()?,
+        },
+        "disable-movie" => DisableMovie { code: a.num()? },
+        "disable-all-movies" => DisableAllMovies,
+        "enable-movie" => EnableMovie { code: a.num()? },
+        "play-foreground-movie" => PlayForegroundMovie { code: a.num()? },
+        "play-background-movie" => PlayBackgroundMovie { code: a.num()? },
+        "stop-movie" => StopMovie { code: a.num()? },
+        "nop" => Nop,
+        "fade-ambient-sounds" => FadeAmbientSounds,
+        "schedule-movie-command" => ScheduleMovieCommand {
+            code: a.num()?,
+            time: a.num()?,
+            cmd: a.num()?,
+            arg: a.num()?,
+        },
+        "activate-plst" => ActivatePlst { record: a.num()? },
+        "activate-slst" => ActivateSlst { record: a.num()? },
+        "activate-mlst-and-play" => ActivateMlstAndPlay { record: a.num()? },
+        "activate-blst" => ActivateBlst { record: a.num()? },
+        "activate-flst" => ActivateFlst { record: a.num()? },
+        "zip-mode" => ZipMode,
+        "activate-mlst" => ActivateMlst {
+            record: a.num()?,
+            u0: a.num()?,
+        },
+        "unknown" => Unknown {
+            cmd: a.num()?,
+            args: a.rest()?,
+        },
+        w => return Err(a.error(&format!("unknown command: {}", w))),
+    };
+    a.finish(command)
+}
@@ -1,11 +1,13 @@
-use crate::{Record, ResourceType, Format};
-use crate::mhk::{MhkFormat, deserialize_from};
-use super::{deserialize_handlers, Command, Event};
+use crate::{Record, ResourceType, Format, FormatWrite};
+use crate::mhk::{MhkFormat, deserialize_from, serialize_into};
+use super::{deserialize_handlers, serialize_handlers, Command, Event};
 
 use anyhow::Result;
 use serde_derive::{Deserialize, Serialize};
 use smol::io::AsyncRead;
 
+const VERSION: u32 = 1;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct TCard;
 
@@ -21,7 +23,7 @@ impl ResourceType for TCard {
 pub struct Card {
     pub name_rec: i16,
     pub zip_mode_place: u16,
-    pub script: std::collections::HashMap<Event, Vec<Command>>,
+    pub script: indexmap::IndexMap<Event, Vec<Command>>,
 }
 
 #[async_trait::async_trait(?Send)]
@@ -33,7 +35,7 @@ where
     {
         let name_rec = deserialize_from(input).await?;
         let zip_mode_place = deserialize_from(input).await?;
-        let script = deserialize_handlers(input).await?;
+        let script = deserialize_handlers(input, self.lenient_scripts()).await?;
         Ok(Record(Card {
             name_rec,
             zip_mode_place,
@@ -41,3 +43,27 @@ where
         }))
     }
 }
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, I> FormatWrite<Fi, TCard, I, Record<Card>> for MhkFormat
+where
+    Fi: Format<TCard, I, Record<Card>> + 'static,
+    I: AsyncRead + Unpin,
+{
+    async fn convert(&self, fmti: &Fi, res: &TCard, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        if let Some(data) = MhkFormat::passthrough(fmti, input).await? {
+            return Ok(data);
+        }
+        let card = fmti.parse(res, input).await?;
+        let mut out = Vec::new();
+        serialize_into(&mut out, &card.name_rec)?;
+        serialize_into(&mut out, &card.zip_mode_place)?;
+        serialize_handlers(&mut out, &card.script)?;
+        Ok(out)
+    }
+    fn version(&self, _res: &TCard) -> u32 {
+        VERSION
+    }
+}
@@ -1,23 +1,30 @@
 use super::{
     Card, Command, Event as ScriptEvent, PictureMeta, RivenFormat, Stack as RivenStack, TBmp,
-    TCard, TPlst,
+    TCard, THspt, TName, TPlst,
 };
-use crate::{Context, Event, Game, Record, ResourceMap, Resources, Stack};
+use crate::{
+    is_not_found, Context, Event, Game, Record, ResourceMap, Resources, Stack,
+};
+
+use std::rc::Rc;
 
 use anyhow::Result;
 
-pub struct Riven<M> {
+pub struct Riven<M: ResourceMap> {
     resources: Resources<M>,
     stack: RivenStack,
     current: Option<CardInfo>,
     stackid: u16,
+    preload: bool,
+    // stacks the current card can go to, loaded one per idle frame
+    pending_preload: Vec<RivenStack>,
 }
 
 #[derive(Debug)]
 struct CardInfo {
     id: u16,
-    card: Record<Card>,
-    plst: Record<Vec<PictureMeta>>,
+    card: Rc<Record<Card>>,
+    plst: Rc<Record<Vec<PictureMeta>>>,
 }
 
 impl<M> Riven<M>
@@ -26,14 +33,24 @@ where
     M::Format: RivenFormat<M::Handle>,
 {
     pub async fn new(map: M) -> Result<Self> {
+        let mut resources = Resources::new(map);
+        // enough to hold every bitmap on a few cards
+        resources.set_cache_budget(Some(64 << 20));
         Ok(Riven {
-            resources: Resources::new(map),
+            resources,
             stack: RivenStack::A,
             current: None,
             stackid: 0,
+            preload: false,
+            pending_preload: vec![],
         })
     }
 
+    // load stacks that the current card can go to before they are needed
+    pub fn set_preload(&mut self, preload: bool) {
+        self.preload = preload;
+    }
+
     pub async fn goto(&mut self, ctx: &mut Context, stack: RivenStack, id: u16) -> Result<()> {
         println!("goto {:?} {:?}", stack, id);
         let cardinfo = CardInfo {
@@ -67,9 +84,57 @@ where
             }
         }
 
+        // preloading is only a hint, a missing stack shouldn't stop us,
+        // and it waits for idle frames instead of holding up the card
+        self.pending_preload.clear();
+        if self.preload {
+            match self.goto_stacks(stack, id).await {
+                Ok(stacks) => self.pending_preload = stacks,
+                Err(e) => eprintln!("could not find stacks to preload: {}", e),
+            }
+        }
+
         Ok(())
     }
 
+    // other stacks the card's scripts, or its hotspots' scripts, go to
+    async fn goto_stacks(&mut self, stack: RivenStack, id: u16)
+                         -> Result<Vec<RivenStack>>
+    {
+        let mut names = vec![];
+        if let Some(current) = &self.current {
+            for cmds in current.card.script.values() {
+                find_goto_stacks(cmds, &mut names);
+            }
+        }
+        match self.resources.open(stack, THspt, id).await {
+            Ok(hspt) => {
+                for cmds in hspt.iter().flat_map(|h| h.script.values()) {
+                    find_goto_stacks(cmds, &mut names);
+                }
+            }
+            Err(e) if is_not_found(&e) => {}
+            Err(e) => return Err(e),
+        }
+        if names.is_empty() {
+            return Ok(vec![]);
+        }
+
+        // NAME 5 holds the stack names used by GotoStack
+        let stack_names = self.resources.open(stack, TName, 5).await?;
+        let mut stacks = vec![];
+        for n in names {
+            let other = stack_names.get(n as usize)
+                .and_then(|name| RivenStack::from_name(&name.name));
+            if let Some(other) = other {
+                if other != stack && !stacks.contains(&other) {
+                    stacks.push(other);
+                }
+            }
+        }
+        Ok(stacks)
+    }
+
     pub async fn script(&mut self, ctx: &mut Context, commands: &[Command]) -> Result<()> {
         for cmd in commands {
             match cmd {
@@ -108,6 +173,20 @@ where
     }
 }
 
+fn find_goto_stacks(commands: &[Command], names: &mut Vec<u16>) {
+    for cmd in commands {
+        match cmd {
+            Command::GotoStack { stack_name, .. } => names.push(*stack_name),
+            Command::Conditional { branches, .. } => {
+                for subcommands in branches.values() {
+                    find_goto_stacks(subcommands, names);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
 #[async_trait::async_trait(?Send)]
 impl<M> Game for Riven<M>
 where
@@ -151,7 +230,14 @@ where
                 }
                 Ok(true)
             }
-            _ => Ok(true),
+            Event::Idle => {
+                if let Some(stack) = self.pending_preload.pop() {
+                    if let Err(e) = self.resources.preload(stack).await {
+                        eprintln!("could not preload {:?}: {}", stack, e);
+                    }
+                }
+                Ok(true)
+            }
         }
     }
 }
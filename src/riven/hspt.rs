@@ -1,11 +1,13 @@
-use crate::{Record, ResourceType, Format};
-use crate::mhk::{MhkFormat, deserialize_from};
-use super::{deserialize_handlers, Command, Event};
+use crate::{Record, ResourceType, Format, FormatWrite};
+use crate::mhk::{MhkFormat, deserialize_from, serialize_into};
+use super::{deserialize_handlers, serialize_handlers, Command, Event};
 
 use anyhow::Result;
 use serde_derive::{Deserialize, Serialize};
 use smol::io::AsyncRead;
 
+const VERSION: u32 = 1;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct THspt;
 
@@ -30,7 +32,7 @@ pub struct Hotspot {
     pub index: u16,
     pub u1: i16,
     pub zip_mode: u16,
-    pub script: std::collections::HashMap<Event, Vec<Command>>,
+    pub script: indexmap::IndexMap<Event, Vec<Command>>,
 }
 
 #[async_trait::async_trait(?Send)]
@@ -58,9 +60,47 @@ where
                 index: deserialize_from(input).await?,
                 u1: deserialize_from(input).await?,
                 zip_mode: deserialize_from(input).await?,
-                script: deserialize_handlers(input).await?,
+                script: deserialize_handlers(
+                    input, self.lenient_scripts(),
+                ).await?,
             });
         }
         Ok(Record(ret))
     }
 }
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, I> FormatWrite<Fi, THspt, I, Record<Vec<Hotspot>>> for MhkFormat
+where
+    Fi: Format<THspt, I, Record<Vec<Hotspot>>> + 'static,
+    I: AsyncRead + Unpin,
+{
+    async fn convert(&self, fmti: &Fi, res: &THspt, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        if let Some(data) = MhkFormat::passthrough(fmti, input).await? {
+            return Ok(data);
+        }
+        let hotspots = fmti.parse(res, input).await?;
+        let mut out = Vec::new();
+        serialize_into(&mut out, &(hotspots.len() as u16))?;
+        for h in hotspots.iter() {
+            serialize_into(&mut out, &h.blst_id)?;
+            serialize_into(&mut out, &h.name_rec)?;
+            serialize_into(&mut out, &h.left)?;
+            serialize_into(&mut out, &h.top)?;
+            serialize_into(&mut out, &h.right)?;
+            serialize_into(&mut out, &h.bottom)?;
+            serialize_into(&mut out, &h.u0)?;
+            serialize_into(&mut out, &h.mouse_cursor)?;
+            serialize_into(&mut out, &h.index)?;
+            serialize_into(&mut out, &h.u1)?;
+            serialize_into(&mut out, &h.zip_mode)?;
+            serialize_handlers(&mut out, &h.script)?;
+        }
+        Ok(out)
+    }
+    fn version(&self, _res: &THspt) -> u32 {
+        VERSION
+    }
+}
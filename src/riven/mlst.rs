@@ -1,10 +1,14 @@
-use crate::{Record, ResourceType, Format};
-use crate::mhk::{MhkFormat, deserialize_u16_table_from};
+use crate::{Record, ResourceType, Format, FormatWrite};
+use crate::mhk::{
+    MhkFormat, deserialize_u16_table_from, serialize_u16_table_into,
+};
 
 use anyhow::Result;
 use serde_derive::{Deserialize, Serialize};
 use smol::io::AsyncRead;
 
+const VERSION: u32 = 1;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct TMlst;
 
@@ -40,3 +44,25 @@ where
         Ok(Record(deserialize_u16_table_from(input).await?))
     }
 }
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, I> FormatWrite<Fi, TMlst, I, Record<Vec<MovieMeta>>> for MhkFormat
+where
+    Fi: Format<TMlst, I, Record<Vec<MovieMeta>>> + 'static,
+    I: AsyncRead + Unpin,
+{
+    async fn convert(&self, fmti: &Fi, res: &TMlst, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        if let Some(data) = MhkFormat::passthrough(fmti, input).await? {
+            return Ok(data);
+        }
+        let data = fmti.parse(res, input).await?;
+        let mut out = Vec::new();
+        serialize_u16_table_into(&mut out, &data)?;
+        Ok(out)
+    }
+    fn version(&self, _res: &TMlst) -> u32 {
+        VERSION
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{Format, ResourceType};
+use crate::{Format, FormatWrite, Raw, RawResource, ResourceType};
 
 mod game;
 pub use game::*;
@@ -6,6 +6,9 @@ pub use game::*;
 mod script;
 pub use script::*;
 
+mod assembly;
+pub use assembly::*;
+
 mod blst;
 pub use blst::*;
 
@@ -42,6 +45,15 @@ pub use tbmp::*;
 mod tcur;
 pub use tcur::*;
 
+mod tmov;
+pub use tmov::*;
+
+mod twav;
+pub use twav::*;
+
+mod xref;
+pub use xref::*;
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Stack {
     A,
@@ -81,42 +93,153 @@ impl crate::Stack for Stack {
 
 pub trait RivenFormat<I>:
     Format<TCard, I, <TCard as ResourceType>::Data>
+    + Format<TName, I, <TName as ResourceType>::Data>
     + Format<TPlst, I, <TPlst as ResourceType>::Data>
+    + Format<THspt, I, <THspt as ResourceType>::Data>
     + Format<TBmp, I, <TBmp as ResourceType>::Data>
 {
 }
 
 impl<F, I> RivenFormat<I> for F where
     F: Format<TCard, I, <TCard as ResourceType>::Data>
+        + Format<TName, I, <TName as ResourceType>::Data>
         + Format<TPlst, I, <TPlst as ResourceType>::Data>
+        + Format<THspt, I, <THspt as ResourceType>::Data>
         + Format<TBmp, I, <TBmp as ResourceType>::Data>
 {
 }
 
-pub async fn map_5cd<F>(
+// the format traits and registries for every resource type, from one
+// list of types so they can't drift apart
+macro_rules! resource_types {
+    ($($t:ident),* $(,)?) => {
+        // every resource type we know how to read, and a raw slot for
+        // the ones we don't
+        pub trait RivenFormatAll<I>:
+            RivenFormat<I> + Format<RawResource, I, Raw>
+            $(+ Format<$t, I, <$t as ResourceType>::Data>)*
+        {
+        }
+
+        impl<F, I> RivenFormatAll<I> for F where
+            F: RivenFormat<I> + Format<RawResource, I, Raw>
+                $(+ Format<$t, I, <$t as ResourceType>::Data>)*
+        {
+        }
+
+        // every resource type we know how to convert from Fi
+        pub trait RivenFormatWriteAll<Fi, I>:
+            FormatWrite<Fi, RawResource, I, Raw>
+            $(+ FormatWrite<Fi, $t, I, <$t as ResourceType>::Data>)*
+        where
+            Fi: RivenFormatAll<I>,
+        {
+        }
+
+        impl<F, Fi, I> RivenFormatWriteAll<Fi, I> for F where
+            Fi: RivenFormatAll<I>,
+            F: FormatWrite<Fi, RawResource, I, Raw>
+                $(+ FormatWrite<Fi, $t, I, <$t as ResourceType>::Data>)*
+        {
+        }
+
+        pub fn registry<M>() -> crate::Registry<M>
+        where
+            M: crate::ResourceMap,
+            M::Format: RivenFormatAll<M::Handle>,
+            M::Stack: Clone,
+        {
+            let mut reg = crate::Registry::new();
+            $(reg.add($t);)*
+            reg
+        }
+
+        // for exporting everything with Resources::write_all_to, which
+        // converts from bytes already read out of the map. types not
+        // listed here are copied through the raw slot
+        pub fn write_registry<M, Mw>() -> crate::WriteRegistry<M, Mw>
+        where
+            M: crate::ResourceMap,
+            Mw: crate::ResourceMap,
+            M::Format: RivenFormatAll<smol::io::Cursor<Vec<u8>>>,
+            Mw::Format:
+                RivenFormatWriteAll<M::Format, smol::io::Cursor<Vec<u8>>>,
+        {
+            let mut reg = crate::WriteRegistry::new();
+            $(reg.add($t);)*
+            reg.add_raw_fallback();
+            reg
+        }
+
+        // for checking every resource type survives a trip through F
+        pub fn verify_registry<M, F>() -> crate::VerifyRegistry<M, F>
+        where
+            M: crate::ResourceMap,
+            M::Format: RivenFormatAll<M::Handle>,
+            F: RivenFormatWriteAll<M::Format, M::Handle>
+                + RivenFormatAll<smol::io::Cursor<Vec<u8>>>,
+            M::Stack: Clone,
+        {
+            let mut reg = crate::VerifyRegistry::new();
+            $(reg.add($t);)*
+            reg
+        }
+    };
+}
+
+resource_types! {
+    TBlst, TCard, TFlst, THspt, TMlst, TName, TPlst, TRmap, TSfxe, TSlst,
+    TBmp, TCur, TWav, TMov,
+}
+
+// the windows 5-cd and dvd releases, the dvd being the 5-cd files
+// unpacked, and the 25th anniversary release, with its cursors in a dll.
+// the mac releases keep cursors in the application's resource fork, which
+// can't be read, so they have no exe stack. the demo is one stack.
+// the 25th anniversary translations aren't loaded.
+pub fn layouts() -> Vec<crate::mhk::MhkLayout> {
+    crate::mhk::MhkLayout::from_json_list(&[
+        &include_bytes!("layouts/5cd.json")[..],
+        &include_bytes!("layouts/dvd.json")[..],
+        &include_bytes!("layouts/25th.json")[..],
+        &include_bytes!("layouts/mac.json")[..],
+        &include_bytes!("layouts/demo.json")[..],
+    ])
+    .expect("built-in layout is invalid")
+}
+
+pub async fn map_layout<F>(
+    fs: F,
+    layout: &crate::mhk::MhkLayout,
+) -> anyhow::Result<crate::mhk::MhkMap<crate::mhk::LayoutFilesystem<F>, Stack>>
+where
+    F: crate::filesystem::Filesystem,
+{
+    let mut map = layout.open(fs).await?;
+    // cursors live in the executable and are needed everywhere
+    map.pin_stack(Stack::Exe);
+    Ok(map)
+}
+
+pub async fn map_auto<F>(
     mut fs: F,
-) -> anyhow::Result<impl crate::ResourceMapList<Format = crate::mhk::MhkFormat, Stack = Stack>>
+) -> anyhow::Result<crate::mhk::MhkMap<crate::mhk::LayoutFilesystem<F>, Stack>>
+where
+    F: crate::filesystem::Filesystem,
+{
+    let layouts = layouts();
+    let layout = crate::mhk::MhkLayout::detect(&layouts, &mut fs).await?;
+    map_layout(fs, layout).await
+}
+
+pub async fn map_5cd<F>(
+    fs: F,
+) -> anyhow::Result<crate::mhk::MhkMap<crate::mhk::LayoutFilesystem<F>, Stack>>
 where
     F: crate::filesystem::Filesystem,
 {
-    let arcriven = fs.open(&["arcriven.z"]).await?;
-    let z = crate::filesystem::ZArchive::new(arcriven).await?;
-    Ok(crate::mhk::MhkMap::new(
-        (z, fs),
-        [
-            (Stack::A, vec!["a_Data.MHK", "a_Sounds.MHK"]),
-            (Stack::B, vec!["b_Data.MHK", "b_Sounds.MHK", "b2_data.MHK"]),
-            (Stack::G, vec!["g_Data.MHK", "g_Sounds.MHK"]),
-            (Stack::J, vec!["j_Data1.MHK", "j_Data2.MHK", "j_Sounds.MHK"]),
-            (Stack::O, vec!["o_Data.MHK", "o_Sounds.MHK"]),
-            (Stack::P, vec!["p_Data.MHK", "p_Sounds.MHK"]),
-            (Stack::R, vec!["r_Data.MHK", "r_Sounds.MHK"]),
-            (Stack::T, vec!["t_Data.MHK", "t_Sounds.MHK"]),
-            (Stack::Extras, vec!["Extras.MHK"]),
-            (Stack::Exe, vec!["Riven.exe"]),
-        ]
-        .iter()
-        .cloned()
-        .collect(),
-    ))
+    let layouts = layouts();
+    let layout = layouts.iter().find(|l| l.name == "5cd")
+        .expect("5cd layout missing");
+    map_layout(fs, layout).await
 }
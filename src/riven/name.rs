@@ -1,5 +1,8 @@
-use crate::{Record, ResourceType, Format};
-use crate::mhk::{MhkFormat, deserialize_from, deserialize_vec_from};
+use crate::{Record, ResourceType, Format, FormatWrite};
+use crate::mhk::{
+    MhkFormat, MhkError,
+    deserialize_from, deserialize_vec_from, serialize_into, serialize_vec_into,
+};
 
 use anyhow::Result;
 use serde_derive::{Deserialize, Serialize};
@@ -7,6 +10,8 @@ use smol::io::{
     BufReader, AsyncRead, AsyncBufReadExt, AsyncSeek, AsyncSeekExt, SeekFrom,
 };
 
+const VERSION: u32 = 1;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct TName;
 
@@ -48,12 +53,51 @@ where
             let mut name = Vec::new();
             bufinput.seek(SeekFrom::Start(start + *offs as u64)).await?;
             bufinput.read_until(0, &mut name).await?;
+            if name.last() == Some(&0) {
+                name.pop();
+            }
             ret.push(Name {
                 unknown: val,
-                name: String::from_utf8_lossy(&name[..name.len() - 1])
-                    .into_owned(),
+                name: String::from_utf8_lossy(&name).into_owned(),
             });
         }
         Ok(Record(ret))
     }
 }
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, I> FormatWrite<Fi, TName, I, Record<Vec<Name>>> for MhkFormat
+where
+    Fi: Format<TName, I, Record<Vec<Name>>> + 'static,
+    I: AsyncRead + AsyncSeek + Unpin,
+{
+    async fn convert(&self, fmti: &Fi, res: &TName, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        if let Some(data) = MhkFormat::passthrough(fmti, input).await? {
+            return Ok(data);
+        }
+        let names = fmti.parse(res, input).await?;
+        let mut offsets = Vec::with_capacity(names.len());
+        let mut strings = Vec::new();
+        for name in names.iter() {
+            if strings.len() > u16::MAX as usize {
+                anyhow::bail!(MhkError::InvalidFormat("NAME too large"));
+            }
+            offsets.push(strings.len() as u16);
+            strings.extend_from_slice(name.name.as_bytes());
+            strings.push(0);
+        }
+        let values: Vec<u16> = names.iter().map(|n| n.unknown).collect();
+
+        let mut out = Vec::new();
+        serialize_into(&mut out, &(names.len() as u16))?;
+        serialize_vec_into(&mut out, &offsets)?;
+        serialize_vec_into(&mut out, &values)?;
+        out.extend_from_slice(&strings);
+        Ok(out)
+    }
+    fn version(&self, _res: &TName) -> u32 {
+        VERSION
+    }
+}
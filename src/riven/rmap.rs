@@ -1,9 +1,13 @@
-use crate::{Record, ResourceType, Format};
-use crate::mhk::{MhkFormat, MhkError, deserialize_vec_from};
+use crate::{Record, ResourceType, Format, FormatWrite};
+use crate::mhk::{
+    MhkFormat, MhkError, deserialize_vec_from, serialize_vec_into,
+};
 
 use anyhow::Result;
 use smol::io::{AsyncRead, AsyncReadExt};
 
+const VERSION: u32 = 1;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct TRmap;
 
@@ -33,3 +37,25 @@ where
         Ok(Record(res))
     }
 }
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, I> FormatWrite<Fi, TRmap, I, Record<Vec<u32>>> for MhkFormat
+where
+    Fi: Format<TRmap, I, Record<Vec<u32>>> + 'static,
+    I: AsyncRead + Unpin,
+{
+    async fn convert(&self, fmti: &Fi, res: &TRmap, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        if let Some(data) = MhkFormat::passthrough(fmti, input).await? {
+            return Ok(data);
+        }
+        let data = fmti.parse(res, input).await?;
+        let mut out = Vec::with_capacity(data.len() * 4);
+        serialize_vec_into(&mut out, &data)?;
+        Ok(out)
+    }
+    fn version(&self, _res: &TRmap) -> u32 {
+        VERSION
+    }
+}
@@ -1,8 +1,13 @@
-use crate::mhk::{MhkError, deserialize_from, deserialize_u16_table_from};
+use crate::mhk::{
+    MhkError,
+    deserialize_from, deserialize_u16_table_from,
+    serialize_into, serialize_u16_table_into,
+};
 
 use std::pin::Pin;
 
 use anyhow::Result;
+use indexmap::IndexMap;
 use serde_derive::{Deserialize, Serialize};
 use smol::io::AsyncRead;
 
@@ -21,35 +26,76 @@ pub enum Event {
     DisplayUpdate,
 }
 
+// in lenient mode, commands that can't be decoded are kept as Unknown
 pub async fn deserialize_handlers<R>(
     reader: &mut R,
-) -> Result<std::collections::HashMap<Event, Vec<Command>>>
+    lenient: bool,
+) -> Result<IndexMap<Event, Vec<Command>>>
 where
     R: AsyncRead + Unpin,
 {
     let count: u16 = deserialize_from(reader).await?;
-    let mut handlers = std::collections::HashMap::with_capacity(count as usize);
+    // keep the order they were read in, so they serialize identically
+    let mut handlers = IndexMap::with_capacity(count as usize);
     for _ in 0..count {
         let event_type: u16 = deserialize_from(reader).await?;
-        let commands = deserialize_commands(reader).await?;
-        let event = match event_type {
-            0 => Ok(Event::MouseDown),
-            1 => Ok(Event::MouseStillDown),
-            2 => Ok(Event::MouseUp),
-            3 => Ok(Event::MouseEnter),
-            4 => Ok(Event::MouseWithin),
-            5 => Ok(Event::MouseLeave),
-            6 => Ok(Event::LoadCard),
-            7 => Ok(Event::CloseCard),
+        let commands = deserialize_commands(reader, lenient).await?;
+        let event = Event::from_code(event_type)
+            .ok_or(MhkError::InvalidFormat("bad event type"))?;
+        if handlers.insert(event, commands).is_some() {
+            anyhow::bail!(MhkError::InvalidFormat("duplicate script handler"));
+        }
+    }
+    Ok(handlers)
+}
+
+impl Event {
+    pub fn from_code(code: u16) -> Option<Self> {
+        match code {
+            0 => Some(Event::MouseDown),
+            1 => Some(Event::MouseStillDown),
+            2 => Some(Event::MouseUp),
+            3 => Some(Event::MouseEnter),
+            4 => Some(Event::MouseWithin),
+            5 => Some(Event::MouseLeave),
+            6 => Some(Event::LoadCard),
+            7 => Some(Event::CloseCard),
             // 8 is not seen
-            9 => Ok(Event::OpenCard),
-            10 => Ok(Event::DisplayUpdate),
+            9 => Some(Event::OpenCard),
+            10 => Some(Event::DisplayUpdate),
+            _ => None,
+        }
+    }
 
-            _ => Err(MhkError::InvalidFormat("bad event type")),
-        }?;
-        handlers.insert(event, commands);
+    pub fn code(&self) -> u16 {
+        match self {
+            Event::MouseDown => 0,
+            Event::MouseStillDown => 1,
+            Event::MouseUp => 2,
+            Event::MouseEnter => 3,
+            Event::MouseWithin => 4,
+            Event::MouseLeave => 5,
+            Event::LoadCard => 6,
+            Event::CloseCard => 7,
+            Event::OpenCard => 9,
+            Event::DisplayUpdate => 10,
+        }
     }
-    Ok(handlers)
+}
+
+pub fn serialize_handlers(
+    out: &mut Vec<u8>,
+    handlers: &IndexMap<Event, Vec<Command>>,
+) -> Result<()> {
+    if handlers.len() > u16::MAX as usize {
+        anyhow::bail!(MhkError::InvalidFormat("too many script handlers"));
+    }
+    serialize_into(out, &(handlers.len() as u16))?;
+    for (event, commands) in handlers {
+        serialize_into(out, &event.code())?;
+        serialize_commands(out, commands)?;
+    }
+    Ok(())
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -79,6 +125,51 @@ pub enum TransitionCode {
         old_move: bool,
     },
     Blend,
+    Unknown {
+        code: u16,
+    },
+}
+
+impl TransitionCode {
+    pub fn from_code(code: u16) -> Self {
+        match code {
+            16 => TransitionCode::Blend,
+            17..=u16::MAX => TransitionCode::Unknown { code },
+            _ => TransitionCode::Direction {
+                direction: match code & 0x3 {
+                    0 => TransitionDirection::Left,
+                    1 => TransitionDirection::Right,
+                    2 => TransitionDirection::Top,
+                    3 => TransitionDirection::Bottom,
+                    _ => unreachable!(),
+                },
+                new_move: (code & 0x4) > 0,
+                old_move: (code & 0x8) > 0,
+            },
+        }
+    }
+
+    pub fn code(&self) -> u16 {
+        match self {
+            TransitionCode::Direction { direction, new_move, old_move } => {
+                let mut code = match direction {
+                    TransitionDirection::Left => 0,
+                    TransitionDirection::Right => 1,
+                    TransitionDirection::Top => 2,
+                    TransitionDirection::Bottom => 3,
+                };
+                if *new_move {
+                    code |= 0x4;
+                }
+                if *old_move {
+                    code |= 0x8;
+                }
+                code
+            },
+            TransitionCode::Blend => 16,
+            TransitionCode::Unknown { code } => *code,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
@@ -117,7 +208,7 @@ pub enum Command {
     },
     Conditional {
         var: u16,
-        branches: std::collections::HashMap<u16, Vec<Command>>,
+        branches: IndexMap<u16, Vec<Command>>,
     },
     EnableHotspot {
         hotspot_id: u16,
@@ -125,6 +216,10 @@ pub enum Command {
     DisableHotspot {
         hotspot_id: u16,
     },
+    // bit 0 stops sound effects, bit 1 stops ambient sounds
+    StopSound {
+        flags: u16,
+    },
     SetCursor {
         cursor: u16,
     },
@@ -152,18 +247,44 @@ pub enum Command {
         stack_name: u16,
         code: u32,
     },
+    // stop drawing a movie, but leave it loaded
+    DisableMovie {
+        code: u16,
+    },
+    DisableAllMovies,
+    // draw a movie again after DisableMovie
+    EnableMovie {
+        code: u16,
+    },
     PlayForegroundMovie {
         code: u16,
     },
     PlayBackgroundMovie {
         code: u16,
     },
+    StopMovie {
+        code: u16,
+    },
+    // takes no arguments and the original engine ignores it
+    Nop,
+    FadeAmbientSounds,
+    // run command cmd(arg) once the movie reaches time
+    ScheduleMovieCommand {
+        code: u16,
+        time: u32,
+        cmd: u16,
+        arg: u16,
+    },
     ActivatePlst {
         record: u16,
     },
     ActivateSlst {
         record: u16,
     },
+    // activate an MLST record and play it right away
+    ActivateMlstAndPlay {
+        record: u16,
+    },
     ActivateBlst {
         record: u16,
     },
@@ -182,9 +303,24 @@ pub enum Command {
     },
 }
 
-// box this one up, because otherwise we make an infinite type
+// real scripts nest conditionals only a few levels deep
+const MAX_SCRIPT_DEPTH: usize = 32;
+
 pub fn deserialize_commands<'a, R>(
     reader: &'a mut R,
+    lenient: bool,
+) -> Pin<Box<dyn smol::future::Future<Output=Result<Vec<Command>>> + 'a>>
+where
+    R: AsyncRead + Unpin,
+{
+    deserialize_commands_nested(reader, 0, lenient)
+}
+
+// box this one up, because otherwise we make an infinite type
+fn deserialize_commands_nested<'a, R>(
+    reader: &'a mut R,
+    depth: usize,
+    lenient: bool,
 ) -> Pin<Box<dyn smol::future::Future<Output=Result<Vec<Command>>> + 'a>>
 where
     R: AsyncRead + Unpin,
@@ -192,6 +328,9 @@ where
     use Command::*;
 
     Box::pin(async move {
+        if depth > MAX_SCRIPT_DEPTH {
+            anyhow::bail!(MhkError::InvalidFormat("script nested too deeply"));
+        }
         let count: u16 = deserialize_from(reader).await?;
         let mut commands = Vec::with_capacity(count as usize);
         for _ in 0..count {
@@ -199,7 +338,7 @@ where
             let args: Vec<u16> =
                 deserialize_u16_table_from(reader).await?;
             
-            commands.push(match (cmd, args.as_slice()) {
+            let command = match (cmd, args.as_slice()) {
                 (1, &[tbmp_id, left, top, right, bottom, u0, u1, u2, u3]) => {
                     Ok(DrawBmp {
                         tbmp_id,
@@ -216,18 +355,10 @@ where
                 
                 (2, &[id]) => Ok(GotoCard { id }),
                 
-                (3, slice) => {
-                    if slice.len() < 1 {
-                        anyhow::bail!(MhkError::InvalidFormat(
-                            "bad inline SLST record",
-                        ));
-                    }
+                (3, slice) if !slice.is_empty()
+                    && slice.len() == 6 + 4 * slice[0] as usize =>
+                {
                     let n = slice[0] as usize;
-                    if slice.len() != 6 + 4 * n {
-                        anyhow::bail!(MhkError::InvalidFormat(
-                            "bad inline SLST record",
-                        ));
-                    }
                     let mut sounds = Vec::with_capacity(n);
                     for i in 0..n {
                         sounds.push(InlineSlst {
@@ -247,71 +378,66 @@ where
                         u1: slice[5 + n],
                     })
                 },
+                (3, _) => {
+                    Err(MhkError::InvalidFormat("bad inline SLST record"))
+                },
 
                 (4, &[id, volume, u1]) => Ok(PlayWav { id, volume, u1 }),
                 (7, &[var, value]) => Ok(SetVariable { var, value }),
 
                 (8, &[var, value_count]) => {
-                    let mut branches = std::collections::HashMap::with_capacity(
-                        value_count as usize,
-                    );
+                    let mut branches =
+                        IndexMap::with_capacity(value_count as usize);
                     for _ in 0..value_count {
                         let value: u16 = deserialize_from(reader).await?;
                         let subcommands =
-                            deserialize_commands(reader).await?;
-                        branches.insert(value, subcommands);
+                            deserialize_commands_nested(
+                                reader, depth + 1, lenient,
+                            ).await?;
+                        // a map can't hold both, so don't drop one quietly
+                        if branches.insert(value, subcommands).is_some() {
+                            anyhow::bail!(MhkError::InvalidFormat(
+                                "duplicate conditional branch"));
+                        }
                     }
                     Ok(Conditional { var, branches })
                 },
+                // the branches that follow can't be skipped without knowing
+                // how many there are, so this can't be kept as Unknown
+                (8, _) => anyhow::bail!(
+                    MhkError::InvalidFormat("bad conditional")),
 
                 (9, &[hotspot_id]) => Ok(EnableHotspot { hotspot_id }),
                 (10, &[hotspot_id]) => Ok(DisableHotspot { hotspot_id }),
 
-                (12, &[u0]) => {
-                    Ok(Unknown {
-                        cmd,
-                        args: vec![u0],
-                    })
-                },
+                (12, &[flags]) => Ok(StopSound { flags }),
                 (13, &[cursor]) => Ok(SetCursor { cursor }),
                 (14, &[ms, u0]) => Ok(Pause { ms, u0 }),
 
-                (17, args) => {
-                    if args.len() < 2 || args.len() < 2 + args[1] as usize {
-                        anyhow::bail!(MhkError::InvalidFormat("bad call"));
+                (17, args) if args.len() >= 2
+                    && args.len() >= 2 + args[1] as usize =>
+                {
+                    if args.len() > 2 + args[1] as usize {
+                        // extra trailing arguments would be lost in a Call
+                        Ok(Unknown { cmd, args: args.to_owned() })
+                    } else {
+                        Ok(Call {
+                            cmd: args[0],
+                            args: args[2..].to_owned(),
+                        })
                     }
-                    Ok(Call {
-                        cmd: args[0],
-                        args: args[2..2 + args[1] as usize].to_owned(),
-                    })
                 },
+                (17, _) => Err(MhkError::InvalidFormat("bad call")),
 
-                (18, args) => {
-                    if args.len() != 1 && args.len() != 5 {
-                        anyhow::bail!(MhkError::InvalidFormat("bad transition"));
-                    }
+                (18, args) if args.len() == 1 || args.len() == 5 => {
                     let mut rect = None;
                     if args.len() == 5 {
                         rect = Some((args[1], args[2], args[3], args[4]));
                     }
-                    let codenum = args[0];
-                    let code = if codenum >= 16 {
-                        TransitionCode::Blend
-                    } else {
-                        TransitionCode::Direction {
-                            direction: match codenum & 0x3 {
-                                0 => TransitionDirection::Left,
-                                1 => TransitionDirection::Right,
-                                2 => TransitionDirection::Top,
-                                3 => TransitionDirection::Bottom,
-                                _ => unreachable!(),
-                            },
-                            new_move: (codenum & 0x4) > 0,
-                            old_move: (codenum & 0x8) > 0,
-                        }
-                    };
+                    let code = TransitionCode::from_code(args[0]);
                     Ok(Transition { code, rect })
                 },
+                (18, _) => Err(MhkError::InvalidFormat("bad transition")),
 
                 (19, &[]) => Ok(ReloadCard),
                 (20, &[]) => Ok(DisableScreenUpdate),
@@ -326,47 +452,29 @@ where
                     })
                 },
 
-                (28, &[code]) => {
-                    Ok(Unknown {
-                        cmd,
-                        args: vec![code],
-                    })
-                },
-                (29, &[]) => Ok(Unknown { cmd, args: vec![] }),
+                (28, &[code]) => Ok(DisableMovie { code }),
+                (29, &[]) => Ok(DisableAllMovies),
 
-                (31, &[code]) => {
-                    Ok(Unknown {
-                        cmd,
-                        args: vec![code],
-                    })
-                },
+                (31, &[code]) => Ok(EnableMovie { code }),
                 (32, &[code]) => Ok(PlayForegroundMovie { code }),
                 (33, &[code]) => Ok(PlayBackgroundMovie { code }),
-                (34, &[u0]) => {
-                    Ok(Unknown {
-                        cmd,
-                        args: vec![u0],
-                    })
-                },
+                (34, &[code]) => Ok(StopMovie { code }),
 
-                (36, &[]) => Ok(Unknown { cmd, args: vec![] }),
-                (37, &[]) => Ok(Unknown { cmd, args: vec![] }),
+                (36, &[]) => Ok(Nop),
+                (37, &[]) => Ok(FadeAmbientSounds),
 
-                (38, &[u1, u2, u3, u4, u5]) => {
-                    Ok(Unknown {
+                (38, &[code, time_hi, time_lo, cmd, arg]) => {
+                    Ok(ScheduleMovieCommand {
+                        code,
+                        time: ((time_hi as u32) << 16) | (time_lo as u32),
                         cmd,
-                        args: vec![u1, u2, u3, u4, u5],
+                        arg,
                     })
                 },
 
                 (39, &[record]) => Ok(ActivatePlst { record }),
                 (40, &[record]) => Ok(ActivateSlst { record }),
-                (41, &[u0]) => {
-                    Ok(Unknown {
-                        cmd,
-                        args: vec![u0],
-                    })
-                },
+                (41, &[record]) => Ok(ActivateMlstAndPlay { record }),
 
                 (43, &[record]) => Ok(ActivateBlst { record }),
                 (44, &[record]) => Ok(ActivateFlst { record }),
@@ -375,13 +483,117 @@ where
 
                 (_cmd, _args) => {
                     Err(MhkError::InvalidFormat("unknown script command"))
-                    // Result::<Command, MhkError>::Ok(Unknown {
-                    //    cmd,
-                    //    args: args.to_owned(),
-                    //})
                 },
-            }?);
+            };
+            commands.push(match command {
+                Ok(command) => command,
+                Err(_) if lenient => Unknown { cmd, args },
+                Err(e) => anyhow::bail!(e),
+            });
         }
         Ok(commands)
     })
 }
+
+pub fn serialize_commands(out: &mut Vec<u8>, commands: &[Command])
+                          -> Result<()>
+{
+    use Command::*;
+
+    if commands.len() > u16::MAX as usize {
+        anyhow::bail!(MhkError::InvalidFormat("too many script commands"));
+    }
+    serialize_into(out, &(commands.len() as u16))?;
+    for command in commands {
+        let (cmd, args) = match command {
+            DrawBmp { tbmp_id, left, top, right, bottom, u0, u1, u2, u3 } => {
+                (1, vec![*tbmp_id, *left, *top, *right, *bottom,
+                         *u0, *u1, *u2, *u3])
+            },
+            GotoCard { id } => (2, vec![*id]),
+            ActivateInlineSlst {
+                sounds, fade_flags, looping, volume, u0, u1,
+            } => {
+                let mut args = Vec::with_capacity(6 + 4 * sounds.len());
+                args.push(sounds.len() as u16);
+                args.extend(sounds.iter().map(|s| s.id));
+                args.extend(&[*fade_flags, *looping, *volume, *u0, *u1]);
+                args.extend(sounds.iter().map(|s| s.volume));
+                args.extend(sounds.iter().map(|s| s.balance));
+                args.extend(sounds.iter().map(|s| s.u2));
+                (3, args)
+            },
+            PlayWav { id, volume, u1 } => (4, vec![*id, *volume, *u1]),
+            SetVariable { var, value } => (7, vec![*var, *value]),
+            Conditional { var, branches } => {
+                serialize_into(out, &8u16)?;
+                if branches.len() > u16::MAX as usize {
+                    anyhow::bail!(MhkError::InvalidFormat(
+                        "too many conditional branches",
+                    ));
+                }
+                serialize_u16_table_into(
+                    out, &[*var, branches.len() as u16])?;
+                for (value, commands) in branches {
+                    serialize_into(out, value)?;
+                    serialize_commands(out, commands)?;
+                }
+                continue;
+            },
+            EnableHotspot { hotspot_id } => (9, vec![*hotspot_id]),
+            DisableHotspot { hotspot_id } => (10, vec![*hotspot_id]),
+            StopSound { flags } => (12, vec![*flags]),
+            SetCursor { cursor } => (13, vec![*cursor]),
+            Pause { ms, u0 } => (14, vec![*ms, *u0]),
+            Call { cmd, args } => {
+                let mut callargs = Vec::with_capacity(2 + args.len());
+                callargs.push(*cmd);
+                callargs.push(args.len() as u16);
+                callargs.extend(args);
+                (17, callargs)
+            },
+            Transition { code, rect } => {
+                let codenum = code.code();
+                match rect {
+                    Some((left, top, right, bottom)) => {
+                        (18, vec![codenum, *left, *top, *right, *bottom])
+                    },
+                    None => (18, vec![codenum]),
+                }
+            },
+            ReloadCard => (19, vec![]),
+            DisableScreenUpdate => (20, vec![]),
+            EnableScreenUpdate => (21, vec![]),
+            IncrementVariable { var, value } => (24, vec![*var, *value]),
+            GotoStack { stack_name, code } => {
+                (27, vec![*stack_name, (*code >> 16) as u16, *code as u16])
+            },
+            DisableMovie { code } => (28, vec![*code]),
+            DisableAllMovies => (29, vec![]),
+            EnableMovie { code } => (31, vec![*code]),
+            PlayForegroundMovie { code } => (32, vec![*code]),
+            PlayBackgroundMovie { code } => (33, vec![*code]),
+            StopMovie { code } => (34, vec![*code]),
+            Nop => (36, vec![]),
+            FadeAmbientSounds => (37, vec![]),
+            ScheduleMovieCommand { code, time, cmd, arg } => {
+                (38, vec![*code, (*time >> 16) as u16, *time as u16,
+                          *cmd, *arg])
+            },
+            ActivatePlst { record } => (39, vec![*record]),
+            ActivateSlst { record } => (40, vec![*record]),
+            ActivateMlstAndPlay { record } => (41, vec![*record]),
+            ActivateBlst { record } => (43, vec![*record]),
+            ActivateFlst { record } => (44, vec![*record]),
+            ZipMode => (45, vec![]),
+            ActivateMlst { record, u0 } => (46, vec![*record, *u0]),
+            // would be read back as a conditional, with no branches
+            Unknown { cmd: 8, .. } => anyhow::bail!(
+                MhkError::InvalidFormat("unknown command is a conditional")),
+            Unknown { cmd, args } => (*cmd, args.clone()),
+        };
+        serialize_into(out, &cmd)?;
+        serialize_u16_table_into(out, &args)?;
+    }
+    Ok(())
+}
@@ -1,10 +1,15 @@
-use crate::{Record, ResourceType, Format};
-use crate::mhk::{MhkFormat, MhkError, deserialize_from, deserialize_vec_from};
+use crate::{Record, ResourceType, Format, FormatWrite};
+use crate::mhk::{
+    MhkFormat, MhkError,
+    deserialize_from, deserialize_vec_from, serialize_into, serialize_vec_into,
+};
 
 use anyhow::Result;
 use serde_derive::{Deserialize, Serialize};
 use smol::io::{AsyncRead, AsyncSeek, AsyncSeekExt, SeekFrom};
 
+const VERSION: u32 = 1;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct TSfxe;
 
@@ -131,7 +136,8 @@ where
                             continue;
                         }
                         match frame[len - 1] {
-                            EffectCommand::IncrementRow { ref mut amount } => {
+                            EffectCommand::IncrementRow { ref mut amount }
+                            if *amount < u16::MAX => {
                                 *amount += 1;
                             },
                             _ => {
@@ -179,3 +185,86 @@ where
         }))
     }
 }
+
+// this normalizes rather than reproducing the original bytes. frames are
+// laid out one after another right after the offset table, so frames that
+// shared bytes each get their own copy. the header's second copy of the
+// rect and frame count comes from the first, which parsing already checks,
+// and an IncrementRow of zero writes nothing. parsing the output always
+// gives back the same Effect.
+#[async_trait::async_trait(?Send)]
+impl<Fi, I> FormatWrite<Fi, TSfxe, I, Record<Effect>> for MhkFormat
+where
+    Fi: Format<TSfxe, I, Record<Effect>> + 'static,
+    I: AsyncRead + AsyncSeek + Unpin,
+{
+    async fn convert(&self, fmti: &Fi, res: &TSfxe, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        if let Some(data) = MhkFormat::passthrough(fmti, input).await? {
+            return Ok(data);
+        }
+        let effect = fmti.parse(res, input).await?;
+        if effect.frames.len() > u16::MAX as usize {
+            anyhow::bail!(MhkError::InvalidFormat("too many SFXE frames"));
+        }
+        let frame_count = effect.frames.len() as u16;
+        let offset_table_position = 52 + effect.u7.len() as u32;
+
+        let mut out = Vec::new();
+        out.extend_from_slice("SL".as_bytes());
+        serialize_into(&mut out, &frame_count)?;
+        serialize_into(&mut out, &offset_table_position)?;
+        serialize_into(&mut out, &effect.left)?;
+        serialize_into(&mut out, &effect.top)?;
+        serialize_into(&mut out, &effect.right)?;
+        serialize_into(&mut out, &effect.bottom)?;
+        serialize_into(&mut out, &effect.effect_speed)?;
+        serialize_into(&mut out, &effect.u0)?;
+        serialize_into(&mut out, &effect.top)?;
+        serialize_into(&mut out, &effect.left)?;
+        serialize_into(&mut out, &effect.bottom)?;
+        serialize_into(&mut out, &effect.right)?;
+        serialize_into(&mut out, &effect.u1)?;
+        serialize_into(&mut out, &frame_count)?;
+        serialize_into(&mut out, &effect.u2)?;
+        serialize_into(&mut out, &effect.u3)?;
+        serialize_into(&mut out, &effect.u4)?;
+        serialize_into(&mut out, &effect.u5)?;
+        serialize_into(&mut out, &effect.u6)?;
+        out.extend_from_slice(&effect.u7);
+
+        // frames go right after the offset table
+        let mut frames = Vec::new();
+        let mut offsets = Vec::with_capacity(effect.frames.len());
+        let frames_start = offset_table_position + 4 * frame_count as u32;
+        for frame in &effect.frames {
+            offsets.push(frames_start + frames.len() as u32);
+            for cmd in frame {
+                match cmd {
+                    EffectCommand::IncrementRow { amount } => {
+                        for _ in 0..*amount {
+                            serialize_into(&mut frames, &1u16)?;
+                        }
+                    },
+                    EffectCommand::Copy {
+                        dst_left, src_left, src_top, row_width,
+                    } => {
+                        serialize_into(&mut frames, &3u16)?;
+                        serialize_into(&mut frames, dst_left)?;
+                        serialize_into(&mut frames, src_left)?;
+                        serialize_into(&mut frames, src_top)?;
+                        serialize_into(&mut frames, row_width)?;
+                    },
+                }
+            }
+            serialize_into(&mut frames, &4u16)?;
+        }
+        serialize_vec_into(&mut out, &offsets)?;
+        out.extend_from_slice(&frames);
+        Ok(out)
+    }
+    fn version(&self, _res: &TSfxe) -> u32 {
+        VERSION
+    }
+}
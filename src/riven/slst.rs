@@ -1,13 +1,16 @@
-use crate::{Record, ResourceType, Format};
+use crate::{Record, ResourceType, Format, FormatWrite};
 use crate::mhk::{
     MhkFormat,
     deserialize_from, deserialize_vec_from, deserialize_u16_table_from,
+    serialize_into, serialize_vec_into, serialize_u16_table_into,
 };
 
 use anyhow::Result;
 use serde_derive::{Deserialize, Serialize};
 use smol::io::AsyncRead;
 
+const VERSION: u32 = 1;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct TSlst;
 
@@ -85,3 +88,44 @@ where
         Ok(Record(ret))
     }
 }
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, I> FormatWrite<Fi, TSlst, I, Record<Vec<SoundMeta>>> for MhkFormat
+where
+    Fi: Format<TSlst, I, Record<Vec<SoundMeta>>> + 'static,
+    I: AsyncRead + Unpin,
+{
+    async fn convert(&self, fmti: &Fi, res: &TSlst, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        if let Some(data) = MhkFormat::passthrough(fmti, input).await? {
+            return Ok(data);
+        }
+        let data = fmti.parse(res, input).await?;
+        let mut out = Vec::new();
+        serialize_into(&mut out, &(data.len() as u16))?;
+        for meta in data.iter() {
+            let ids: Vec<u16> = meta.sounds.iter().map(|s| s.id).collect();
+            let volumes: Vec<u16> =
+                meta.sounds.iter().map(|s| s.volume).collect();
+            let balances: Vec<i16> =
+                meta.sounds.iter().map(|s| s.balance).collect();
+            let u2: Vec<u16> = meta.sounds.iter().map(|s| s.u2).collect();
+
+            serialize_into(&mut out, &meta.index)?;
+            serialize_u16_table_into(&mut out, &ids)?;
+            serialize_into(&mut out, &meta.fade_flags)?;
+            serialize_into(&mut out, &meta.looping)?;
+            serialize_into(&mut out, &meta.global_volume)?;
+            serialize_into(&mut out, &meta.u0)?;
+            serialize_into(&mut out, &meta.u1)?;
+            serialize_vec_into(&mut out, &volumes)?;
+            serialize_vec_into(&mut out, &balances)?;
+            serialize_vec_into(&mut out, &u2)?;
+        }
+        Ok(out)
+    }
+    fn version(&self, _res: &TSlst) -> u32 {
+        VERSION
+    }
+}
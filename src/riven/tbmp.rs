@@ -1,10 +1,12 @@
-use crate::{Bitmap, PaletteBitmap, ResourceType, Format};
-use crate::mhk::{MhkFormat, MhkError, deserialize_from};
+use crate::{Bitmap, PaletteBitmap, ResourceType, Format, FormatWrite};
+use crate::mhk::{MhkFormat, MhkError, deserialize_from, serialize_into};
 
 use anyhow::Result;
 use serde_derive::{Deserialize, Serialize};
 use smol::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, SeekFrom};
 
+const VERSION: u32 = 2;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct TBmp;
 
@@ -63,19 +65,15 @@ async fn read_uncompressed<R>(
 where
     R: AsyncRead + AsyncSeek + Unpin,
 {
-    let mut data = vec![
-        0;
-        header.width as usize
-            * header.height as usize
-            * flags.bytes_per_pixel as usize
-    ];
+    // grow row by row, so a bad header fails before allocating everything
+    let row = header.width as usize * flags.bytes_per_pixel as usize;
+    let mut data = Vec::new();
     let jump = header.bytes_per_row as i64
         - (header.width as i64 * flags.bytes_per_pixel as i64);
-    for y in 0..header.height as usize {
-        let start = y * header.width as usize * flags.bytes_per_pixel as usize;
-        let end =
-            start + header.width as usize * flags.bytes_per_pixel as usize;
-        input.read_exact(&mut data[start..end]).await?;
+    for _ in 0..header.height as usize {
+        let start = data.len();
+        data.resize(start + row, 0);
+        input.read_exact(&mut data[start..]).await?;
         input.seek(SeekFrom::Current(jump)).await?;
     }
     Ok(data)
@@ -93,18 +91,23 @@ fn copy_lookback_repeat(
     let start = data.len() - lookback;
     let end = start + len;
     data.reserve(len);
-    unsafe {
-        // we will initialize this immediately after...
-        data.set_len(data.len() + len);
-
-        for i in start..end {
-            data[i + lookback] = data[i];
-        }
+    for i in start..end {
+        let b = data[i];
+        data.push(b);
     }
 
     Some(())
 }
 
+// pixel n back from the end, so 1 is the last pixel
+fn pixel_back(data: &[u8], n: usize) -> Option<u8> {
+    data.len().checked_sub(n).and_then(|i| data.get(i)).cloned()
+}
+
+fn last_duplet(data: &[u8]) -> Option<(u8, u8)> {
+    Some((pixel_back(data, 2)?, pixel_back(data, 1)?))
+}
+
 async fn read_riven<R>(
     header: &BmpHeader,
     flags: &BmpFlags,
@@ -119,11 +122,8 @@ where
         ));
     }
 
-    let mut cmds =
-        Vec::with_capacity(header.width as usize * header.height as usize);
-    let mut out = Vec::with_capacity(
-        header.height as usize * header.bytes_per_row as usize,
-    );
+    let mut cmds = Vec::new();
+    let mut out = Vec::new();
     input.read_to_end(&mut cmds).await?;
 
     // used for when something unexpected comes up
@@ -174,18 +174,16 @@ where
                             // repeat duplet at relative position m, where..=
                             m &= 0x0f;
                             c += 1;
-                            let start = out.len() - 2 * m as usize;
-                            let duplet = out
-                                .get(start..start + 2)
+                            let a = pixel_back(&out, 2 * m as usize)
+                                .ok_or_else(invalid_err)?;
+                            let b = pixel_back(&out, 2 * m as usize - 1)
                                 .ok_or_else(invalid_err)?;
-                            let (a, b) = (duplet[0], duplet[1]);
                             out.extend_from_slice(&[a, b]);
                         },
                         [0x10, p, ..] => {
                             // repeat last duplet, but change second pixel to p
                             c += 2;
-                            let &a = out
-                                .get(out.len() - 2)
+                            let a = pixel_back(&out, 2)
                                 .ok_or_else(invalid_err)?;
                             out.extend_from_slice(&[a, p]);
                         },
@@ -197,12 +195,10 @@ where
                             m &= 0x0f;
                             c += 1;
                             out.reserve(2);
-                            let &a = out
-                                .get(out.len() - 2)
+                            let a = pixel_back(&out, 2)
                                 .ok_or_else(invalid_err)?;
                             out.push(a);
-                            let &b = out
-                                .get(out.len() - m as usize)
+                            let b = pixel_back(&out, m as usize)
                                 .ok_or_else(invalid_err)?;
                             out.push(b);
                         },
@@ -210,27 +206,22 @@ where
                             // repeat last duplet, but add x to second
                             x &= 0x0f;
                             c += 1;
-                            let duplet = out
-                                .get(out.len() - 2..out.len())
+                            let (a, b) = last_duplet(&out)
                                 .ok_or_else(invalid_err)?;
-                            let (a, b) = (duplet[0], duplet[1]);
                             out.extend_from_slice(&[a, b.wrapping_add(x)]);
                         },
                         [mut x @ 0x30..=0x3f, ..] => {
                             // repeat last duplet, but subtract x from second
                             x &= 0x0f;
                             c += 1;
-                            let duplet = out
-                                .get(out.len() - 2..out.len())
+                            let (a, b) = last_duplet(&out)
                                 .ok_or_else(invalid_err)?;
-                            let (a, b) = (duplet[0], duplet[1]);
                             out.extend_from_slice(&[a, b.wrapping_sub(x)]);
                         },
                         [0x40, p, ..] => {
                             // repeat last duplet, but change first pixel to p
                             c += 2;
-                            let &b = out
-                                .get(out.len() - 1)
+                            let b = pixel_back(&out, 1)
                                 .ok_or_else(invalid_err)?;
                             out.extend_from_slice(&[p, b]);
                         },
@@ -239,11 +230,9 @@ where
                             // (-m is given in pixels)
                             m &= 0x0f;
                             c += 1;
-                            let &a = out
-                                .get(out.len() - m as usize)
+                            let a = pixel_back(&out, m as usize)
                                 .ok_or_else(invalid_err)?;
-                            let &b = out
-                                .get(out.len() - 1)
+                            let b = pixel_back(&out, 1)
                                 .ok_or_else(invalid_err)?;
                             out.extend_from_slice(&[a, b]);
                         },
@@ -256,8 +245,7 @@ where
                             // output pixel at -m, then p
                             m &= 0x07;
                             c += 2;
-                            let &a = out
-                                .get(out.len() - m as usize)
+                            let a = pixel_back(&out, m as usize)
                                 .ok_or_else(invalid_err)?;
                             out.extend_from_slice(&[a, p]);
                         },
@@ -270,8 +258,7 @@ where
                             c += 2;
                             out.reserve(2);
                             out.push(p);
-                            let &b = out
-                                .get(out.len() - m as usize)
+                            let b = pixel_back(&out, m as usize)
                                 .ok_or_else(invalid_err)?;
                             out.push(b);
                         },
@@ -279,8 +266,7 @@ where
                             // output p, then (second pixel last duplet) + x
                             x &= 0x0f;
                             c += 2;
-                            let &b = out
-                                .get(out.len() - 1)
+                            let b = pixel_back(&out, 1)
                                 .ok_or_else(invalid_err)?;
                             out.extend_from_slice(&[p, b.wrapping_add(x)]);
                         },
@@ -288,8 +274,7 @@ where
                             // output p, then (second pixel last duplet) - x
                             x &= 0x0f;
                             c += 2;
-                            let &b = out
-                                .get(out.len() - 1)
+                            let b = pixel_back(&out, 1)
                                 .ok_or_else(invalid_err)?;
                             out.extend_from_slice(&[p, b.wrapping_sub(x)]);
                         },
@@ -297,18 +282,15 @@ where
                             // repeat last duplet, but add x to first
                             x &= 0x0f;
                             c += 1;
-                            let duplet = out
-                                .get(out.len() - 2..out.len())
+                            let (a, b) = last_duplet(&out)
                                 .ok_or_else(invalid_err)?;
-                            let (a, b) = (duplet[0], duplet[1]);
                             out.extend_from_slice(&[a.wrapping_add(x), b]);
                         },
                         [mut x @ 0x90..=0x9f, p, ..] => {
                             // output (first pixel last duplet) + x, then p
                             x &= 0x0f;
                             c += 2;
-                            let &a = out
-                                .get(out.len() - 2)
+                            let a = pixel_back(&out, 2)
                                 .ok_or_else(invalid_err)?;
                             out.extend_from_slice(&[a.wrapping_add(x), p]);
                         },
@@ -317,10 +299,8 @@ where
                             let x = (xy & 0xf0) >> 4;
                             let y = xy & 0x0f;
                             c += 2;
-                            let duplet = out
-                                .get(out.len() - 2..out.len())
+                            let (a, b) = last_duplet(&out)
                                 .ok_or_else(invalid_err)?;
-                            let (a, b) = (duplet[0], duplet[1]);
                             out.extend_from_slice(&[
                                 a.wrapping_add(x),
                                 b.wrapping_add(y),
@@ -331,10 +311,8 @@ where
                             let x = (xy & 0xf0) >> 4;
                             let y = xy & 0x0f;
                             c += 2;
-                            let duplet = out
-                                .get(out.len() - 2..out.len())
+                            let (a, b) = last_duplet(&out)
                                 .ok_or_else(invalid_err)?;
-                            let (a, b) = (duplet[0], duplet[1]);
                             out.extend_from_slice(&[
                                 a.wrapping_add(x),
                                 b.wrapping_sub(y),
@@ -344,18 +322,15 @@ where
                             // repeat last duplet, but subtract x from first
                             x &= 0x0f;
                             c += 1;
-                            let duplet = out
-                                .get(out.len() - 2..out.len())
+                            let (a, b) = last_duplet(&out)
                                 .ok_or_else(invalid_err)?;
-                            let (a, b) = (duplet[0], duplet[1]);
                             out.extend_from_slice(&[a.wrapping_sub(x), b]);
                         },
                         [mut x @ 0xd0..=0xdf, p, ..] => {
                             // output (first pixel last duplet) - x, then p
                             x &= 0x0f;
                             c += 2;
-                            let &a = out
-                                .get(out.len() - 2)
+                            let a = pixel_back(&out, 2)
                                 .ok_or_else(invalid_err)?;
                             out.extend_from_slice(&[a.wrapping_sub(x), p]);
                         },
@@ -364,10 +339,8 @@ where
                             let x = (xy & 0xf0) >> 4;
                             let y = xy & 0x0f;
                             c += 2;
-                            let duplet = out
-                                .get(out.len() - 2..out.len())
+                            let (a, b) = last_duplet(&out)
                                 .ok_or_else(invalid_err)?;
-                            let (a, b) = (duplet[0], duplet[1]);
                             out.extend_from_slice(&[
                                 a.wrapping_sub(x),
                                 b.wrapping_add(y),
@@ -378,10 +351,8 @@ where
                             let x = (xy & 0xf0) >> 4;
                             let y = xy & 0x0f;
                             c += 2;
-                            let duplet = out
-                                .get(out.len() - 2..out.len())
+                            let (a, b) = last_duplet(&out)
                                 .ok_or_else(invalid_err)?;
-                            let (a, b) = (duplet[0], duplet[1]);
                             out.extend_from_slice(&[
                                 a.wrapping_sub(x),
                                 b.wrapping_sub(y),
@@ -505,7 +476,9 @@ where
             let oldpos = input.seek(SeekFrom::Current(0)).await?;
             let pchunk: BmpPalette = deserialize_from(input).await?;
             let newpos = input.seek(SeekFrom::Current(0)).await?;
-            let table_size = pchunk.table_size as u64 - (newpos - oldpos);
+            let table_size = (pchunk.table_size as u64)
+                .checked_sub(newpos - oldpos)
+                .ok_or(MhkError::InvalidFormat("bad tBMP palette size"))?;
             let mut colors = vec![0; table_size as usize];
             input.read_exact(&mut colors).await?;
 
@@ -555,6 +528,7 @@ where
                 palette: Some(PaletteBitmap {
                     palette: p,
                     image: data_raw,
+                    transparency: vec![],
                 }),
                 data: colored,
             })
@@ -569,3 +543,284 @@ where
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TBmpCompression {
+    None,
+    Riven,
+    // 24 bits per pixel with no palette, which is never compressed
+    Truecolor,
+}
+
+// one step of the riven command stream, before grouping into commands
+#[derive(Clone, Copy, Debug)]
+enum RivenOp {
+    Literal(u8, u8),
+    // repeat the last duplet this many times
+    Repeat(u8),
+    // a subcommand, up to 3 bytes long
+    Sub([u8; 3], usize),
+}
+
+const NO_MATCH: usize = usize::MAX;
+
+// for each pixel, the nearest earlier pixel starting the same two bytes,
+// so matches are searched only where one can start
+fn match_chains(pixels: &[u8]) -> Vec<usize> {
+    let mut head = vec![NO_MATCH; 1 << 16];
+    let mut prev = vec![NO_MATCH; pixels.len()];
+    for (i, pair) in pixels.windows(2).enumerate() {
+        let key = (pair[0] as usize) << 8 | pair[1] as usize;
+        prev[i] = head[key];
+        head[key] = i;
+    }
+    prev
+}
+
+// longest run, in duplets, matching earlier output, and the distance
+// in pixels back to where it starts
+fn longest_match(pixels: &[u8], chains: &[usize], pos: usize)
+                 -> (usize, usize)
+{
+    // the 0xfc subcommand can copy at most 33 duplets from 1023 pixels back
+    let max_len = (2 * 33).min(pixels.len() - pos);
+    let mut best = (0, 0);
+    let mut start = chains[pos];
+    while start != NO_MATCH && pos - start <= 0x3ff {
+        let m = pos - start;
+        let len = (0..max_len)
+            .take_while(|&k| pixels[pos + k] == pixels[pos + k - m])
+            .count();
+        if len / 2 > best.0 {
+            best = (len / 2, m);
+            if len == max_len {
+                break;
+            }
+        }
+        start = chains[start];
+    }
+    best
+}
+
+fn riven_op(pixels: &[u8], chains: &[usize], pos: usize)
+            -> (RivenOp, usize)
+{
+    let (p1, p2) = (pixels[pos], pixels[pos + 1]);
+    let last = last_duplet(&pixels[..pos]);
+
+    if let Some((a, b)) = last {
+        let repeats = pixels[pos..].chunks_exact(2)
+            .take(0x3f)
+            .take_while(|d| d[0] == a && d[1] == b)
+            .count();
+        if repeats >= 2 {
+            return (RivenOp::Repeat(repeats as u8), 2 * repeats);
+        }
+    }
+
+    let (len, m) = longest_match(pixels, chains, pos);
+    if len >= 2 {
+        let nrm = ((len - 2) << 3) as u8 | 0x4 | (m >> 8) as u8;
+        return (RivenOp::Sub([0xfc, nrm, m as u8], 3), 2 * len);
+    }
+
+    let (a, b) = match last {
+        Some(d) => d,
+        None => return (RivenOp::Literal(p1, p2), 2),
+    };
+
+    // an identical duplet a few duplets back
+    for m in 1..=0xf {
+        if 2 * m > pos {
+            break;
+        }
+        if pixels[pos - 2 * m] == p1 && pixels[pos - 2 * m + 1] == p2 {
+            return (RivenOp::Sub([m as u8, 0, 0], 1), 2);
+        }
+    }
+
+    // small changes to the last duplet
+    let (dx, dy) = (p1.wrapping_sub(a), p2.wrapping_sub(b));
+    let small = |d: u8| d <= 0xf;
+    let small_neg = |d: u8| d.wrapping_neg() <= 0xf;
+    let sub = match (dx, dy) {
+        (0, y) if small(y) => ([0x20 | y, 0, 0], 1),
+        (0, y) if small_neg(y) => ([0x30 | y.wrapping_neg(), 0, 0], 1),
+        (x, 0) if small(x) => ([0x80 | x, 0, 0], 1),
+        (x, 0) if small_neg(x) => ([0xc0 | x.wrapping_neg(), 0, 0], 1),
+        (0, _) => ([0x10, p2, 0], 2),
+        (_, 0) => ([0x40, p1, 0], 2),
+        (x, y) if small(x) && small(y) => ([0xa0, x << 4 | y, 0], 2),
+        (x, y) if small(x) && small_neg(y) => {
+            ([0xb0, x << 4 | y.wrapping_neg(), 0], 2)
+        },
+        (x, y) if small_neg(x) && small(y) => {
+            ([0xe0, x.wrapping_neg() << 4 | y, 0], 2)
+        },
+        (x, y) if small_neg(x) && small_neg(y) => {
+            ([0xf0, x.wrapping_neg() << 4 | y.wrapping_neg(), 0], 2)
+        },
+        _ => return (RivenOp::Literal(p1, p2), 2),
+    };
+    (RivenOp::Sub(sub.0, sub.1), 2)
+}
+
+// pixels must already be padded out to whole rows
+fn write_riven(pixels: &[u8], out: &mut Vec<u8>) {
+    let chains = match_chains(pixels);
+    let mut ops = Vec::new();
+    let mut pos = 0;
+    while pos + 1 < pixels.len() {
+        let (op, used) = riven_op(pixels, &chains, pos);
+        ops.push(op);
+        pos += used;
+    }
+
+    // the first 4 bytes are unknown, so...
+    out.extend_from_slice(&[0; 4]);
+    let mut i = 0;
+    while i < ops.len() {
+        match ops[i] {
+            RivenOp::Repeat(n) => {
+                out.push(0x40 | n);
+                i += 1;
+            },
+            RivenOp::Literal(..) => {
+                let run = ops[i..].iter()
+                    .take(0x3f)
+                    .take_while(|op| matches!(op, RivenOp::Literal(..)))
+                    .count();
+                out.push(run as u8);
+                for op in &ops[i..i + run] {
+                    if let RivenOp::Literal(a, b) = op {
+                        out.extend_from_slice(&[*a, *b]);
+                    }
+                }
+                i += run;
+            },
+            RivenOp::Sub(..) => {
+                let run = ops[i..].iter()
+                    .take(0x3f)
+                    .take_while(|op| matches!(op, RivenOp::Sub(..)))
+                    .count();
+                out.push(0xc0 | run as u8);
+                for op in &ops[i..i + run] {
+                    if let RivenOp::Sub(bytes, len) = op {
+                        out.extend_from_slice(&bytes[..*len]);
+                    }
+                }
+                i += run;
+            },
+        }
+    }
+    out.push(0x00);
+}
+
+// bitmaps are written with 8 bits per pixel, and quantized if they have
+// more than 256 colors, unless TBmpCompression::Truecolor asks to keep
+// every color
+pub fn encode_tbmp(bmp: &Bitmap, compression: TBmpCompression)
+                   -> Result<Vec<u8>>
+{
+    let width = bmp.width as usize;
+    let pixels = width * bmp.height as usize;
+    if bmp.data.len() != pixels {
+        anyhow::bail!(MhkError::InvalidFormat("tBMP size mismatch"));
+    }
+    let primary = match compression {
+        TBmpCompression::None => 0,
+        TBmpCompression::Riven => 4,
+        TBmpCompression::Truecolor => return encode_truecolor(bmp),
+    };
+
+    let pal = bmp.to_palette(256);
+    if pal.image.len() != pixels {
+        anyhow::bail!(MhkError::InvalidFormat("tBMP size mismatch"));
+    }
+
+    // rows are padded out to 4 bytes
+    let stride = (width + 3) & !3;
+    if stride > u16::MAX as usize {
+        anyhow::bail!(MhkError::InvalidFormat("tBMP too wide"));
+    }
+
+    let mut out = Vec::new();
+    serialize_into(&mut out, &BmpHeader {
+        width: bmp.width,
+        height: bmp.height,
+        bytes_per_row: stride as u16,
+        compression_flags: 2 | (primary << 8),
+    })?;
+
+    // always write a full table, in BGR, but only count the colors used
+    serialize_into(&mut out, &BmpPalette {
+        table_size: 4 + 256 * 3,
+        bits_per_color: 24,
+        color_count: (pal.palette.len().clamp(1, 256) - 1) as u8,
+    })?;
+    for i in 0..256 {
+        let c = pal.palette.get(i).cloned()
+            .unwrap_or(palette::Srgb::new(0, 0, 0));
+        out.extend_from_slice(&[c.blue, c.green, c.red]);
+    }
+
+    let mut rows = Vec::with_capacity(stride * bmp.height as usize);
+    if width > 0 {
+        for row in pal.image.chunks(width) {
+            rows.extend_from_slice(row);
+            rows.resize(rows.len() + stride - width, 0);
+        }
+    }
+
+    if compression == TBmpCompression::Riven {
+        write_riven(&rows, &mut out);
+    } else {
+        out.extend_from_slice(&rows);
+    }
+    Ok(out)
+}
+
+// 24 bits per pixel, in BGR, with no palette
+fn encode_truecolor(bmp: &Bitmap) -> Result<Vec<u8>> {
+    let width = bmp.width as usize;
+    let stride = (width * 3 + 3) & !3;
+    if stride > u16::MAX as usize {
+        anyhow::bail!(MhkError::InvalidFormat("tBMP too wide"));
+    }
+
+    let mut out = Vec::with_capacity(8 + stride * bmp.height as usize);
+    serialize_into(&mut out, &BmpHeader {
+        width: bmp.width,
+        height: bmp.height,
+        bytes_per_row: stride as u16,
+        compression_flags: 4,
+    })?;
+    if width > 0 {
+        for row in bmp.data.chunks(width) {
+            for c in row {
+                out.extend_from_slice(&[c.blue, c.green, c.red]);
+            }
+            out.resize(out.len() + stride - width * 3, 0);
+        }
+    }
+    Ok(out)
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, I> FormatWrite<Fi, TBmp, I, Bitmap> for MhkFormat
+where
+    Fi: Format<TBmp, I, Bitmap> + 'static,
+    I: AsyncRead + AsyncSeek + Unpin,
+{
+    async fn convert(&self, fmti: &Fi, res: &TBmp, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        if let Some(data) = MhkFormat::passthrough(fmti, input).await? {
+            return Ok(data);
+        }
+        let bmp = fmti.parse(res, input).await?;
+        encode_tbmp(&bmp, TBmpCompression::Riven)
+    }
+    fn version(&self, _res: &TBmp) -> u32 {
+        VERSION
+    }
+}
@@ -1,10 +1,12 @@
-use crate::{Cursor, ResourceType, Format};
-use crate::mhk::{MhkFormat, deserialize_le_from};
+use crate::{Cursor, ResourceType, Format, FormatWrite};
+use crate::mhk::{MhkFormat, MhkError, deserialize_le_from};
 
 use anyhow::Result;
 use serde_derive::{Deserialize, Serialize};
 use smol::io::{AsyncRead, AsyncReadExt};
 
+const VERSION: u32 = 1;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct TCur;
 
@@ -39,19 +41,30 @@ where
     async fn parse(&self, _res: &TCur, input: &mut I) -> Result<Cursor> {
         let mut header: CurHeader = deserialize_le_from(input).await?;
         if header.headersize != 40 {
-            anyhow::bail!("bad cursor header");
+            anyhow::bail!(MhkError::InvalidFormat("bad cursor header"));
         }
         if header.planes > 1 {
-            anyhow::bail!("bad cursor header");
+            anyhow::bail!(MhkError::InvalidFormat("bad cursor header"));
         }
         if header.compression != 0 {
-            anyhow::bail!("can't decompress cursor");
+            anyhow::bail!(MhkError::InvalidFormat("can't decompress cursor"));
         }
 
         header.height = header.height / 2;
         if header.colors == 0 {
-            header.colors = 1 << header.bpp;
+            header.colors = match header.bpp {
+                1 | 8 => 1 << header.bpp,
+                _ => anyhow::bail!(MhkError::InvalidFormat(
+                    "unsupported cursor bpp")),
+            };
+        }
+        if header.colors > 256 {
+            anyhow::bail!(MhkError::InvalidFormat("too many cursor colors"));
+        }
+        if header.width > 256 || header.height > 256 {
+            anyhow::bail!(MhkError::InvalidFormat("cursor too large"));
         }
+        let pixels = header.width * header.height;
 
         // read palette
         let mut palette = Vec::with_capacity(header.colors as usize);
@@ -62,11 +75,12 @@ where
         }
 
         // read XOR map
-        let mut xor = vec![0; (header.width * header.height) as usize];
+        let mut xor = vec![0; pixels as usize];
         match header.bpp {
             1 => {
                 if header.width % 8 != 0 {
-                    anyhow::bail!("packed cursor pixels not aligned");
+                    anyhow::bail!(MhkError::InvalidFormat(
+                        "packed cursor pixels not aligned"));
                 }
                 let packed = xor.len() / 8;
                 let start = xor.len() - packed;
@@ -80,7 +94,8 @@ where
                 }
             }
             8 => input.read_exact(&mut xor).await?,
-            _ => anyhow::bail!("cursor bpp {:?} unsupported", header.bpp),
+            _ => anyhow::bail!(MhkError::InvalidFormat(
+                "unsupported cursor bpp")),
         }
 
         // read AND map
@@ -89,12 +104,12 @@ where
         input.read_exact(&mut and).await?;
 
         // combine XOR and palette and AND into an image
-        let mut image = Vec::with_capacity((header.width * header.height) as usize);
+        let mut image = Vec::with_capacity(pixels as usize);
         for y in 0..header.height {
             for x in 0..header.width {
                 let i = ((header.height - y - 1) * header.width + x) as usize;
                 if xor[i] as usize >= palette.len() {
-                    anyhow::bail!("bad cursor data");
+                    anyhow::bail!(MhkError::InvalidFormat("bad cursor data"));
                 }
                 let mut alpha: u8 = 255;
                 if and[i / 8] & (1 << (7 - x % 8)) > 0 {
@@ -115,3 +130,21 @@ where
         })
     }
 }
+
+// there's no tCUR encoder, so the only way in is copying another archive
+#[async_trait::async_trait(?Send)]
+impl<I> FormatWrite<MhkFormat, TCur, I, Cursor> for MhkFormat
+where
+    I: AsyncRead + Unpin,
+{
+    async fn convert(&self, _fmti: &MhkFormat, _res: &TCur, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data).await?;
+        Ok(data)
+    }
+    fn version(&self, _res: &TCur) -> u32 {
+        VERSION
+    }
+}
@@ -0,0 +1,11 @@
+use crate::{Movie, ResourceType};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TMov;
+
+impl ResourceType for TMov {
+    type Data = Movie;
+    fn name(&self) -> &str {
+        "tMOV"
+    }
+}
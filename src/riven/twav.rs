@@ -0,0 +1,11 @@
+use crate::{Sound, ResourceType};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TWav;
+
+impl ResourceType for TWav {
+    type Data = Sound;
+    fn name(&self) -> &str {
+        "tWAV"
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{ResourceMapList, ResourceType, Resources};
+use super::{
+    Command, RivenFormatAll, Stack, TBlst, TBmp, TCard, TCur, TFlst, THspt,
+    TMlst, TMov, TPlst, TSfxe, TSlst,
+};
+
+use std::collections::HashSet;
+
+use anyhow::Result;
+
+// a resource, or one record inside a per-card list like PLST. records are
+// found by their index field, except for HSPT which goes by blst-id
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub stack: Stack,
+    pub typ: String,
+    pub id: u16,
+    pub record: Option<u16>,
+}
+
+impl Location {
+    fn new(stack: Stack, typ: &str, id: u16, record: Option<u16>) -> Self {
+        Location {
+            stack,
+            typ: typ.to_owned(),
+            id,
+            record,
+        }
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use crate::Stack as _;
+        write!(f, "{} {} {}", self.stack.name(), self.typ, self.id)?;
+        if let Some(record) = self.record {
+            write!(f, " #{}", record)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub from: Location,
+    // the field or command holding the reference, like "bitmap-id"
+    pub via: &'static str,
+    pub to: Location,
+}
+
+// every reference between resources in one stack
+#[derive(Debug, Clone)]
+pub struct CrossReference {
+    references: Vec<Reference>,
+    existing: HashSet<Location>,
+}
+
+impl CrossReference {
+    pub async fn build<M>(resources: &mut Resources<M>, stack: Stack)
+                          -> Result<Self>
+    where
+        M: ResourceMapList<Stack = Stack>,
+        M::Format: RivenFormatAll<M::Handle>,
+    {
+        let mut xref = CrossReference {
+            references: vec![],
+            existing: HashSet::new(),
+        };
+        // things only referred to, never opened
+        for typ in &[TBmp.name(), TMov.name(), TSfxe.name()] {
+            xref.exists(resources, stack, typ).await?;
+        }
+        // cursors live in the executable
+        xref.exists(resources, Stack::Exe, TCur.name()).await?;
+
+        for id in xref.exists(resources, stack, TCard.name()).await? {
+            let card = resources.open(stack, TCard, id).await?;
+            let from = Location::new(stack, TCard.name(), id, None);
+            for commands in card.script.values() {
+                xref.script(&from, commands);
+            }
+        }
+
+        for id in xref.exists(resources, stack, THspt.name()).await? {
+            let hspt = resources.open(stack, THspt, id).await?;
+            for h in hspt.iter() {
+                let from = Location::new(stack, THspt.name(), id,
+                                         Some(h.blst_id));
+                xref.existing.insert(from.clone());
+                xref.add(&from, "mouse-cursor",
+                         Location::new(Stack::Exe, TCur.name(),
+                                       h.mouse_cursor, None));
+                for commands in h.script.values() {
+                    xref.script(&from, commands);
+                }
+            }
+        }
+
+        for id in xref.exists(resources, stack, TPlst.name()).await? {
+            for p in resources.open(stack, TPlst, id).await?.iter() {
+                let from = xref.record(stack, TPlst.name(), id, p.index);
+                xref.add(&from, "bitmap-id",
+                         Location::new(stack, TBmp.name(), p.bitmap_id, None));
+            }
+        }
+
+        for id in xref.exists(resources, stack, TMlst.name()).await? {
+            for m in resources.open(stack, TMlst, id).await?.iter() {
+                let from = xref.record(stack, TMlst.name(), id, m.index);
+                xref.add(&from, "movie-id",
+                         Location::new(stack, TMov.name(), m.movie_id, None));
+            }
+        }
+
+        for id in xref.exists(resources, stack, TFlst.name()).await? {
+            for e in resources.open(stack, TFlst, id).await?.iter() {
+                let from = xref.record(stack, TFlst.name(), id, e.index);
+                xref.add(&from, "sfxe-id",
+                         Location::new(stack, TSfxe.name(), e.sfxe_id, None));
+            }
+        }
+
+        for id in xref.exists(resources, stack, TBlst.name()).await? {
+            for b in resources.open(stack, TBlst, id).await?.iter() {
+                let from = xref.record(stack, TBlst.name(), id, b.index);
+                xref.add(&from, "hotspot-id",
+                         Location::new(stack, THspt.name(), id,
+                                       Some(b.hotspot_id)));
+            }
+        }
+
+        for id in xref.exists(resources, stack, TSlst.name()).await? {
+            for s in resources.open(stack, TSlst, id).await?.iter() {
+                xref.record(stack, TSlst.name(), id, s.index);
+            }
+        }
+
+        Ok(xref)
+    }
+
+    // list a type, remembering that those ids exist
+    async fn exists<M>(&mut self, resources: &mut Resources<M>, stack: Stack,
+                       typ: &str) -> Result<Vec<u16>>
+    where
+        M: ResourceMapList<Stack = Stack>,
+    {
+        let ids = resources.list(stack, typ).await?;
+        for id in &ids {
+            self.existing.insert(Location::new(stack, typ, *id, None));
+        }
+        Ok(ids)
+    }
+
+    fn record(&mut self, stack: Stack, typ: &str, id: u16, index: u16)
+              -> Location
+    {
+        let loc = Location::new(stack, typ, id, Some(index));
+        self.existing.insert(loc.clone());
+        loc
+    }
+
+    fn add(&mut self, from: &Location, via: &'static str, to: Location) {
+        self.references.push(Reference {
+            from: from.clone(),
+            via,
+            to,
+        });
+    }
+
+    // record lists are per card, and share the card's id
+    fn script(&mut self, from: &Location, commands: &[Command]) {
+        let stack = from.stack;
+        let card = from.id;
+        for cmd in commands {
+            let (via, typ, id, record) = match cmd {
+                Command::DrawBmp { tbmp_id, .. } => {
+                    ("draw-bmp", TBmp.name(), *tbmp_id, None)
+                }
+                Command::GotoCard { id } => {
+                    ("goto-card", TCard.name(), *id, None)
+                }
+                Command::ActivatePlst { record } => {
+                    ("activate-plst", TPlst.name(), card, Some(*record))
+                }
+                Command::ActivateSlst { record } => {
+                    ("activate-slst", TSlst.name(), card, Some(*record))
+                }
+                Command::ActivateMlst { record, .. } => {
+                    ("activate-mlst", TMlst.name(), card, Some(*record))
+                }
+                Command::ActivateMlstAndPlay { record } => (
+                    "activate-mlst-and-play", TMlst.name(), card,
+                    Some(*record),
+                ),
+                Command::Conditional { branches, .. } => {
+                    for branch in branches.values() {
+                        self.script(from, branch);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            self.add(from, via, Location::new(stack, typ, id, record));
+        }
+    }
+
+    pub fn references(&self) -> &[Reference] {
+        &self.references
+    }
+
+    // everything pointing at a resource, or at any record inside it
+    pub fn referencing<'a>(&'a self, stack: Stack, typ: &'a str, id: u16)
+                           -> impl Iterator<Item = &'a Reference>
+    {
+        self.references.iter().filter(move |r| {
+            r.to.stack == stack && r.to.typ == typ && r.to.id == id
+        })
+    }
+
+    // references to resources or records that aren't there
+    pub fn dangling(&self) -> impl Iterator<Item = &Reference> {
+        self.references.iter()
+            .filter(move |r| !self.existing.contains(&r.to))
+    }
+}
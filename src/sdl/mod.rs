@@ -60,6 +60,11 @@ impl Sdl {
                 }
             }
 
+            // once a frame, for work that can wait
+            if !game.handle_event(&mut gamectx, Event::Idle).await? {
+                break 'running;
+            }
+
             smol::Timer::new(std::time::Duration::new(0, 1_000_000_000u32 / 60)).await;
         }
         Ok(())
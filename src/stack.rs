@@ -1,4 +1,8 @@
 pub trait Stack: Sized + std::cmp::Eq + std::hash::Hash {
     fn name(&self) -> &str;
     fn all() -> Vec<Self>;
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::all().into_iter().find(|s| s.name() == name)
+    }
 }
@@ -0,0 +1,193 @@
+use crate::bitmap::le_u16;
+use crate::{Bitmap, Format, FormatWrite, PaletteBitmap};
+
+use anyhow::Result;
+use smol::io::{AsyncRead, AsyncReadExt};
+
+const VERSION: u32 = 1;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct TgaFormat;
+
+#[async_trait::async_trait(?Send)]
+impl<R, I> Format<R, I, Bitmap> for TgaFormat
+where
+    I: AsyncRead + Unpin,
+{
+    fn extension(&self, _res: &R) -> Option<&str> {
+        Some(".tga")
+    }
+    async fn parse(&self, _res: &R, input: &mut I) -> Result<Bitmap> {
+        let mut buf = Vec::with_capacity(1 << 16);
+        input.read_to_end(&mut buf).await?;
+        if buf.len() < 18 {
+            anyhow::bail!("unexpected end of image");
+        }
+
+        let id_len = buf[0] as usize;
+        let image_type = buf[2];
+        let cmap_first = le_u16(&buf, 3)? as usize;
+        let cmap_len = le_u16(&buf, 5)? as usize;
+        let cmap_bits = buf[7] as usize;
+        let width = le_u16(&buf, 12)?;
+        let height = le_u16(&buf, 14)?;
+        let bpp = buf[16] as usize;
+        let descriptor = buf[17];
+
+        // the colormap is there even for color images, if it says so
+        let mut pos = 18 + id_len;
+        let cmap_size = if buf[1] == 1 { cmap_len * cmap_bits / 8 } else { 0 };
+        let cmap = buf.get(pos..pos + cmap_size)
+            .ok_or_else(|| anyhow::anyhow!("unexpected end of image"))?;
+        pos += cmap_size;
+
+        let step = bpp / 8;
+        let valid = match image_type & !8 {
+            1 => bpp == 8 && buf[1] == 1,
+            2 => bpp == 24 || bpp == 32,
+            3 => bpp == 8,
+            _ => false,
+        };
+        if !valid {
+            anyhow::bail!("unsupported tga type {} at {} bpp", image_type, bpp);
+        }
+
+        let w = width as usize;
+        let h = height as usize;
+        let mut pixels = if image_type & 8 != 0 {
+            unpack_rle(buf.get(pos..).unwrap_or(&[]), w * h, step)?
+        } else {
+            buf.get(pos..pos + w * h * step)
+                .ok_or_else(|| anyhow::anyhow!("unexpected end of image"))?
+                .to_owned()
+        };
+
+        // rows are stored bottom-up and left-to-right unless flagged
+        let stride = w * step;
+        if descriptor & 0x10 != 0 {
+            for row in pixels.chunks_mut(stride.max(1)) {
+                let mut px: Vec<&[u8]> = row.chunks(step).collect();
+                px.reverse();
+                let flipped = px.concat();
+                row.copy_from_slice(&flipped);
+            }
+        }
+        let mut rows: Vec<&[u8]> = pixels.chunks(stride.max(1)).collect();
+        if descriptor & 0x20 == 0 {
+            rows.reverse();
+        }
+
+        match image_type & !8 {
+            1 => {
+                let palette = match cmap_bits {
+                    24 | 32 => cmap.chunks(cmap_bits / 8)
+                        .map(|c| palette::Srgb::new(c[2], c[1], c[0]))
+                        .take(256)
+                        .collect(),
+                    _ => anyhow::bail!("unsupported tga colormap depth {}",
+                                       cmap_bits),
+                };
+                let mut image = Vec::with_capacity(w * h);
+                for i in rows.concat() {
+                    match (i as usize).checked_sub(cmap_first) {
+                        Some(i) => image.push(i as u8),
+                        None => anyhow::bail!(
+                            "palette index {} is out of range", i),
+                    }
+                }
+                Bitmap::from_palette(width, height, PaletteBitmap {
+                    palette,
+                    image,
+                    transparency: vec![],
+                })
+            }
+            t => {
+                let data = rows.concat().chunks(step)
+                    .map(|c| match t {
+                        3 => palette::Srgb::new(c[0], c[0], c[0]),
+                        _ => palette::Srgb::new(c[2], c[1], c[0]),
+                    })
+                    .collect();
+                Ok(Bitmap {
+                    width,
+                    height,
+                    palette: None,
+                    data,
+                })
+            }
+        }
+    }
+}
+
+fn unpack_rle(data: &[u8], count: usize, step: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(count * step);
+    let mut pos = 0;
+    let eof = || anyhow::anyhow!("unexpected end of image");
+    while out.len() < count * step {
+        let header = *data.get(pos).ok_or_else(eof)?;
+        pos += 1;
+        let n = (header & 0x7f) as usize + 1;
+        if header & 0x80 != 0 {
+            let px = data.get(pos..pos + step).ok_or_else(eof)?;
+            pos += step;
+            for _ in 0..n {
+                out.extend(px);
+            }
+        } else {
+            out.extend(data.get(pos..pos + n * step).ok_or_else(eof)?);
+            pos += n * step;
+        }
+    }
+    // packets may run past the end of the image
+    out.truncate(count * step);
+    Ok(out)
+}
+
+#[async_trait::async_trait(?Send)]
+impl<Fi, R, I> FormatWrite<Fi, R, I, Bitmap> for TgaFormat
+where
+    Fi: Format<R, I, Bitmap>,
+    I: AsyncRead + Unpin,
+{
+    async fn convert(&self, fmti: &Fi, res: &R, input: &mut I)
+                     -> Result<Vec<u8>>
+    {
+        let bmp = fmti.parse(res, input).await?;
+        let (cmap_type, image_type, colors, bpp) = match &bmp.palette {
+            Some(pal) => (1, 1, pal.palette.len(), 8),
+            None => (0, 2, 0, 24),
+        };
+
+        let mut buf = Vec::with_capacity(
+            18 + colors * 3 + bmp.width as usize * bmp.height as usize * 3,
+        );
+        buf.extend(&[0, cmap_type, image_type]);
+        buf.extend(&0u16.to_le_bytes());
+        buf.extend(&(colors as u16).to_le_bytes());
+        buf.push(if colors > 0 { 24 } else { 0 });
+        buf.extend(&0u16.to_le_bytes());
+        buf.extend(&0u16.to_le_bytes());
+        buf.extend(&bmp.width.to_le_bytes());
+        buf.extend(&bmp.height.to_le_bytes());
+        // top-left origin
+        buf.extend(&[bpp, 0x20]);
+
+        match &bmp.palette {
+            Some(pal) => {
+                for c in &pal.palette {
+                    buf.extend(&[c.blue, c.green, c.red]);
+                }
+                buf.extend(&pal.image);
+            }
+            None => {
+                for c in &bmp.data {
+                    buf.extend(&[c.blue, c.green, c.red]);
+                }
+            }
+        }
+        Ok(buf)
+    }
+    fn version(&self, _res: &R) -> u32 {
+        VERSION
+    }
+}
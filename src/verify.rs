@@ -0,0 +1,135 @@
+use crate::diff::diff_json;
+use crate::{Bitmap, Cursor, Movie, Raw, RawData, Record, Sound};
+
+// compare a resource to one that went through another format and back,
+// describing the first difference, if any
+pub trait Compare {
+    fn compare(&self, other: &Self) -> Option<String>;
+}
+
+impl<T> Compare for Record<T>
+where
+    T: PartialEq + serde::Serialize,
+{
+    fn compare(&self, other: &Self) -> Option<String> {
+        if self.0 == other.0 {
+            return None;
+        }
+        // go through json to find where they differ
+        let mut out = vec![];
+        if let (Ok(a), Ok(b)) =
+            (serde_json::to_value(&self.0), serde_json::to_value(&other.0))
+        {
+            diff_json("", &a, &b, &mut out);
+        }
+        Some(out.into_iter().next()
+             .unwrap_or_else(|| "records differ".to_owned()))
+    }
+}
+
+fn compare_size(a: (u16, u16), b: (u16, u16)) -> Option<String> {
+    if a != b {
+        Some(format!("size {}x{} became {}x{}", a.0, a.1, b.0, b.1))
+    } else {
+        None
+    }
+}
+
+fn position(width: u16, i: usize) -> (usize, usize) {
+    (i % width as usize, i / width as usize)
+}
+
+impl Compare for Bitmap {
+    fn compare(&self, other: &Self) -> Option<String> {
+        let size = compare_size(
+            (self.width, self.height), (other.width, other.height));
+        if size.is_some() {
+            return size;
+        }
+        if let Some(i) = self.data.iter().zip(other.data.iter())
+            .position(|(a, b)| a != b)
+        {
+            return Some(format!("pixel {:?} differs",
+                                position(self.width, i)));
+        }
+        // the colors can match while the palette doesn't
+        let (pa, pb) = match (&self.palette, &other.palette) {
+            (None, _) => return None,
+            (Some(_), None) => return Some("palette was lost".to_owned()),
+            (Some(pa), Some(pb)) => (pa, pb),
+        };
+        if let Some(i) = pa.image.iter().zip(pb.image.iter())
+            .position(|(a, b)| a != b)
+        {
+            return Some(format!("palette index at {:?} differs",
+                                position(self.width, i)));
+        }
+        if let Some(i) = pa.palette.iter().zip(pb.palette.iter())
+            .position(|(a, b)| a != b)
+        {
+            return Some(format!("palette entry {} differs", i));
+        }
+        if pa.transparency != pb.transparency {
+            return Some("palette transparency differs".to_owned());
+        }
+        None
+    }
+}
+
+impl Compare for Cursor {
+    fn compare(&self, other: &Self) -> Option<String> {
+        let size = compare_size(
+            (self.width, self.height), (other.width, other.height));
+        if size.is_some() {
+            return size;
+        }
+        if self.hotspot != other.hotspot {
+            return Some(format!("hotspot {:?} became {:?}",
+                                self.hotspot, other.hotspot));
+        }
+        // fully transparent pixels match whatever their color
+        if let Some(i) = self.data.iter().zip(other.data.iter())
+            .position(|(a, b)| a != b && (a.alpha != 0 || b.alpha != 0))
+        {
+            return Some(format!("pixel {:?} differs",
+                                position(self.width, i)));
+        }
+        None
+    }
+}
+
+fn compare_bytes(a: &[u8], b: &[u8]) -> Option<String> {
+    if let Some(i) = a.iter().zip(b).position(|(x, y)| x != y) {
+        return Some(format!("byte {} differs", i));
+    }
+    if a.len() != b.len() {
+        return Some(format!("length {} became {}", a.len(), b.len()));
+    }
+    None
+}
+
+impl Compare for Sound {
+    fn compare(&self, other: &Self) -> Option<String> {
+        compare_bytes(self.bytes(), other.bytes())
+    }
+}
+
+impl Compare for Movie {
+    fn compare(&self, other: &Self) -> Option<String> {
+        compare_bytes(self.bytes(), other.bytes())
+    }
+}
+
+impl Compare for Raw {
+    fn compare(&self, other: &Self) -> Option<String> {
+        compare_bytes(self.bytes(), other.bytes())
+    }
+}
+
+#[derive(Debug)]
+pub struct Mismatch<S> {
+    pub stack: S,
+    pub typ: String,
+    pub id: u16,
+    pub problem: String,
+}
@@ -0,0 +1,672 @@
+use moiety::mhk::testing::ResourceBuilder;
+use moiety::mhk::MhkFormat;
+use moiety::riven::*;
+use moiety::{
+    BincodeFormat, Bitmap, BmpFormat, CurFormat, Cursor, Format, FormatWrite,
+    PngCursorFormat, PngFormat, PpmFormat, Record, TgaFormat,
+};
+
+use smol::io::Cursor as Input;
+
+// convert MHK data into another format, then parse it back out
+fn through<R, F>(fmt: F, res: R, data: Vec<u8>) -> R::Data
+where
+    R: moiety::ResourceType,
+    MhkFormat: Format<R, Input<Vec<u8>>, R::Data>,
+    F: FormatWrite<MhkFormat, R, Input<Vec<u8>>, R::Data>,
+{
+    smol::block_on(async {
+        let mut input = Input::new(data);
+        let out = fmt.convert(&MhkFormat::default(), &res, &mut input)
+            .await.unwrap();
+        fmt.parse(&res, &mut Input::new(out)).await.unwrap()
+    })
+}
+
+#[test]
+fn bincode_record() {
+    let mut b = ResourceBuilder::new();
+    b.i16(3).u16(0).u16(1);
+    b.u16(6).u16(2);
+    b.u16(39).u16_table(&[1]);
+    b.u16(8).u16_table(&[4, 1]);
+    b.u16(1).u16(1).u16(2).u16_table(&[12]);
+    let data = b.finish();
+    let card: Record<Card> = through(BincodeFormat, TCard, data.clone());
+    let expected =
+        moiety::mhk::testing::parse_bytes(TCard, &data).unwrap();
+    assert_eq!(card, expected);
+}
+
+#[test]
+fn bincode_bitmap() {
+    let mut b = ResourceBuilder::new();
+    b.u16(2).u16(2).u16(4).u16(2 | 1 << 3);
+    b.u16(4 + 3 * 2).u8(24).u8(2);
+    b.bytes(&[0, 0, 255, 255, 0, 0]);
+    b.bytes(&[0, 1, 9, 9, 1, 0, 9, 9]);
+    let bmp: Bitmap = through(BincodeFormat, TBmp, b.finish());
+    assert_eq!((bmp.width, bmp.height), (2, 2));
+    assert_eq!(bmp.palette.unwrap().image, vec![0, 1, 1, 0]);
+    assert_eq!(bmp.data[0], palette::Srgb::new(255, 0, 0));
+    assert_eq!(bmp.data[1], palette::Srgb::new(0, 0, 255));
+}
+
+#[test]
+fn bincode_cursor() {
+    let mut data = Vec::new();
+    // 8x1, 1 bit, hotspot (1, 0), little endian
+    for v in &[1u16, 0] {
+        data.extend_from_slice(&v.to_le_bytes());
+    }
+    for v in &[40u32, 8, 2] {
+        data.extend_from_slice(&v.to_le_bytes());
+    }
+    for v in &[1u16, 1] {
+        data.extend_from_slice(&v.to_le_bytes());
+    }
+    data.extend_from_slice(&[0; 24]);
+    // palette, XOR map, AND map
+    data.extend_from_slice(&[0, 0, 0, 0, 255, 255, 255, 0]);
+    data.extend_from_slice(&[0b1010_0000, 0b0000_1111]);
+
+    let expected = moiety::mhk::testing::parse_bytes(TCur, &data).unwrap();
+    let cur: Cursor = through(BincodeFormat, TCur, data);
+    assert_eq!((cur.width, cur.height), (8, 1));
+    assert_eq!(cur.hotspot, (1, 0));
+    assert_eq!(cur.data, expected.data);
+}
+
+#[test]
+fn bincode_rejects_bad_sizes() {
+    type Cached = (u16, u16, Option<(Vec<u8>, Vec<u8>, Vec<u8>)>, Vec<u8>);
+    let parse = |bmp: Cached| -> anyhow::Result<Bitmap> {
+        let mut data = b"MOIB".to_vec();
+        data.extend_from_slice(&BincodeFormat::VERSION.to_le_bytes());
+        data.extend(bincode::serialize(&bmp).unwrap());
+        smol::block_on(BincodeFormat.parse(&TBmp, &mut Input::new(data)))
+    };
+    assert!(parse((2, 1, None, vec![1; 6])).is_ok());
+    // partial pixel, and too few pixels
+    assert!(parse((2, 1, None, vec![1; 5])).is_err());
+    assert!(parse((2, 2, None, vec![1; 6])).is_err());
+    // partial palette entry, and too few indices
+    assert!(parse((2, 1, Some((vec![1; 4], vec![0; 2], vec![])), vec![]))
+            .is_err());
+    assert!(parse((2, 1, Some((vec![1; 3], vec![0; 1], vec![])), vec![]))
+            .is_err());
+}
+
+#[test]
+fn bincode_rejects_old_files() {
+    let mut data = b"MOIB".to_vec();
+    data.extend_from_slice(&1u32.to_le_bytes());
+    data.extend(bincode::serialize(&(2u16, 1u16, vec![1u8; 6])).unwrap());
+    let err = smol::block_on(BincodeFormat.parse(&TBmp, &mut Input::new(data)))
+        .map(|_: Bitmap| ())
+        .unwrap_err();
+    assert!(err.to_string().contains("version 1"), "{}", err);
+}
+
+#[test]
+fn bincode_cache_version() {
+    use moiety::filesystem::{FilesystemWrite, LocalFilesystem};
+
+    let dir = std::env::temp_dir()
+        .join(format!("moiety-cache-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut fs = LocalFilesystem::new(&dir);
+    smol::block_on(async {
+        assert!(BincodeFormat::check_cache(&mut fs).await.is_err());
+        BincodeFormat::mark_cache(&mut fs).await.unwrap();
+        BincodeFormat::check_cache(&mut fs).await.unwrap();
+        fs.write(&["version"], b"0").await.unwrap();
+        assert!(BincodeFormat::check_cache(&mut fs).await.is_err());
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn direct_map_index() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::{
+        CurFormat, DirectMap, JsonFormat, MhkWriter, MixedFormat, PngFormat,
+        RawFormat, ResourceMapWrite, Resources,
+    };
+
+    let dir = std::env::temp_dir()
+        .join(format!("moiety-direct-{}", std::process::id()));
+    let fmt = MixedFormat::new(PngFormat, CurFormat::default(),
+                               JsonFormat(false))
+        .with_sound(RawFormat(".wav"))
+        .with_movie(RawFormat(".mov"))
+        .with_raw(RawFormat(".bin"));
+
+    let mut b = ResourceBuilder::new();
+    b.u16(1).u16(1).u16(10).u16(0).u16(0).u16(608).u16(392);
+    let plst = b.finish();
+    let mut map = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+    smol::block_on(async {
+        map.write_raw(Stack::J, "PLST", 1, "", &plst).await.unwrap();
+        map.write_raw(Stack::J, "PLST", 7, "", &plst).await.unwrap();
+        map.write_raw(Stack::T, "PLST", 3, "", &plst).await.unwrap();
+        map.write_raw(Stack::J, "tWAV", 2, "", b"RIFF").await.unwrap();
+        // nothing knows this type, so it goes out untouched
+        map.write_raw(Stack::J, "XXXX", 4, "", b"data").await.unwrap();
+        let mut rs = Resources::new(map);
+        let mut outrs = Resources::new(
+            DirectMap::new(LocalFilesystem::new(&dir), fmt.clone()));
+        let report = rs.write_all_to(&mut outrs, &write_registry(), 1, |_| {})
+            .await.unwrap();
+        assert_eq!(report.written, 5);
+        outrs.flush().await.unwrap();
+        assert_eq!(std::fs::read(dir.join("jspit/XXXX/00004.bin")).unwrap(),
+                   b"data");
+
+        // a fresh map finds everything through the index
+        let mut direct = DirectMap::new(LocalFilesystem::new(&dir), fmt);
+        assert_eq!(direct.extension(Stack::J, "PLST", 7).await.unwrap(),
+                   Some(".json".to_owned()));
+        assert_eq!(direct.extension(Stack::J, "tWAV", 2).await.unwrap(),
+                   Some(".wav".to_owned()));
+        assert_eq!(direct.extension(Stack::J, "PLST", 2).await.unwrap(),
+                   None);
+        let mut back = Resources::new(direct);
+        assert_eq!(back.list(Stack::J, "PLST").await.unwrap(), vec![1, 7]);
+        assert_eq!(back.list(Stack::T, "PLST").await.unwrap(), vec![3]);
+        assert_eq!(back.list(Stack::J, "CARD").await.unwrap().len(), 0);
+        assert_eq!(back.open(Stack::J, TPlst, 7).await.unwrap(),
+                   rs.open(Stack::J, TPlst, 7).await.unwrap());
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn direct_map_without_index() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::{
+        CurFormat, DirectMap, JsonFormat, MhkWriter, MixedFormat, PngFormat,
+        ResourceMapWrite, Resources,
+    };
+
+    let dir = std::env::temp_dir()
+        .join(format!("moiety-noindex-{}", std::process::id()));
+    let fmt = MixedFormat::new(PngFormat, CurFormat::default(),
+                               JsonFormat(false));
+
+    let mut b = ResourceBuilder::new();
+    b.u16(1).u16(1).u16(10).u16(0).u16(0).u16(608).u16(392);
+    let plst = b.finish();
+    let mut map = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+    smol::block_on(async {
+        map.write_raw(Stack::J, "PLST", 4, "", &plst).await.unwrap();
+        let mut rs = Resources::new(map);
+
+        // single writes are listed without an explicit flush
+        let mut outrs = Resources::new(
+            DirectMap::new(LocalFilesystem::new(&dir), fmt.clone()));
+        rs.write_resource_to(&mut outrs, Stack::J, TPlst, 4).await.unwrap();
+        let mut back = Resources::new(
+            DirectMap::new(LocalFilesystem::new(&dir), fmt.clone()));
+        assert_eq!(back.list(Stack::J, "PLST").await.unwrap(), vec![4]);
+
+        // trees from before the index are scanned instead
+        std::fs::remove_file(dir.join("index.json")).unwrap();
+        let mut old = DirectMap::new(LocalFilesystem::new(&dir), fmt);
+        assert_eq!(old.extension(Stack::J, "PLST", 4).await.unwrap(),
+                   Some(".json".to_owned()));
+        let mut old = Resources::new(old);
+        assert_eq!(old.list(Stack::J, "PLST").await.unwrap(), vec![4]);
+        assert_eq!(old.list(Stack::T, "PLST").await.unwrap().len(), 0);
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn layered_map() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::{
+        CurFormat, DirectMap, JsonFormat, LayeredMap, MhkWriter, MixedFormat,
+        PngFormat, ResourceMapWrite, Resources,
+    };
+
+    let dir = std::env::temp_dir()
+        .join(format!("moiety-layered-{}", std::process::id()));
+    let fmt = MixedFormat::new(PngFormat, CurFormat::default(),
+                               JsonFormat(false));
+    let plst = |bitmap_id: u16| {
+        let mut b = ResourceBuilder::new();
+        b.u16(1).u16(1).u16(bitmap_id).u16(0).u16(0).u16(608).u16(392);
+        b.finish()
+    };
+
+    smol::block_on(async {
+        // write the override as json, the way vahttool exports it
+        let mut edited = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+        edited.write_raw(Stack::J, "PLST", 1, "", &plst(99)).await.unwrap();
+        let mut over = Resources::new(
+            DirectMap::new(LocalFilesystem::new(&dir), fmt.clone()));
+        Resources::new(edited)
+            .write_resource_to(&mut over, Stack::J, TPlst, 1).await.unwrap();
+        over.flush().await.unwrap();
+
+        let mut base = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+        base.write_raw(Stack::J, "PLST", 1, "", &plst(10)).await.unwrap();
+        base.write_raw(Stack::J, "PLST", 2, "", &plst(11)).await.unwrap();
+
+        let over = DirectMap::new(LocalFilesystem::new(&dir), fmt);
+        let mut rs = Resources::new(LayeredMap::new(over, base));
+        assert_eq!(rs.list(Stack::J, "PLST").await.unwrap(), vec![1, 2]);
+        assert_eq!(rs.open(Stack::J, TPlst, 1).await.unwrap()[0].bitmap_id,
+                   99);
+        assert_eq!(rs.open(Stack::J, TPlst, 2).await.unwrap()[0].bitmap_id,
+                   11);
+        assert!(rs.open(Stack::J, TPlst, 3).await.is_err());
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// an override map that can't read anything
+struct BrokenMap(
+    moiety::MhkWriter<moiety::filesystem::LocalFilesystem, Stack>,
+);
+
+#[async_trait::async_trait(?Send)]
+impl moiety::ResourceMap for BrokenMap {
+    type Handle = Input<Vec<u8>>;
+    type Stack = Stack;
+    type Format = MhkFormat;
+
+    fn format(&self) -> &Self::Format {
+        self.0.format()
+    }
+
+    async fn open_raw(&mut self, stack: Stack, typ: &str, id: u16,
+                      ext: &str) -> anyhow::Result<Self::Handle> {
+        let _ = self.0.open_raw(stack, typ, id, ext).await?;
+        anyhow::bail!("disk on fire")
+    }
+}
+
+#[test]
+fn layered_map_errors() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::{LayeredMap, MhkWriter, ResourceMapWrite, Resources};
+
+    let mut b = ResourceBuilder::new();
+    b.u16(1).u16(1).u16(10).u16(0).u16(0).u16(608).u16(392);
+    let plst = b.finish();
+    smol::block_on(async {
+        let mut over = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+        over.write_raw(Stack::J, "PLST", 1, "", &plst).await.unwrap();
+        let mut base = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+        base.write_raw(Stack::J, "PLST", 1, "", &plst).await.unwrap();
+        base.write_raw(Stack::J, "PLST", 2, "", &plst).await.unwrap();
+
+        let mut rs = Resources::new(LayeredMap::new(BrokenMap(over), base));
+        // missing from the override is fine, broken in it is not
+        assert!(rs.open(Stack::J, TPlst, 2).await.is_ok());
+        let err = rs.open(Stack::J, TPlst, 1).await.unwrap_err();
+        assert_eq!(err.to_string(), "disk on fire");
+    });
+}
+
+#[test]
+fn verify_formats() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::{
+        Compare, CurFormat, JsonFormat, MhkWriter, MixedFormat, PngFormat,
+        ResourceMapWrite, Resources,
+    };
+
+    let mut b = ResourceBuilder::new();
+    b.u16(1).u16(1).u16(10).u16(0).u16(0).u16(608).u16(392);
+    let plst = b.finish();
+    let mut b = ResourceBuilder::new();
+    b.u16(2).u16(2).u16(4).u16(2 | 1 << 3);
+    b.u16(4 + 3 * 2).u8(24).u8(2);
+    b.bytes(&[0, 0, 255, 255, 0, 0]);
+    b.bytes(&[0, 1, 9, 9, 1, 0, 9, 9]);
+    let tbmp = b.finish();
+
+    let mut map = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+    smol::block_on(async {
+        map.write_raw(Stack::J, "PLST", 1, "", &plst).await.unwrap();
+        map.write_raw(Stack::J, "tBMP", 2, "", &tbmp).await.unwrap();
+        map.write_raw(Stack::J, "tBMP", 3, "", &[0, 1]).await.unwrap();
+        let mut rs = Resources::new(map);
+
+        let mut calls = 0;
+        let mismatches = verify_registry()
+            .verify_all(&mut rs, &BincodeFormat, |done, total| {
+                calls += 1;
+                assert_eq!((done, total), (calls, 3));
+            }).await.unwrap();
+        assert_eq!(calls, 3);
+        // only the broken bitmap
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!((mismatches[0].typ.as_str(), mismatches[0].id),
+                   ("tBMP", 3));
+
+        let export = MixedFormat::new(PngFormat, CurFormat::default(),
+                                      JsonFormat(false));
+        assert_eq!(rs.verify(&export, Stack::J, TPlst, 1).await.unwrap(),
+                   None);
+        assert_eq!(rs.verify(&BincodeFormat, Stack::J, TBmp, 2).await
+                   .unwrap(), None);
+        assert_eq!(rs.verify(&export, Stack::J, TBmp, 2).await.unwrap(),
+                   None);
+    });
+
+    let bmp = through(BincodeFormat, TBmp, tbmp);
+    let mut moved = bmp.clone();
+    moved.data.swap(0, 1);
+    assert_eq!(bmp.compare(&moved), Some("pixel (0, 0) differs".to_owned()));
+    let mut flat = bmp.clone();
+    flat.palette = None;
+    assert_eq!(bmp.compare(&flat), Some("palette was lost".to_owned()));
+    assert_eq!(flat.compare(&bmp), None);
+
+    let mut b = ResourceBuilder::new();
+    b.u16(1).u16(1).u16(10).u16(0).u16(0).u16(608).u16(392);
+    let plst = through(BincodeFormat, TPlst, b.finish());
+    let mut edited = plst.clone();
+    edited[0].bitmap_id = 11;
+    assert_eq!(plst.compare(&edited),
+               Some("/0/bitmap-id: 10 -> 11".to_owned()));
+}
+
+fn encode_png(width: u32, height: u32, depth: png::BitDepth,
+              palette: &[u8], trns: Option<&[u8]>, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    {
+        let mut enc = png::Encoder::new(&mut out, width, height);
+        enc.set_color(png::ColorType::Indexed);
+        enc.set_depth(depth);
+        enc.set_palette(palette.to_owned());
+        if let Some(trns) = trns {
+            enc.set_trns(trns.to_owned());
+        }
+        enc.write_header().unwrap().write_image_data(data).unwrap();
+    }
+    out
+}
+
+fn parse_png(data: Vec<u8>) -> anyhow::Result<Bitmap> {
+    smol::block_on(PngFormat.parse(&TBmp, &mut Input::new(data)))
+}
+
+#[test]
+fn png_keeps_palette() {
+    let mut b = ResourceBuilder::new();
+    b.u16(2).u16(2).u16(4).u16(2 | 1 << 3);
+    b.u16(4 + 3 * 2).u8(24).u8(2);
+    b.bytes(&[0, 0, 255, 255, 0, 0]);
+    b.bytes(&[0, 1, 9, 9, 1, 0, 9, 9]);
+    let bmp: Bitmap = through(PngFormat, TBmp, b.finish());
+    assert_eq!((bmp.width, bmp.height), (2, 2));
+    let pal = bmp.palette.unwrap();
+    assert_eq!(pal.image, vec![0, 1, 1, 0]);
+    assert_eq!(pal.palette[0], palette::Srgb::new(255, 0, 0));
+    assert!(pal.transparency.is_empty());
+    assert_eq!(bmp.data[1], palette::Srgb::new(0, 0, 255));
+}
+
+#[test]
+fn png_packed_indices() {
+    // 3x2 at 2 bits, rows padded to a byte
+    let palette = [0, 0, 0, 10, 20, 30, 40, 50, 60, 70, 80, 90];
+    let data = [0b00_01_10_00, 0b11_10_01_00];
+    let png = encode_png(3, 2, png::BitDepth::Two, &palette,
+                         Some(&[0, 128]), &data);
+    let bmp = parse_png(png).unwrap();
+    let pal = bmp.palette.unwrap();
+    assert_eq!(pal.image, vec![0, 1, 2, 3, 2, 1]);
+    assert_eq!(pal.palette.len(), 4);
+    assert_eq!(pal.transparency, vec![0, 128]);
+    assert_eq!(bmp.data[3], palette::Srgb::new(70, 80, 90));
+
+    // and it goes back out with the transparency
+    let png = encode_png(3, 2, png::BitDepth::Two, &palette,
+                         Some(&[0, 128]), &data);
+    let again = smol::block_on(
+        PngFormat.convert(&PngFormat, &TBmp, &mut Input::new(png))).unwrap();
+    let again = parse_png(again).unwrap().palette.unwrap();
+    assert_eq!(again.image, vec![0, 1, 2, 3, 2, 1]);
+    assert_eq!(again.transparency, vec![0, 128]);
+}
+
+#[test]
+fn png_bad_index() {
+    let png = encode_png(2, 1, png::BitDepth::Eight, &[0, 0, 0],
+                         None, &[0, 5]);
+    assert!(parse_png(png).is_err());
+}
+
+#[test]
+fn png_too_wide() {
+    let png = encode_png(70000, 1, png::BitDepth::One, &[0, 0, 0],
+                         None, &[0; 8750]);
+    assert!(parse_png(png).is_err());
+}
+
+fn paletted_tbmp() -> Vec<u8> {
+    let mut b = ResourceBuilder::new();
+    b.u16(2).u16(2).u16(4).u16(2 | 1 << 3);
+    b.u16(4 + 3 * 2).u8(24).u8(2);
+    b.bytes(&[0, 0, 255, 255, 0, 0]);
+    b.bytes(&[0, 1, 9, 9, 1, 0, 9, 9]);
+    b.finish()
+}
+
+fn parse_with<F>(fmt: F, data: Vec<u8>) -> anyhow::Result<Bitmap>
+where
+    F: Format<TBmp, Input<Vec<u8>>, Bitmap>,
+{
+    smol::block_on(fmt.parse(&TBmp, &mut Input::new(data)))
+}
+
+#[test]
+fn exchange_formats_paletted() {
+    use moiety::Compare;
+
+    let original: Bitmap = through(BincodeFormat, TBmp, paletted_tbmp());
+    let bmp: Bitmap = through(BmpFormat, TBmp, paletted_tbmp());
+    assert_eq!(original.compare(&bmp), None);
+    assert_eq!(bmp.palette.unwrap().image, vec![0, 1, 1, 0]);
+    let tga: Bitmap = through(TgaFormat, TBmp, paletted_tbmp());
+    assert_eq!(original.compare(&tga), None);
+    assert_eq!(tga.palette.unwrap().image, vec![0, 1, 1, 0]);
+
+    // ppm has nowhere to put a palette
+    let ppm: Bitmap = through(PpmFormat, TBmp, paletted_tbmp());
+    assert!(ppm.palette.is_none());
+    assert_eq!(ppm.data, original.data);
+}
+
+#[test]
+fn exchange_formats_truecolor() {
+    // 3x2 so bmp rows need padding
+    let mut ppm = b"P6\n# a comment\n3 2\n255\n".to_vec();
+    ppm.extend((0..18).map(|v| v * 10));
+    let source = parse_with(PpmFormat, ppm.clone()).unwrap();
+    assert_eq!(source.data[1], palette::Srgb::new(30, 40, 50));
+
+    let bmp = smol::block_on(BmpFormat.convert(
+        &PpmFormat, &TBmp, &mut Input::new(ppm.clone()))).unwrap();
+    let bmp = parse_with(BmpFormat, bmp).unwrap();
+    assert!(bmp.palette.is_none());
+    assert_eq!(bmp.data, source.data);
+    let tga = smol::block_on(TgaFormat.convert(
+        &PpmFormat, &TBmp, &mut Input::new(ppm))).unwrap();
+    let tga = parse_with(TgaFormat, tga).unwrap();
+    assert_eq!(tga.data, source.data);
+}
+
+#[test]
+fn ppm_maxval_and_gray() {
+    let pgm = b"P5 2 1 15\n\x00\x0f".to_vec();
+    let bmp = parse_with(PpmFormat, pgm).unwrap();
+    assert_eq!(bmp.data, vec![palette::Srgb::new(0, 0, 0),
+                              palette::Srgb::new(255, 255, 255)]);
+    assert!(parse_with(PpmFormat, b"P3 1 1 255\n0 0 0".to_vec()).is_err());
+    assert!(parse_with(PpmFormat, b"P6 2 2 255\n\0\0\0".to_vec()).is_err());
+}
+
+#[test]
+fn tga_rle_bottom_up() {
+    // 2x2 grayscale, rle, stored bottom row first
+    let mut tga = vec![0, 0, 11, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 2, 0, 8, 0];
+    tga.extend(&[0x81, 7, 0x01, 1, 2]);
+    let bmp = parse_with(TgaFormat, tga).unwrap();
+    let gray: Vec<u8> = bmp.data.iter().map(|c| c.red).collect();
+    assert_eq!(gray, vec![1, 2, 7, 7]);
+
+    // colormap starting at 4 means index 3 is out of range
+    let mut tga = vec![0, 1, 1, 4, 0, 1, 0, 24, 0, 0, 0, 0, 1, 0, 1, 0, 8, 0];
+    tga.extend(&[0, 0, 0, 3]);
+    assert!(parse_with(TgaFormat, tga).is_err());
+}
+
+#[test]
+fn bmp_rejects_bad_files() {
+    let bmp = smol::block_on(BmpFormat.convert(
+        &MhkFormat::default(), &TBmp, &mut Input::new(paletted_tbmp())))
+        .unwrap();
+    assert!(parse_with(BmpFormat, bmp[..bmp.len() - 1].to_vec()).is_err());
+    let mut rle = bmp.clone();
+    rle[30] = 1;
+    assert!(parse_with(BmpFormat, rle).is_err());
+    assert!(parse_with(BmpFormat, b"PNG".to_vec()).is_err());
+}
+
+// a .cur with one solid image per size, hotspot in the middle
+fn encode_cur(sizes: &[u32]) -> Vec<u8> {
+    let mut dir = ico::IconDir::new(ico::ResourceType::Cursor);
+    for &size in sizes {
+        let pixels = (size * size) as usize;
+        let rgba = [size as u8, 0, 0, 255].repeat(pixels);
+        let mut icon = ico::IconImage::from_rgba_data(size, size, rgba);
+        icon.set_cursor_hotspot(Some((size as u16 / 2, size as u16 / 2)));
+        dir.add_entry(ico::IconDirEntry::encode(&icon).unwrap());
+    }
+    let mut out = Vec::new();
+    dir.write(&mut out).unwrap();
+    out
+}
+
+fn parse_cur<F>(fmt: F, data: Vec<u8>) -> anyhow::Result<Cursor>
+where
+    F: Format<TCur, Input<Vec<u8>>, Cursor>,
+{
+    smol::block_on(fmt.parse(&TCur, &mut Input::new(data)))
+}
+
+#[test]
+fn cur_best_size() {
+    let cur = encode_cur(&[16, 48, 32]);
+    let big = parse_cur(CurFormat::default(), cur.clone()).unwrap();
+    assert_eq!((big.width, big.hotspot), (48, (24, 24)));
+    let near = CurFormat::default().with_size(Some((30, 30)));
+    let near = parse_cur(near, cur).unwrap();
+    assert_eq!((near.width, near.hotspot), (32, (16, 16)));
+    assert_eq!(near.data[0], palette::Srgba::new(32, 0, 0, 255));
+}
+
+#[test]
+fn ani_cursor() {
+    fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend(&(body.len() as u32).to_le_bytes());
+        out.extend(body);
+        if body.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+    let mut anih = vec![0; 36];
+    anih[0] = 36;
+    anih[32] = 1;
+    let mut frames = b"fram".to_vec();
+    frames.extend(chunk(b"icon", &encode_cur(&[16])));
+    frames.extend(chunk(b"icon", &encode_cur(&[8, 32])));
+    let mut body = b"ACON".to_vec();
+    body.extend(chunk(b"anih", &anih));
+    body.extend(chunk(b"seq ", &[1, 0, 0, 0, 0, 0, 0, 0]));
+    body.extend(chunk(b"LIST", &frames));
+    let ani = chunk(b"RIFF", &body);
+
+    // the sequence starts on the second frame
+    let cur = parse_cur(CurFormat::default(), ani.clone()).unwrap();
+    assert_eq!((cur.width, cur.hotspot), (32, (16, 16)));
+
+    let mut raw = ani;
+    raw[12 + 8 + 32] = 0;
+    assert!(parse_cur(CurFormat::default(), raw).is_err());
+}
+
+#[test]
+fn png_cursor() {
+    let cur = encode_cur(&[8]);
+    let png = smol::block_on(PngCursorFormat.convert(
+        &CurFormat::default(), &TCur, &mut Input::new(cur.clone()))).unwrap();
+    let original = parse_cur(CurFormat::default(), cur).unwrap();
+    let back = parse_cur(PngCursorFormat, png).unwrap();
+    assert_eq!((back.width, back.height), (8, 8));
+    assert_eq!(back.hotspot, (4, 4));
+    assert_eq!(back.data, original.data);
+
+    // a plain png has nowhere to say where the hotspot is
+    let plain = encode_png(1, 1, png::BitDepth::Eight, &[0, 0, 0],
+                           None, &[0]);
+    assert!(parse_cur(PngCursorFormat, plain).is_err());
+}
+
+#[test]
+fn mixed_format_media() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::{
+        DirectMap, JsonFormat, MhkWriter, MixedFormat, Movie, Raw, RawFormat,
+        RawResource, ResourceMapWrite, Resources, Sound,
+    };
+
+    let dir = std::env::temp_dir()
+        .join(format!("moiety-media-{}", std::process::id()));
+    let fmt = MixedFormat::new(PngFormat, CurFormat::default(),
+                               JsonFormat(false))
+        .with_sound(RawFormat(".wav"))
+        .with_movie(RawFormat(".mov"))
+        .with_raw(RawFormat(".bin"));
+    assert_eq!(Format::<_, Input<Vec<u8>>, Sound>::extension(&fmt, &TWav),
+               Some(".wav"));
+    assert_eq!(Format::<_, Input<Vec<u8>>, Movie>::extension(&fmt, &TMov),
+               Some(".mov"));
+
+    let movie: Movie = through(fmt.clone(), TMov, b"moov".to_vec());
+    assert_eq!(movie, Movie(b"moov".to_vec()));
+
+    let mut map = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+    smol::block_on(async {
+        map.write_raw(Stack::J, "tWAV", 1, "", b"MHWK").await.unwrap();
+        map.write_raw(Stack::J, "tMOV", 2, "", b"moov").await.unwrap();
+        map.write_raw(Stack::J, "XXXX", 3, "", b"????").await.unwrap();
+        let mut rs = Resources::new(map);
+        let mut outrs = Resources::new(
+            DirectMap::new(LocalFilesystem::new(&dir), fmt));
+        rs.write_resource_to(&mut outrs, Stack::J, TWav, 1).await.unwrap();
+        rs.write_resource_to(&mut outrs, Stack::J, TMov, 2).await.unwrap();
+        rs.write_resource_to(&mut outrs, Stack::J, RawResource("XXXX"), 3)
+            .await.unwrap();
+        outrs.flush().await.unwrap();
+
+        assert_eq!(*outrs.open(Stack::J, TWav, 1).await.unwrap(),
+                   Sound(b"MHWK".to_vec()));
+        assert_eq!(*outrs.open(Stack::J, RawResource("XXXX"), 3).await
+                   .unwrap(), Raw(b"????".to_vec()));
+    });
+    // each kind lands under its own extension
+    for path in &["tWAV/00001.wav", "tMOV/00002.mov", "XXXX/00003.bin"] {
+        assert!(dir.join("jspit").join(path).is_file(), "{}", path);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
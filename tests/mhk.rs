@@ -0,0 +1,203 @@
+use moiety::mhk::testing::MhkBuilder;
+use moiety::mhk::{ArchiveProblem, MhkArchive};
+use moiety::MhkError;
+
+use proptest::prelude::*;
+use smol::io::{AsyncReadExt, Cursor};
+
+fn open(data: Vec<u8>) -> anyhow::Result<MhkArchive<Cursor<Vec<u8>>>> {
+    smol::block_on(MhkArchive::new(Cursor::new(data)))
+}
+
+fn read(arc: &MhkArchive<Cursor<Vec<u8>>>, typ: &str, id: u16) -> Vec<u8> {
+    let mut handle = arc.open(typ, id).unwrap();
+    let mut buf = Vec::new();
+    smol::block_on(handle.read_to_end(&mut buf)).unwrap();
+    buf
+}
+
+fn is_invalid_format(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref::<MhkError>(), Some(MhkError::InvalidFormat(_)))
+}
+
+#[test]
+fn empty_archive() {
+    let arc = open(MhkBuilder::new().build().unwrap()).unwrap();
+    assert!(arc.files.is_empty());
+    assert!(arc.resources.is_empty());
+}
+
+#[test]
+fn named_resources() {
+    let mut b = MhkBuilder::new();
+    b.add_named("tBMP", 5, "hello", b"abc");
+    b.add("tBMP", 6, b"defg");
+    let arc = open(b.build().unwrap()).unwrap();
+    let rs = &arc.resources["tBMP"];
+    assert_eq!(rs[&5].name.as_deref(), Some("hello"));
+    assert_eq!(rs[&6].name, None);
+    assert_eq!(read(&arc, "tBMP", 5), b"abc");
+    assert_eq!(read(&arc, "tBMP", 6), b"defg");
+}
+
+#[test]
+fn file_table_index_zero() {
+    let mut b = MhkBuilder::new();
+    b.add_file(b"data", 0);
+    b.add_resource("CARD", 1, 0);
+    let err = open(b.build().unwrap()).unwrap_err();
+    assert!(is_invalid_format(&err));
+}
+
+#[test]
+fn file_table_index_past_end() {
+    let mut b = MhkBuilder::new();
+    b.add_file(b"data", 0);
+    b.add_resource("CARD", 1, 2);
+    let err = open(b.build().unwrap()).unwrap_err();
+    assert!(is_invalid_format(&err));
+}
+
+#[test]
+fn lenient_archive_problems() {
+    let mut b = MhkBuilder::new();
+    b.add("CARD", 1, b"first");
+    b.add("CARD", 1, b"second");
+    b.add_resource("CARD", 2, 9);
+    let data = b.build().unwrap();
+    assert!(is_invalid_format(&open(data.clone()).unwrap_err()));
+
+    let arc = smol::block_on(MhkArchive::new_lenient(Cursor::new(data)))
+        .unwrap();
+    assert_eq!(arc.problems, vec![
+        ArchiveProblem::DuplicateId { ty: "CARD".to_owned(), id: 1 },
+        ArchiveProblem::BadFileIndex {
+            ty: "CARD".to_owned(), id: 2, index: 9,
+        },
+    ]);
+    assert_eq!(read(&arc, "CARD", 1), b"first");
+    assert!(arc.open("CARD", 2).is_err());
+}
+
+#[test]
+fn missing_resource() {
+    let mut b = MhkBuilder::new();
+    b.add("CARD", 1, b"data");
+    let arc = open(b.build().unwrap()).unwrap();
+    assert!(arc.open("CARD", 2).is_err());
+    assert!(arc.open("PLST", 1).is_err());
+}
+
+fn resources() -> impl Strategy<Value = Vec<(String, u16, Option<String>, Vec<u8>)>> {
+    let typ = prop::sample::select(vec!["CARD", "PLST", "tBMP", "tWAV"]);
+    let entry = (
+        typ,
+        any::<u16>(),
+        prop::option::of("[a-z]{1,8}"),
+        prop::collection::vec(any::<u8>(), 0..64),
+    );
+    prop::collection::vec(entry, 0..16).prop_map(|entries| {
+        let mut seen = std::collections::HashSet::new();
+        entries.into_iter()
+            .filter(|(t, id, _, _)| seen.insert((*t, *id)))
+            .map(|(t, id, name, data)| (t.to_owned(), id, name, data))
+            .collect()
+    })
+}
+
+proptest! {
+    #[test]
+    fn archive_round_trip(entries in resources()) {
+        let mut b = MhkBuilder::new();
+        for (t, id, name, data) in &entries {
+            match name {
+                Some(name) => b.add_named(t, *id, name, data),
+                None => b.add(t, *id, data),
+            };
+        }
+        let arc = open(b.build().unwrap()).unwrap();
+        let count: usize = arc.resources.values().map(|r| r.len()).sum();
+        prop_assert_eq!(count, entries.len());
+        for (t, id, name, data) in &entries {
+            let info = &arc.resources[t][id];
+            prop_assert_eq!(&info.name, name);
+            prop_assert_eq!(&read(&arc, t, *id), data);
+        }
+    }
+
+    #[test]
+    fn archive_garbage_does_not_panic(
+        data in prop::collection::vec(any::<u8>(), 0..256),
+    ) {
+        let _ = open(data);
+    }
+
+    #[test]
+    fn archive_corruption_does_not_panic(
+        entries in resources(),
+        flips in prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 1..8),
+    ) {
+        let mut b = MhkBuilder::new();
+        for (t, id, _, data) in &entries {
+            b.add(t, *id, data);
+        }
+        let mut data = b.build().unwrap();
+        for (i, v) in flips {
+            let i = i.index(data.len());
+            data[i] = v;
+        }
+        if let Ok(arc) = open(data) {
+            for (t, rs) in &arc.resources {
+                for id in rs.keys() {
+                    let mut buf = Vec::new();
+                    if let Ok(mut h) = arc.open(t, *id) {
+                        let _ = smol::block_on(h.read_to_end(&mut buf));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn layout_extends() {
+    use moiety::mhk::MhkLayout;
+    let base = br#"{"name": "a", "optional": ["x"], "stacks": {"s": ["x"]}}"#;
+    let child = br#"{"name": "b", "extends": "a", "probe": ["y"]}"#;
+    let layouts = MhkLayout::from_json_list(&[base, child]).unwrap();
+    assert_eq!(layouts[1].stacks, layouts[0].stacks);
+    assert_eq!(layouts[1].optional, vec!["x".to_owned()]);
+    assert_eq!(layouts[1].probe, vec!["y".to_owned()]);
+
+    let orphan = br#"{"name": "c", "extends": "nope"}"#;
+    let err = MhkLayout::from_json_list(&[orphan]).unwrap_err();
+    assert!(matches!(err.downcast_ref::<MhkError>(),
+                     Some(MhkError::UnknownLayoutBase(_))));
+
+    // the built-in layouts must all resolve
+    for layout in moiety::riven::layouts() {
+        assert!(!layout.stacks.is_empty(), "{}", layout.name);
+    }
+}
+
+#[test]
+fn layout_detect() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::mhk::MhkLayout;
+
+    let layouts = moiety::riven::layouts();
+    for layout in &layouts {
+        // only the files that tell this edition apart
+        let dir = std::env::temp_dir().join(format!(
+            "moiety-layout-{}-{}", layout.name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in layout.probe.iter().chain(layout.archive.iter()) {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let mut fs = LocalFilesystem::new(&dir);
+        let found = smol::block_on(MhkLayout::detect(&layouts, &mut fs))
+            .unwrap();
+        assert_eq!(found.name, layout.name);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
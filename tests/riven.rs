@@ -0,0 +1,1197 @@
+use moiety::mhk::testing::{
+    copy_bytes, parse_bytes, roundtrip_bytes, ResourceBuilder,
+};
+use moiety::riven::*;
+use moiety::{Bitmap, ExportStatus, PaletteBitmap};
+
+use proptest::prelude::*;
+
+#[test]
+fn plst() {
+    let mut b = ResourceBuilder::new();
+    b.u16(2);
+    b.u16(1).u16(10).u16(0).u16(0).u16(608).u16(392);
+    b.u16(2).u16(11).u16(5).u16(6).u16(7).u16(8);
+    let plst = parse_bytes(TPlst, &b.finish()).unwrap();
+    assert_eq!(plst.len(), 2);
+    assert_eq!(plst[1], PictureMeta {
+        index: 2,
+        bitmap_id: 11,
+        left: 5,
+        top: 6,
+        right: 7,
+        bottom: 8,
+    });
+}
+
+#[test]
+fn name() {
+    let mut b = ResourceBuilder::new();
+    b.u16(2);
+    b.u16(0).u16(4);
+    b.u16(7).u16(9);
+    b.bytes(b"abc\0def\0");
+    let names = parse_bytes(TName, &b.finish()).unwrap();
+    assert_eq!(names[0].name, "abc");
+    assert_eq!(names[0].unknown, 7);
+    assert_eq!(names[1].name, "def");
+}
+
+#[test]
+fn name_unterminated() {
+    let mut b = ResourceBuilder::new();
+    b.u16(1).u16(0).u16(0);
+    let names = parse_bytes(TName, &b.finish()).unwrap();
+    assert_eq!(names[0].name, "");
+}
+
+#[test]
+fn card_script() {
+    let mut b = ResourceBuilder::new();
+    // name_rec, zip_mode_place, one handler
+    b.i16(3).u16(0).u16(1);
+    // load card, two commands
+    b.u16(6).u16(2);
+    // activate plst 1
+    b.u16(39).u16_table(&[1]);
+    // conditional on var 4, one branch
+    b.u16(8).u16_table(&[4, 1]);
+    b.u16(1).u16(1).u16(2).u16_table(&[12]);
+    let card = parse_bytes(TCard, &b.finish()).unwrap();
+    assert_eq!(card.name_rec, 3);
+    let cmds = &card.script[&Event::LoadCard];
+    assert_eq!(cmds[0], Command::ActivatePlst { record: 1 });
+    match &cmds[1] {
+        Command::Conditional { var, branches } => {
+            assert_eq!(*var, 4);
+            assert_eq!(branches[&1], vec![Command::GotoCard { id: 12 }]);
+        }
+        c => panic!("unexpected command {:?}", c),
+    }
+}
+
+#[test]
+fn script_duplicates() {
+    // two load card handlers
+    let mut b = ResourceBuilder::new();
+    b.i16(0).u16(0).u16(2);
+    b.u16(6).u16(0);
+    b.u16(6).u16(0);
+    assert!(parse_bytes(TCard, &b.finish()).is_err());
+
+    // two branches for the same value, even when lenient
+    let mut b = ResourceBuilder::new();
+    b.u16(1);
+    b.u16(8).u16_table(&[4, 2]);
+    b.u16(1).u16(0);
+    b.u16(1).u16(0);
+    assert!(commands_from(b.finish(), true).is_err());
+}
+
+#[test]
+fn script_nesting_limit() {
+    let mut b = ResourceBuilder::new();
+    b.i16(0).u16(0).u16(1).u16(6);
+    for _ in 0..1000 {
+        b.u16(1).u16(8).u16_table(&[0, 1]).u16(0);
+    }
+    assert!(parse_bytes(TCard, &b.finish()).is_err());
+}
+
+#[test]
+fn tbmp_uncompressed() {
+    let mut b = ResourceBuilder::new();
+    // 2x2, 8 bit, stride 4, palette present
+    b.u16(2).u16(2).u16(4).u16(2 | 1 << 3);
+    b.u16(4 + 3 * 2).u8(24).u8(2);
+    b.bytes(&[0, 0, 255, 255, 0, 0]);
+    b.bytes(&[0, 1, 9, 9, 1, 0, 9, 9]);
+    let bmp = parse_bytes(TBmp, &b.finish()).unwrap();
+    assert_eq!((bmp.width, bmp.height), (2, 2));
+    let pal = bmp.palette.unwrap();
+    assert_eq!(pal.image, vec![0, 1, 1, 0]);
+    assert_eq!(bmp.data[0], palette::Srgb::new(255, 0, 0));
+    assert_eq!(bmp.data[1], palette::Srgb::new(0, 0, 255));
+}
+
+#[test]
+fn tbmp_riven() {
+    let mut b = ResourceBuilder::new();
+    // 4x2, 8 bit, riven compression
+    b.u16(4).u16(2).u16(4).u16(2 | 4 << 8);
+    b.u16(4 + 3).u8(24).u8(1);
+    b.bytes(&[1, 2, 3]);
+    b.bytes(&[0, 0, 0, 0]);
+    // two literal duplets, then repeat the last 4 pixels once
+    b.bytes(&[0x02, 0, 0, 0, 0, 0x81, 0x00]);
+    let bmp = parse_bytes(TBmp, &b.finish()).unwrap();
+    assert_eq!(bmp.palette.unwrap().image, vec![0; 8]);
+}
+
+#[test]
+fn tbmp_riven_lookback_underflow() {
+    let mut b = ResourceBuilder::new();
+    b.u16(4).u16(1).u16(4).u16(2 | 4 << 8);
+    b.u16(4 + 3).u8(24).u8(1);
+    b.bytes(&[1, 2, 3]);
+    b.bytes(&[0, 0, 0, 0]);
+    // subcommands that look back before any output
+    b.bytes(&[0xc1, 0x0f, 0x00]);
+    assert!(parse_bytes(TBmp, &b.finish()).is_err());
+}
+
+fn indexed_bitmap(width: u16, height: u16, image: Vec<u8>) -> Bitmap {
+    let palette: Vec<_> = (0..=255)
+        .map(|i| palette::Srgb::new(i, 255 - i, i / 2))
+        .collect();
+    Bitmap {
+        width,
+        height,
+        data: image.iter().map(|&i| palette[i as usize]).collect(),
+        palette: Some(PaletteBitmap {
+            palette,
+            image,
+            transparency: vec![],
+        }),
+    }
+}
+
+fn tbmp_round_trip(bmp: &Bitmap, compression: TBmpCompression) -> Bitmap {
+    parse_bytes(TBmp, &encode_tbmp(bmp, compression).unwrap()).unwrap()
+}
+
+#[test]
+fn tbmp_encode_gradient() {
+    // small steps between pixels exercise the delta subcommands
+    let image = (0..37 * 11)
+        .map(|i: u32| ((i % 37) * 3 + (i / 37) * 5 + (i % 3)) as u8)
+        .collect();
+    let bmp = indexed_bitmap(37, 11, image);
+    for &compression in &[TBmpCompression::None, TBmpCompression::Riven] {
+        let out = tbmp_round_trip(&bmp, compression);
+        assert_eq!((out.width, out.height), (37, 11));
+        assert_eq!(out.data, bmp.data);
+        let pal = out.palette.unwrap();
+        assert_eq!(pal.image, bmp.palette.as_ref().unwrap().image);
+    }
+}
+
+#[test]
+fn tbmp_encode_compresses() {
+    let bmp = indexed_bitmap(64, 64, vec![7; 64 * 64]);
+    let raw = encode_tbmp(&bmp, TBmpCompression::None).unwrap();
+    let riven = encode_tbmp(&bmp, TBmpCompression::Riven).unwrap();
+    assert!(riven.len() * 4 < raw.len());
+}
+
+#[test]
+fn tbmp_encode_truecolor() {
+    // too many colors for a palette, so riven compression quantizes
+    let data: Vec<_> = (0..63 * 65u32)
+        .map(|i| palette::Srgb::new((i % 64 * 4) as u8, (i / 64 * 4) as u8,
+                                    (i % 7) as u8))
+        .collect();
+    let bmp = Bitmap { width: 63, height: 65, palette: None, data };
+    for &compression in &[TBmpCompression::None, TBmpCompression::Riven] {
+        let out = tbmp_round_trip(&bmp, compression);
+        assert!(out.palette.is_some());
+        for (a, b) in out.data.iter().zip(&bmp.data) {
+            assert!((a.red as i32 - b.red as i32).abs() <= 16);
+            assert!((a.green as i32 - b.green as i32).abs() <= 16);
+            assert!(a.blue <= 8);
+        }
+    }
+
+    // unless every color is asked for
+    let out = tbmp_round_trip(&bmp, TBmpCompression::Truecolor);
+    assert!(out.palette.is_none());
+    assert_eq!(out.data, bmp.data);
+}
+
+#[test]
+fn tbmp_copy_keeps_bytes() {
+    // a table with extra colors, which re-encoding would change
+    let mut b = ResourceBuilder::new();
+    b.u16(2).u16(2).u16(4).u16(2 | 1 << 3);
+    b.u16(4 + 3 * 3).u8(24).u8(2);
+    b.bytes(&[0, 0, 255, 255, 0, 0, 1, 2, 3]);
+    b.bytes(&[0, 1, 9, 9, 1, 0, 9, 9]);
+    let data = b.finish();
+    assert_eq!(copy_bytes(TBmp, &data).unwrap(), data);
+
+    // and the palette size written is the real one
+    let bmp = parse_bytes(TBmp, &data).unwrap();
+    let encoded = encode_tbmp(&bmp, TBmpCompression::None).unwrap();
+    assert_eq!(encoded[8..12], [3, 4, 24, 2]);
+}
+
+#[test]
+fn sfxe() {
+    let mut b = ResourceBuilder::new();
+    b.bytes(b"SL").u16(1).u32(52);
+    b.u16(1).u16(2).u16(3).u16(4).u16(5).u16(6);
+    b.u16(2).u16(1).u16(4).u16(3);
+    b.u16(7).u16(1);
+    b.u32(0).u32(0).u32(0).u32(0).u32(0);
+    b.u32(56);
+    b.u16(1).u16(1).u16(3).u16(1).u16(2).u16(3).u16(4).u16(4);
+    let sfxe = parse_bytes(TSfxe, &b.finish()).unwrap();
+    assert_eq!(sfxe.frames, vec![vec![
+        EffectCommand::IncrementRow { amount: 2 },
+        EffectCommand::Copy {
+            dst_left: 1,
+            src_left: 2,
+            src_top: 3,
+            row_width: 4,
+        },
+    ]]);
+}
+
+#[test]
+fn sfxe_normalizes() {
+    // both frames point at the same commands
+    let mut b = ResourceBuilder::new();
+    b.bytes(b"SL").u16(2).u32(52);
+    b.u16(1).u16(2).u16(3).u16(4).u16(5).u16(6);
+    b.u16(2).u16(1).u16(4).u16(3);
+    b.u16(7).u16(2);
+    b.u32(0).u32(0).u32(0).u32(0).u32(0);
+    b.u32(60).u32(60);
+    b.u16(1).u16(4);
+    let shared = b.finish();
+
+    let written = roundtrip_bytes(TSfxe, &shared).unwrap();
+    assert_ne!(written, shared);
+    assert_eq!(written.len(), shared.len() + 4);
+    assert_eq!(parse_bytes(TSfxe, &written).unwrap(),
+               parse_bytes(TSfxe, &shared).unwrap());
+    assert_eq!(roundtrip_bytes(TSfxe, &written).unwrap(), written);
+}
+
+#[test]
+fn roundtrip() {
+    let mut b = ResourceBuilder::new();
+    b.u16(1).u16(1).u16(10).u16(0).u16(0).u16(608).u16(392);
+    let plst = b.finish();
+    assert_eq!(roundtrip_bytes(TPlst, &plst).unwrap(), plst);
+
+    let mut b = ResourceBuilder::new();
+    b.u16(2).u16(0).u16(4).u16(7).u16(9);
+    b.bytes(b"abc\0def\0");
+    let name = b.finish();
+    assert_eq!(roundtrip_bytes(TName, &name).unwrap(), name);
+
+    let mut b = ResourceBuilder::new();
+    b.i16(3).u16(0).u16(1);
+    b.u16(6).u16(2);
+    b.u16(39).u16_table(&[1]);
+    b.u16(8).u16_table(&[4, 1]);
+    b.u16(1).u16(1).u16(2).u16_table(&[12]);
+    let card = b.finish();
+    assert_eq!(roundtrip_bytes(TCard, &card).unwrap(), card);
+
+    let mut b = ResourceBuilder::new();
+    b.bytes(b"SL").u16(1).u32(52);
+    b.u16(1).u16(2).u16(3).u16(4).u16(5).u16(6);
+    b.u16(2).u16(1).u16(4).u16(3);
+    b.u16(7).u16(1);
+    b.u32(0).u32(0).u32(0).u32(0).u32(0);
+    b.u32(56);
+    b.u16(1).u16(1).u16(3).u16(1).u16(2).u16(3).u16(4).u16(4);
+    let sfxe = b.finish();
+    assert_eq!(roundtrip_bytes(TSfxe, &sfxe).unwrap(), sfxe);
+}
+
+#[test]
+fn script_round_trip_odd_encodings() {
+    let mut b = ResourceBuilder::new();
+    // handlers and branches out of order, an unusual transition code,
+    // and a call with extra trailing arguments
+    b.i16(0).u16(0).u16(2);
+    b.u16(9).u16(1);
+    b.u16(8).u16_table(&[4, 2]);
+    b.u16(3).u16(1).u16(18).u16_table(&[17]);
+    b.u16(1).u16(1).u16(17).u16_table(&[5, 1, 6, 7]);
+    b.u16(6).u16(0);
+    let card = b.finish();
+    assert_eq!(roundtrip_bytes(TCard, &card).unwrap(), card);
+}
+
+fn commands_from(data: Vec<u8>, lenient: bool) -> anyhow::Result<Vec<Command>> {
+    let mut input = smol::io::Cursor::new(data);
+    smol::block_on(deserialize_commands(&mut input, lenient))
+}
+
+#[test]
+fn script_typed_opcodes() {
+    let mut b = ResourceBuilder::new();
+    b.u16(4);
+    b.u16(12).u16_table(&[3]);
+    b.u16(38).u16_table(&[5, 1, 2, 32, 7]);
+    b.u16(41).u16_table(&[2]);
+    b.u16(36).u16_table(&[]);
+    let data = b.finish();
+    let commands = commands_from(data.clone(), false).unwrap();
+    assert_eq!(commands, vec![
+        Command::StopSound { flags: 3 },
+        Command::ScheduleMovieCommand {
+            code: 5,
+            time: 0x10002,
+            cmd: 32,
+            arg: 7,
+        },
+        Command::ActivateMlstAndPlay { record: 2 },
+        Command::Nop,
+    ]);
+    let mut out = Vec::new();
+    serialize_commands(&mut out, &commands).unwrap();
+    assert_eq!(out, data);
+}
+
+#[test]
+fn script_lenient() {
+    let mut b = ResourceBuilder::new();
+    // an opcode nobody knows, and a goto-card with too many arguments
+    b.u16(2);
+    b.u16(99).u16_table(&[1, 2]);
+    b.u16(2).u16_table(&[1, 2]);
+    let data = b.finish();
+    assert!(commands_from(data.clone(), false).is_err());
+    let commands = commands_from(data.clone(), true).unwrap();
+    assert_eq!(commands[0], Command::Unknown { cmd: 99, args: vec![1, 2] });
+    assert_eq!(commands[1], Command::Unknown { cmd: 2, args: vec![1, 2] });
+    let mut out = Vec::new();
+    serialize_commands(&mut out, &commands).unwrap();
+    assert_eq!(out, data);
+
+    // a conditional with the wrong arguments can't be stepped over
+    let mut b = ResourceBuilder::new();
+    b.u16(2);
+    b.u16(8).u16_table(&[4]);
+    b.u16(1).u16(0);
+    b.u16(2).u16_table(&[1]);
+    assert!(commands_from(b.finish(), true).is_err());
+    let bad = [Command::Unknown { cmd: 8, args: vec![4] }];
+    assert!(serialize_commands(&mut Vec::new(), &bad).is_err());
+
+    use moiety::mhk::MhkFormat;
+    assert!(!MhkFormat::default().lenient_scripts());
+    assert!(MhkFormat::default().with_lenient_scripts(true)
+            .lenient_scripts());
+}
+
+#[test]
+fn script_assembly() {
+    let names = ScriptNames {
+        externals: vec!["xfoo".to_owned()],
+        variables: vec!["avar".to_owned(), "bvar".to_owned()],
+        stacks: vec!["aspit".to_owned()],
+    };
+    let text = "\
+on load-card
+    set-variable bvar 3  # a comment
+    switch 0
+        case 1
+            call xfoo 7 8
+            transition left old-move 1 2 3 4
+        case 0
+    end
+    activate-inline-slst 1 2 3 4 5 (6 7 8 9) (10 11 12 13)
+on mouse-down
+    goto-stack aspit 65537
+    unknown 99 1 2
+";
+    let script = assemble(text, &names).unwrap();
+    let load = &script[&Event::LoadCard];
+    assert_eq!(load[0], Command::SetVariable { var: 1, value: 3 });
+    match &load[1] {
+        Command::Conditional { var: 0, branches } => {
+            assert_eq!(branches.keys().collect::<Vec<_>>(), vec![&1, &0]);
+            assert_eq!(branches[&1][0], Command::Call {
+                cmd: 0,
+                args: vec![7, 8],
+            });
+        },
+        c => panic!("expected a conditional, got {:?}", c),
+    }
+    assert_eq!(script[&Event::MouseDown][0], Command::GotoStack {
+        stack_name: 0,
+        code: 65537,
+    });
+
+    let again = disassemble(&script, &names);
+    assert!(again.contains("switch avar"));
+    assert_eq!(assemble(&again, &names).unwrap(), script);
+}
+
+#[test]
+fn script_assembly_errors() {
+    let names = ScriptNames::default();
+    assert!(assemble("goto-card 1", &names).is_err());
+    assert!(assemble("on load-card\n    goto-card", &names).is_err());
+    assert!(assemble("on load-card\n    goto-card 1 2", &names).is_err());
+    assert!(assemble("on load-card\n    switch 1\n", &names).is_err());
+    assert!(assemble("on load-card\n    set-variable x 1", &names).is_err());
+    assert!(assemble_commands("end", &names).is_err());
+}
+
+#[test]
+fn script_names_load() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::{MhkWriter, ResourceMapWrite, Resources};
+
+    let mut b = ResourceBuilder::new();
+    b.u16(1).u16(0).u16(0).bytes(b"avar\0");
+    let vars = b.finish();
+    let mut map = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+    smol::block_on(async {
+        // missing tables are fine
+        map.write_raw(Stack::J, "NAME", 4, "", &vars).await.unwrap();
+        let mut rs = Resources::new(map);
+        let names = ScriptNames::load(&mut rs, Stack::J).await.unwrap();
+        assert_eq!(names.variables, vec!["avar".to_owned()]);
+        assert!(names.externals.is_empty());
+
+        // broken ones aren't
+        let mut map = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+        map.write_raw(Stack::J, "NAME", 3, "", &[0, 9]).await.unwrap();
+        let mut rs = Resources::new(map);
+        assert!(ScriptNames::load(&mut rs, Stack::J).await.is_err());
+    });
+}
+
+#[test]
+fn registry_open() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::{AnyResource, MhkWriter, ResourceMapWrite, Resources};
+
+    let mut b = ResourceBuilder::new();
+    b.u16(1).u16(1).u16(10).u16(0).u16(0).u16(608).u16(392);
+    let plst = b.finish();
+
+    // never finished, so this never touches the filesystem
+    let mut map = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+    smol::block_on(async {
+        map.write_raw(Stack::J, "PLST", 3, "", &plst).await.unwrap();
+        let mut rs = Resources::new(map);
+        let reg = registry();
+        assert!(reg.types().contains(&"CARD"));
+        assert!(reg.types().contains(&"tBMP"));
+
+        assert_eq!(reg.list(&mut rs, Stack::J, "PLST").await.unwrap(), vec![3]);
+        match reg.open(&mut rs, Stack::J, "PLST", 3).await.unwrap() {
+            AnyResource::Record(value) => {
+                assert_eq!(value[0]["bitmap-id"], 10);
+                assert_eq!(value[0]["right"], 608);
+            },
+            r => panic!("expected a record, got {:?}", r),
+        }
+        assert!(reg.open(&mut rs, Stack::J, "PLST", 4).await.is_err());
+        assert!(reg.open(&mut rs, Stack::J, "XXXX", 3).await.is_err());
+    });
+}
+
+#[test]
+fn diff_maps() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::{Change, MhkWriter, ResourceMapWrite, Resources};
+
+    let plst = |right| {
+        let mut b = ResourceBuilder::new();
+        b.u16(1).u16(1).u16(10).u16(0).u16(0).u16(right).u16(392);
+        b.finish()
+    };
+    let tbmp = |image: &[u8]| {
+        let mut b = ResourceBuilder::new();
+        b.u16(2).u16(2).u16(4).u16(2 | 1 << 3);
+        b.u16(4 + 3 * 2).u8(24).u8(2);
+        b.bytes(&[0, 0, 255, 255, 0, 0]);
+        b.bytes(image);
+        b.finish()
+    };
+
+    let mut old = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+    let mut new = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+    smol::block_on(async {
+        old.write_raw(Stack::J, "PLST", 1, "", &plst(608)).await.unwrap();
+        new.write_raw(Stack::J, "PLST", 1, "", &plst(600)).await.unwrap();
+        let same = [0, 1, 9, 9, 1, 0, 9, 9];
+        old.write_raw(Stack::J, "tBMP", 2, "", &tbmp(&same)).await.unwrap();
+        new.write_raw(Stack::J, "tBMP", 2, "", &tbmp(&[1, 1, 9, 9, 1, 1, 9, 9]))
+            .await.unwrap();
+        old.write_raw(Stack::J, "tBMP", 3, "", &tbmp(&same)).await.unwrap();
+        new.write_raw(Stack::J, "tBMP", 3, "", &tbmp(&same)).await.unwrap();
+        old.write_raw(Stack::J, "tBMP", 4, "", &tbmp(&same)).await.unwrap();
+        new.write_raw(Stack::O, "tBMP", 5, "", &tbmp(&same)).await.unwrap();
+
+        let mut oldrs = Resources::new(old);
+        let mut newrs = Resources::new(new);
+        let mut calls = 0;
+        let diffs = registry()
+            .diff(&mut oldrs, &registry(), &mut newrs, |done, total| {
+                calls += 1;
+                assert_eq!((done, total), (calls, 5));
+            }).await.unwrap();
+        assert_eq!(calls, 5);
+
+        let summary: Vec<_> = diffs.iter()
+            .map(|d| (d.stack, d.typ.as_str(), d.id, d.change.clone()))
+            .collect();
+        assert_eq!(summary, vec![
+            (Stack::J, "PLST", 1,
+             Change::Changed(vec!["/0/right: 608 -> 600".to_owned()])),
+            (Stack::J, "tBMP", 2,
+             Change::Changed(vec!["2 of 4 pixels differ".to_owned()])),
+            (Stack::J, "tBMP", 4, Change::Removed),
+            (Stack::O, "tBMP", 5, Change::Added),
+        ]);
+    });
+}
+
+#[test]
+fn cross_reference() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::{MhkWriter, ResourceMapWrite, Resources};
+
+    // on load: activate-plst 1, draw-bmp 5, and goto-card 12 if var 4 is 1
+    let mut b = ResourceBuilder::new();
+    b.i16(3).u16(0).u16(1);
+    b.u16(6).u16(3);
+    b.u16(39).u16_table(&[1]);
+    b.u16(1).u16_table(&[5, 0, 0, 608, 392, 0, 0, 0, 0]);
+    b.u16(8).u16_table(&[4, 1]);
+    b.u16(1).u16(1).u16(2).u16_table(&[12]);
+    let card = b.finish();
+    let mut b = ResourceBuilder::new();
+    b.u16(1).u16(1).u16(10).u16(0).u16(0).u16(608).u16(392);
+    let plst = b.finish();
+
+    let mut map = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+    smol::block_on(async {
+        map.write_raw(Stack::J, "CARD", 7, "", &card).await.unwrap();
+        map.write_raw(Stack::J, "PLST", 7, "", &plst).await.unwrap();
+        map.write_raw(Stack::J, "tBMP", 10, "", &[]).await.unwrap();
+        let mut rs = Resources::new(map);
+        let xref = CrossReference::build(&mut rs, Stack::J).await.unwrap();
+        assert_eq!(xref.references().len(), 4);
+
+        let users: Vec<_> = xref.referencing(Stack::J, "tBMP", 10)
+            .map(|r| (r.from.to_string(), r.via))
+            .collect();
+        assert_eq!(users, vec![("jspit PLST 7 #1".to_owned(), "bitmap-id")]);
+        let users: Vec<_> = xref.referencing(Stack::J, "PLST", 7)
+            .map(|r| (r.from.to_string(), r.via))
+            .collect();
+        assert_eq!(users, vec![("jspit CARD 7".to_owned(), "activate-plst")]);
+
+        let dangling: Vec<_> = xref.dangling()
+            .map(|r| (r.via, r.to.to_string()))
+            .collect();
+        assert_eq!(dangling, vec![
+            ("draw-bmp", "jspit tBMP 5".to_owned()),
+            ("goto-card", "jspit CARD 12".to_owned()),
+        ]);
+    });
+}
+
+#[test]
+fn write_all() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::{MhkWriter, ResourceMapWrite, Resources};
+
+    let mut b = ResourceBuilder::new();
+    b.u16(1).u16(1).u16(10).u16(0).u16(0).u16(608).u16(392);
+    let plst = b.finish();
+    let mut b = ResourceBuilder::new();
+    b.u16(0).u16(2).u16(0).u16(7);
+    let rmap = b.finish();
+
+    let mut map = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+    let out = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+    smol::block_on(async {
+        map.write_raw(Stack::J, "PLST", 1, "", &plst).await.unwrap();
+        map.write_raw(Stack::T, "PLST", 2, "", &plst).await.unwrap();
+        map.write_raw(Stack::J, "RMAP", 1, "", &rmap).await.unwrap();
+        // too short to parse, but copying between archives doesn't parse
+        map.write_raw(Stack::J, "RMAP", 2, "", &[1, 2]).await.unwrap();
+        let mut rs = Resources::new(map);
+        let mut outrs = Resources::new(out);
+        let reg = write_registry();
+        assert!(reg.types().contains(&"tBMP"));
+
+        let mut seen = vec![];
+        let report = rs.write_all_to(&mut outrs, &reg, 2, |p| {
+            assert_eq!(p.total, 4);
+            seen.push((p.done, p.typ.to_owned(), p.id, p.status));
+        }).await.unwrap();
+        assert_eq!(report.total, 4);
+        assert_eq!(report.written, 4);
+        assert_eq!(report.skipped, 0);
+        assert!(report.errors.is_empty());
+        assert_eq!(seen.iter().map(|s| s.0).collect::<Vec<_>>(),
+                   vec![1, 2, 3, 4]);
+        assert!(seen.iter().any(|s| s.1 == "PLST" && s.2 == 1
+                                && s.3 == ExportStatus::Written));
+        assert!(seen.iter().all(|s| s.3 == ExportStatus::Written));
+
+        assert_eq!(rs.open(Stack::J, TPlst, 1).await.unwrap(),
+                   outrs.open(Stack::J, TPlst, 1).await.unwrap());
+        assert_eq!(rs.open(Stack::T, TPlst, 2).await.unwrap(),
+                   outrs.open(Stack::T, TPlst, 2).await.unwrap());
+        assert_eq!(outrs.list(Stack::J, "RMAP").await.unwrap(), vec![1, 2]);
+        assert!(outrs.open(Stack::J, TRmap, 2).await.is_err());
+        assert_eq!(outrs.list(Stack::J, "CARD").await.unwrap().len(), 0);
+    });
+}
+
+// counts raw opens, to see what the cache in Resources saves
+struct CountingMap<M> {
+    map: M,
+    opens: std::rc::Rc<std::cell::Cell<usize>>,
+    fingerprint: Option<String>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl<M: moiety::ResourceMap> moiety::ResourceMap for CountingMap<M> {
+    type Handle = M::Handle;
+    type Stack = M::Stack;
+    type Format = M::Format;
+
+    fn format(&self) -> &Self::Format {
+        self.map.format()
+    }
+
+    async fn open_raw(&mut self, stack: Self::Stack, typ: &str, id: u16,
+                      ext: &str) -> anyhow::Result<Self::Handle> {
+        self.opens.set(self.opens.get() + 1);
+        self.map.open_raw(stack, typ, id, ext).await
+    }
+
+    async fn fingerprint(&mut self, _stack: Self::Stack, _typ: &str,
+                         _id: u16) -> anyhow::Result<Option<String>> {
+        Ok(self.fingerprint.clone())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<M: moiety::ResourceMapList> moiety::ResourceMapList for CountingMap<M> {
+    async fn list(&mut self, stack: Self::Stack, typ: &str)
+                  -> anyhow::Result<Vec<u16>> {
+        self.map.list(stack, typ).await
+    }
+}
+
+#[test]
+fn resource_cache() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::{MhkWriter, ResourceMapWrite, Resources};
+
+    let mut b = ResourceBuilder::new();
+    b.u16(1).u16(1).u16(10).u16(0).u16(0).u16(608).u16(392);
+    let plst = b.finish();
+
+    let mut map = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+    let opens = std::rc::Rc::new(std::cell::Cell::new(0));
+    smol::block_on(async {
+        map.write_raw(Stack::J, "PLST", 1, "", &plst).await.unwrap();
+        map.write_raw(Stack::J, "PLST", 2, "", &plst).await.unwrap();
+        let mut rs = Resources::new(CountingMap {
+            map,
+            opens: opens.clone(),
+            fingerprint: None,
+        });
+
+        // off by default
+        rs.open(Stack::J, TPlst, 1).await.unwrap();
+        rs.open(Stack::J, TPlst, 1).await.unwrap();
+        assert_eq!(opens.get(), 2);
+        assert_eq!(rs.cache_used(), 0);
+
+        rs.set_cache_budget(Some(1 << 20));
+        let a = rs.open(Stack::J, TPlst, 1).await.unwrap();
+        let b = rs.open(Stack::J, TPlst, 1).await.unwrap();
+        assert_eq!(a, b);
+        assert_eq!(opens.get(), 3);
+        assert!(rs.cache_used() > 0);
+
+        rs.invalidate(Stack::J, "PLST", 1);
+        rs.open(Stack::J, TPlst, 1).await.unwrap();
+        assert_eq!(opens.get(), 4);
+
+        // only room for one, so the least recently used goes
+        rs.set_cache_budget(Some(rs.cache_used()));
+        rs.open(Stack::J, TPlst, 2).await.unwrap();
+        rs.open(Stack::J, TPlst, 2).await.unwrap();
+        assert_eq!(opens.get(), 5);
+        rs.open(Stack::J, TPlst, 1).await.unwrap();
+        assert_eq!(opens.get(), 6);
+
+        rs.invalidate_stack(&Stack::J);
+        assert_eq!(rs.cache_used(), 0);
+    });
+}
+
+#[test]
+fn incremental_export() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::{Manifest, MhkWriter, ResourceMapWrite, Resources};
+
+    let mut b = ResourceBuilder::new();
+    b.u16(1).u16(1).u16(10).u16(0).u16(0).u16(608).u16(392);
+    let plst = b.finish();
+
+    let mut map = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+    let out = MhkWriter::new(LocalFilesystem::new("/nonexistent"));
+    let opens = std::rc::Rc::new(std::cell::Cell::new(0));
+    smol::block_on(async {
+        map.write_raw(Stack::J, "PLST", 1, "", &plst).await.unwrap();
+        map.write_raw(Stack::J, "PLST", 2, "", &plst).await.unwrap();
+        let mut rs = Resources::new(CountingMap {
+            map,
+            opens: opens.clone(),
+            fingerprint: Some("MAIN.MHK:100".to_owned()),
+        });
+        let mut outrs = Resources::new(out);
+        outrs.set_manifest(Some(Manifest::new()));
+        let reg = write_registry();
+
+        let report = rs.write_all_to(&mut outrs, &reg, 4, |_| {})
+            .await.unwrap();
+        assert_eq!((report.written, report.skipped), (2, 0));
+        assert_eq!(opens.get(), 2);
+        assert_eq!(outrs.manifest().unwrap().len(), 2);
+
+        // nothing changed, so nothing is converted again
+        let report = rs.write_all_to(&mut outrs, &reg, 4, |_| {})
+            .await.unwrap();
+        assert_eq!((report.written, report.skipped), (0, 2));
+        assert_eq!(opens.get(), 2);
+        rs.write_resource_to(&mut outrs, Stack::J, TPlst, 1).await.unwrap();
+        assert_eq!(opens.get(), 2);
+
+        // a different source for one of them
+        let mut manifest = outrs.manifest().unwrap().clone();
+        manifest.insert(&Stack::J, "PLST", 2, "old".to_owned());
+        outrs.set_manifest(Some(manifest));
+        let report = rs.write_all_to(&mut outrs, &reg, 4, |_| {})
+            .await.unwrap();
+        assert_eq!((report.written, report.skipped), (1, 1));
+        assert_eq!(opens.get(), 3);
+
+        // manifests survive a trip through json
+        let json = outrs.manifest().unwrap().to_json().unwrap();
+        let manifest = Manifest::from_json(&json).unwrap();
+        assert_eq!(Some(&manifest), outrs.manifest());
+    });
+}
+
+#[test]
+fn export_fingerprints() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::mhk::{testing::MhkBuilder, MhkMap};
+    use moiety::{
+        CurFormat, DirectMap, JsonFormat, Manifest, MixedFormat, PngFormat,
+        RawFormat, Resources,
+    };
+    use std::collections::HashMap;
+
+    let dir = std::env::temp_dir()
+        .join(format!("moiety-fingerprint-{}", std::process::id()));
+    let out = dir.join("out");
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut b = ResourceBuilder::new();
+    b.u16(1).u16(1).u16(10).u16(0).u16(0).u16(608).u16(392);
+    let plst = b.finish();
+    let write_archive = |bitmap_id: u8, padding: usize| {
+        let mut data = plst.clone();
+        data[5] = bitmap_id;
+        let mut b = MhkBuilder::new();
+        // a file no resource uses, to move the PLST along
+        b.add_file(&vec![0; padding], 0);
+        b.add("PLST", 1, &data);
+        std::fs::write(dir.join("j.MHK"), b.build().unwrap()).unwrap();
+    };
+    let export = |pretty: bool| smol::block_on(async {
+        use moiety::Stack as _;
+        let mut stackfiles: HashMap<_, _> = Stack::all().into_iter()
+            .map(|s| (s, vec!["none.MHK"]))
+            .collect();
+        stackfiles.insert(Stack::J, vec!["j.MHK"]);
+        let mut map = MhkMap::new(LocalFilesystem::new(&dir), stackfiles);
+        map.add_optional_file("none.MHK");
+        let mut rs = Resources::new(map);
+        let mut outfs = LocalFilesystem::new(&out);
+        let fmt = MixedFormat::new(PngFormat, CurFormat::default(),
+                                   JsonFormat(pretty))
+            .with_sound(RawFormat(".wav"))
+            .with_movie(RawFormat(".mov"))
+            .with_raw(RawFormat(".bin"));
+        let mut outrs = Resources::new(
+            DirectMap::new(LocalFilesystem::new(&out), fmt));
+        outrs.set_manifest(Some(Manifest::load(&mut outfs).await.unwrap()));
+        let report = rs.write_all_to(&mut outrs, &write_registry(), 1, |_| {})
+            .await.unwrap();
+        outrs.flush().await.unwrap();
+        outrs.manifest().unwrap().save(&mut outfs).await.unwrap();
+        report.written
+    });
+
+    write_archive(10, 0);
+    assert_eq!(export(false), 1);
+    assert_eq!(export(false), 0);
+    // a patched resource that moved
+    write_archive(11, 4);
+    assert_eq!(export(false), 1);
+    assert_eq!(export(false), 0);
+    // writers with different options don't share exports
+    assert_eq!(export(true), 1);
+    assert_eq!(export(true), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn stack_eviction() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::mhk::{testing::MhkBuilder, MhkMap};
+    use moiety::ResourceMap;
+    use std::collections::HashMap;
+
+    let dir = std::env::temp_dir()
+        .join(format!("moiety-evict-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut stackfiles = HashMap::new();
+    for (stack, name) in &[(Stack::A, "a.MHK"), (Stack::B, "b.MHK"),
+                           (Stack::G, "g.MHK")] {
+        let mut b = MhkBuilder::new();
+        b.add("tBMP", 1, b"abc");
+        std::fs::write(dir.join(name), b.build().unwrap()).unwrap();
+        stackfiles.insert(*stack, vec![*name]);
+    }
+
+    let mut map = MhkMap::new(LocalFilesystem::new(&dir), stackfiles);
+    map.set_memory_budget(Some(1));
+    map.pin_stack(Stack::G);
+    let loaded = |map: &MhkMap<_, _>| {
+        let mut stacks = map.loaded_stacks();
+        stacks.sort_by_key(|s| format!("{:?}", s));
+        stacks
+    };
+    smol::block_on(async {
+        map.preload(Stack::G).await.unwrap();
+        map.open_raw(Stack::A, "tBMP", 1, "").await.unwrap();
+        assert_eq!(loaded(&map), vec![Stack::A, Stack::G]);
+        // a preload can't push out the stack in use
+        map.preload(Stack::B).await.unwrap();
+        assert_eq!(loaded(&map), vec![Stack::A, Stack::G]);
+        // but using another stack does, except for pinned ones
+        map.open_raw(Stack::B, "tBMP", 1, "").await.unwrap();
+        assert_eq!(loaded(&map), vec![Stack::B, Stack::G]);
+        // and tables are all that count against the budget
+        assert!(map.memory_used() < 1 << 16);
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// files held whole in memory, like ones unpacked from an installer
+struct MemoryFilesystem(std::collections::HashMap<String, Vec<u8>>);
+
+#[async_trait::async_trait(?Send)]
+impl moiety::filesystem::Filesystem for MemoryFilesystem {
+    type Handle = smol::io::Cursor<Vec<u8>>;
+    async fn open(&mut self, path: &[&str]) -> anyhow::Result<Self::Handle> {
+        self.0.get(&path.join("/"))
+            .map(|data| smol::io::Cursor::new(data.clone()))
+            .ok_or_else(|| anyhow::anyhow!("no file {:?}", path))
+    }
+}
+
+#[test]
+fn memory_budget_counts_unpacked_files() {
+    use moiety::mhk::{testing::MhkBuilder, MhkMap};
+    use moiety::ResourceMap;
+    use std::collections::HashMap;
+
+    let mut files = HashMap::new();
+    let mut stackfiles = HashMap::new();
+    for (stack, name) in &[(Stack::A, "a.MHK"), (Stack::B, "b.MHK")] {
+        let mut b = MhkBuilder::new();
+        b.add("tBMP", 1, &[0; 1 << 16]);
+        files.insert(name.to_string(), b.build().unwrap());
+        stackfiles.insert(*stack, vec![*name]);
+    }
+
+    let mut map = MhkMap::new(MemoryFilesystem(files), stackfiles);
+    map.set_memory_budget(Some(100 << 10));
+    smol::block_on(async {
+        map.open_raw(Stack::A, "tBMP", 1, "").await.unwrap();
+        assert!(map.memory_used() > 1 << 16);
+        // both won't fit, so the older one goes
+        map.open_raw(Stack::B, "tBMP", 1, "").await.unwrap();
+        assert_eq!(map.loaded_stacks(), vec![Stack::B]);
+    });
+}
+
+#[test]
+fn patch_invalidates_cache() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::mhk::{testing::MhkBuilder, MhkMap};
+    use moiety::{Raw, RawResource, Resources};
+    use std::collections::HashMap;
+
+    let dir = std::env::temp_dir()
+        .join(format!("moiety-patch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, data) in &[("j.MHK", b"old"), ("j-patch.MHK", b"new")] {
+        let mut b = MhkBuilder::new();
+        b.add("XXXX", 1, *data);
+        std::fs::write(dir.join(name), b.build().unwrap()).unwrap();
+    }
+
+    let mut stackfiles = HashMap::new();
+    stackfiles.insert(Stack::J, vec!["j.MHK"]);
+    let map = MhkMap::new(LocalFilesystem::new(&dir), stackfiles);
+    let mut rs = Resources::new(map);
+    rs.set_cache_budget(Some(1 << 20));
+    smol::block_on(async {
+        let res = RawResource("XXXX");
+        assert_eq!(*rs.open(Stack::J, res, 1).await.unwrap(),
+                   Raw(b"old".to_vec()));
+        rs.add_patch_file(Stack::J, "j-patch.MHK");
+        assert_eq!(*rs.open(Stack::J, res, 1).await.unwrap(),
+                   Raw(b"new".to_vec()));
+    });
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn writer_finish() {
+    use moiety::filesystem::LocalFilesystem;
+    use moiety::{MhkWriter, ResourceMapWrite};
+
+    let dir = std::env::temp_dir()
+        .join(format!("moiety-writer-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut map = MhkWriter::new(LocalFilesystem::new(&dir));
+    assert!(!map.is_dirty());
+    smol::block_on(async {
+        map.write_raw(Stack::J, "NAME", 1, "", b"\0\0").await.unwrap();
+        assert!(map.is_dirty());
+        assert!(!dir.join("jspit.MHK").exists());
+        map.flush().await.unwrap();
+    });
+    assert!(!map.is_dirty());
+    assert!(dir.join("jspit.MHK").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// parse and re-serialize every CARD and HSPT in a real copy of the game.
+// run with MOIETY_RIVEN pointing at one and --ignored
+#[test]
+#[ignore]
+fn script_round_trip_game_data() {
+    use moiety::{ResourceMap, ResourceMapList, ResourceType, Stack as _};
+    use smol::io::AsyncReadExt;
+
+    let path = std::env::var("MOIETY_RIVEN")
+        .expect("MOIETY_RIVEN should point at a copy of Riven");
+    smol::block_on(async {
+        let fs = moiety::filesystem::LocalFilesystem::new(path);
+        let mut map = map_auto(fs).await.unwrap();
+        for stack in Stack::all() {
+            for typ in &[TCard.name(), THspt.name()] {
+                for id in map.list(stack, typ).await.unwrap() {
+                    let mut data = Vec::new();
+                    let mut handle =
+                        map.open_raw(stack, typ, id, "").await.unwrap();
+                    handle.read_to_end(&mut data).await.unwrap();
+                    let out = if *typ == "CARD" {
+                        roundtrip_bytes(TCard, &data)
+                    } else {
+                        roundtrip_bytes(THspt, &data)
+                    };
+                    assert_eq!(out.unwrap(), data, "{} {} {}",
+                               stack.name(), typ, id);
+                }
+            }
+        }
+    });
+}
+
+macro_rules! no_panic {
+    ($name:ident, $res:expr) => {
+        proptest! {
+            #[test]
+            fn $name(data in prop::collection::vec(any::<u8>(), 0..512)) {
+                let _ = parse_bytes($res, &data);
+            }
+        }
+    };
+}
+
+no_panic!(blst_garbage, TBlst);
+no_panic!(card_garbage, TCard);
+no_panic!(flst_garbage, TFlst);
+no_panic!(hspt_garbage, THspt);
+no_panic!(mlst_garbage, TMlst);
+no_panic!(name_garbage, TName);
+no_panic!(plst_garbage, TPlst);
+no_panic!(rmap_garbage, TRmap);
+no_panic!(sfxe_garbage, TSfxe);
+no_panic!(slst_garbage, TSlst);
+no_panic!(tbmp_garbage, TBmp);
+no_panic!(tcur_garbage, TCur);
+
+// (opcode, argument count) for commands with a fixed layout
+const SCRIPT_SHAPES: &[(u16, usize)] = &[
+    (1, 9), (2, 1), (4, 3), (7, 2), (9, 1), (10, 1), (12, 1), (13, 1),
+    (14, 2), (19, 0), (20, 0), (21, 0), (24, 2), (27, 3), (28, 1), (29, 0),
+    (31, 1), (32, 1), (33, 1), (34, 1), (36, 0), (37, 0), (38, 5), (39, 1),
+    (40, 1), (41, 1), (43, 1), (44, 1), (45, 0), (46, 2),
+];
+
+// a single command, as the u16 words it is made of
+fn script_command() -> impl Strategy<Value = Vec<u16>> {
+    let simple = (
+        prop::sample::select(SCRIPT_SHAPES),
+        prop::collection::vec(any::<u16>(), 9),
+    ).prop_map(|((cmd, n), args)| {
+        let mut words = vec![cmd, n as u16];
+        words.extend(&args[..n]);
+        words
+    });
+    let call = (
+        any::<u16>(),
+        prop::collection::vec(any::<u16>(), 0..4),
+        prop::collection::vec(any::<u16>(), 0..2),
+    ).prop_map(|(cmd, args, extra)| {
+        let len = 2 + args.len() + extra.len();
+        let mut words = vec![17, len as u16, cmd, args.len() as u16];
+        words.extend(args);
+        words.extend(extra);
+        words
+    });
+    let transition = (
+        any::<u16>(),
+        prop::option::of(any::<(u16, u16, u16, u16)>()),
+    ).prop_map(|(code, rect)| match rect {
+        Some((l, t, r, b)) => vec![18, 5, code, l, t, r, b],
+        None => vec![18, 1, code],
+    });
+    let slst = (
+        prop::collection::vec(any::<[u16; 4]>(), 0..3),
+        any::<[u16; 5]>(),
+    ).prop_map(|(sounds, fields)| {
+        let mut words = vec![3, 6 + 4 * sounds.len() as u16];
+        words.push(sounds.len() as u16);
+        words.extend(sounds.iter().map(|s| s[0]));
+        words.extend(&fields);
+        for i in 1..4 {
+            words.extend(sounds.iter().map(|s| s[i]));
+        }
+        words
+    });
+    let leaf = prop_oneof![simple, call, transition, slst];
+    leaf.prop_recursive(3, 32, 4, |inner| {
+        let commands = prop::collection::vec(inner, 0..4);
+        (
+            any::<u16>(),
+            prop::collection::hash_map(any::<u16>(), commands, 0..4),
+        ).prop_map(|(var, branches)| {
+            let mut words = vec![8, 2, var, branches.len() as u16];
+            for (value, commands) in branches {
+                words.push(value);
+                words.push(commands.len() as u16);
+                words.extend(commands.into_iter().flatten());
+            }
+            words
+        })
+    })
+}
+
+proptest! {
+    #[test]
+    fn tbmp_riven_stream_does_not_panic(
+        width in 0u16..64,
+        height in 0u16..64,
+        stream in prop::collection::vec(any::<u8>(), 0..512),
+    ) {
+        let mut b = ResourceBuilder::new();
+        b.u16(width).u16(height).u16(width).u16(2 | 4 << 8);
+        b.u16(4 + 3).u8(24).u8(1);
+        b.bytes(&[1, 2, 3]);
+        b.bytes(&[0, 0, 0, 0]);
+        b.bytes(&stream);
+        let _ = parse_bytes(TBmp, &b.finish());
+    }
+
+    #[test]
+    fn tbmp_encode_round_trip(
+        width in 0u16..40,
+        height in 0u16..20,
+        seed in prop::collection::vec(any::<u8>(), 1..64),
+        period in 1usize..200,
+    ) {
+        // repeat a short pattern so the lookback commands get used
+        let size = width as usize * height as usize;
+        let image: Vec<u8> = (0..size)
+            .map(|i| seed[(i % period) % seed.len()])
+            .collect();
+        let bmp = indexed_bitmap(width, height, image);
+        for &compression in &[TBmpCompression::None, TBmpCompression::Riven] {
+            let out = tbmp_round_trip(&bmp, compression);
+            prop_assert_eq!(&out.data, &bmp.data);
+        }
+    }
+
+    #[test]
+    fn card_script_round_trip(
+        name_rec in any::<i16>(),
+        zip in any::<u16>(),
+        events in Just(vec![0u16, 1, 2, 3, 4, 5, 6, 7, 9, 10]).prop_shuffle(),
+        count in 0usize..4,
+        handlers in prop::collection::vec(
+            prop::collection::vec(script_command(), 0..4), 4),
+    ) {
+        let mut b = ResourceBuilder::new();
+        b.i16(name_rec).u16(zip).u16(count as u16);
+        for (event, commands) in events.iter().zip(&handlers).take(count) {
+            b.u16(*event).u16(commands.len() as u16);
+            for word in commands.iter().flatten() {
+                b.u16(*word);
+            }
+        }
+        let card = b.finish();
+        prop_assert_eq!(roundtrip_bytes(TCard, &card).unwrap(), card);
+    }
+
+    #[test]
+    fn card_script_assembly_round_trip(
+        commands in prop::collection::vec(script_command(), 0..6),
+    ) {
+        let mut b = ResourceBuilder::new();
+        b.i16(0).u16(0).u16(1).u16(6).u16(commands.len() as u16);
+        for word in commands.iter().flatten() {
+            b.u16(*word);
+        }
+        let card = parse_bytes(TCard, &b.finish()).unwrap();
+        let names = ScriptNames::default();
+        let text = disassemble(&card.script, &names);
+        prop_assert_eq!(&assemble(&text, &names).unwrap(), &card.script);
+    }
+
+    #[test]
+    fn table_round_trip(
+        entries in prop::collection::vec(any::<(u16, u16, u16)>(), 0..32),
+    ) {
+        let mut b = ResourceBuilder::new();
+        b.u16(entries.len() as u16);
+        for (index, enabled, hotspot_id) in &entries {
+            b.u16(*index).u16(*enabled).u16(*hotspot_id);
+        }
+        let blst = parse_bytes(TBlst, &b.finish()).unwrap();
+        let expected: Vec<_> = entries.iter().map(|&(index, enabled, hotspot_id)| {
+            ButtonMeta { index, enabled, hotspot_id }
+        }).collect();
+        prop_assert_eq!(&*blst, &expected);
+    }
+}
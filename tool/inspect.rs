@@ -0,0 +1,97 @@
+use moiety::mhk::MhkArchive;
+
+use anyhow::Result;
+
+pub async fn inspect(path: &str) -> Result<()> {
+    let file = std::fs::File::open(path)?;
+    let arc = MhkArchive::new_lenient(smol::Unblock::new(file)).await?;
+
+    println!("{}", path);
+    println!("  MHWK file size: {}", arc.mhwk.file_size);
+    println!("  RSRC version: {:#06x}", arc.rsrc.version);
+    println!("  RSRC compaction: {}", arc.rsrc.compaction);
+    println!("  RSRC file size: {}", arc.rsrc.file_size);
+    println!("  resource dir offset: {}", arc.rsrc.resource_dir_offset);
+    println!("  file table offset: {}", arc.rsrc.file_table_offset);
+    println!("  file table size: {}", arc.rsrc.file_table_size);
+    println!("  file table entries: {}", arc.files.len());
+
+    println!();
+    println!("types:");
+    for entry in &arc.type_table {
+        let ty = String::from_utf8_lossy(&entry.resource_type);
+        let count = arc.resources.get(ty.as_ref()).map(|r| r.len())
+            .unwrap_or(0);
+        println!("  {} resource table {:#06x} name table {:#06x} ({} resources)",
+                 ty, entry.resource_table_offset, entry.name_table_offset,
+                 count);
+    }
+
+    println!();
+    println!("resources:");
+    let mut referenced = vec![0usize; arc.files.len()];
+    for entry in &arc.type_table {
+        let ty = String::from_utf8_lossy(&entry.resource_type);
+        let rs = match arc.resources.get(ty.as_ref()) {
+            Some(rs) => rs,
+            None => continue,
+        };
+        let mut ids: Vec<_> = rs.keys().cloned().collect();
+        ids.sort();
+        for id in ids {
+            let info = &rs[&id];
+            let file = &arc.files[info.file_table_index];
+            referenced[info.file_table_index] += 1;
+            println!("  {} {:5} file {:4} offset {:10} size {:8} flags {:#04x} {}",
+                     info.ty, info.id, info.file_table_index + 1,
+                     file.offset, file.size, file.flags,
+                     info.name.as_deref().unwrap_or(""));
+        }
+    }
+
+    println!();
+    println!("problems:");
+    let mut problems = 0;
+    for problem in &arc.problems {
+        println!("  {}", problem);
+        problems += 1;
+    }
+    for (i, count) in referenced.iter().enumerate() {
+        if *count == 0 {
+            println!("  file {} is not referenced by any resource", i + 1);
+            problems += 1;
+        }
+    }
+
+    // compare each file against whichever earlier one reaches furthest,
+    // so a big file is caught overlapping everything it covers
+    let mut order: Vec<usize> = (0..arc.files.len()).collect();
+    order.sort_by_key(|&i| (arc.files[i].offset, arc.files[i].size));
+    let end = |i: usize| arc.files[i].offset + arc.files[i].size;
+    let mut furthest: Option<usize> = None;
+    for &i in &order {
+        if let Some(j) = furthest.filter(|&j| end(j) > arc.files[i].offset) {
+            let (a, b) = (&arc.files[j], &arc.files[i]);
+            println!("  file {} ({}+{}) overlaps file {} ({}+{})",
+                     j + 1, a.offset, a.size, i + 1, b.offset, b.size);
+            problems += 1;
+        }
+        if furthest.is_none_or(|j| end(i) > end(j)) {
+            furthest = Some(i);
+        }
+    }
+
+    for (i, file) in arc.files.iter().enumerate() {
+        if file.offset + file.size > arc.rsrc.file_size as u64 {
+            println!("  file {} ({}+{}) extends past end of archive",
+                     i + 1, file.offset, file.size);
+            problems += 1;
+        }
+    }
+
+    if problems == 0 {
+        println!("  none");
+    }
+
+    Ok(())
+}
@@ -1,37 +1,374 @@
-use moiety::filesystem::{LocalFilesystem, LoggingFilesystem};
-use moiety::{DirectMap, JsonFormat, CurFormat, PngFormat, MixedFormat, Resources};
+use moiety::filesystem::LocalFilesystem;
+use moiety::{
+    AnyResource, BincodeFormat, Change, DirectMap, JsonFormat, CurFormat,
+    PngFormat, Manifest, MixedFormat, RawFormat, ResourceMap,
+    ResourceMapWrite, Resources, Stack,
+};
+use moiety::mhk::{LayoutFilesystem, MhkFormat, MhkLayout, MhkMap};
 use moiety::riven;
 
+use std::rc::Rc;
+
+mod inspect;
+
 fn main() -> anyhow::Result<()> {
+    let mut args: Vec<String> = std::env::args().collect();
+    let jobs = take_jobs(&mut args)?;
     smol::run(async {
-        let fs = LocalFilesystem::new("/Users/agrif/vault/games/riven/");
-        let outfs = LoggingFilesystem::new(
-            "out",
-            LocalFilesystem::new("./local/riven/"),
-        );
-
-        let map = riven::map_5cd(fs).await?;
-        let outmap = DirectMap::new(outfs, MixedFormat {
-            bitmap: PngFormat,
-            cursor: CurFormat,
-            record: JsonFormat(false),
-        });
-
-        let mut rs = Resources::new(map);
-        let mut outrs = Resources::new(outmap);
-
-        rs.write_to(&mut outrs, riven::TBlst).await?;
-        rs.write_to(&mut outrs, riven::TCard).await?;
-        rs.write_to(&mut outrs, riven::TFlst).await?;
-        rs.write_to(&mut outrs, riven::THspt).await?;
-        rs.write_to(&mut outrs, riven::TMlst).await?;
-        rs.write_to(&mut outrs, riven::TName).await?;
-        rs.write_to(&mut outrs, riven::TPlst).await?;
-        rs.write_to(&mut outrs, riven::TRmap).await?;
-        rs.write_to(&mut outrs, riven::TSfxe).await?;
-        rs.write_to(&mut outrs, riven::TSlst).await?;
-        rs.write_to(&mut outrs, riven::TBmp).await?;
-        rs.write_to(&mut outrs, riven::TCur).await?;
-        Ok(())
+        match args.get(1).map(|s| s.as_str()) {
+            None | Some("export") => export(args.get(2), jobs).await,
+            Some("cache") => cache(args.get(2), jobs).await,
+            Some("verify") => match args.get(2).map(|s| s.as_str()) {
+                None | Some("export") => verify(export_format(), args.get(3))
+                    .await,
+                Some("cache") => verify(BincodeFormat, args.get(3)).await,
+                Some(_) => anyhow::bail!(
+                    "usage: {} verify [export|cache] [LAYOUT]", args[0]),
+            },
+            Some("show") => {
+                if args.len() < 4 || args.len() > 5 {
+                    anyhow::bail!("usage: {} show STACK TYPE [ID]", args[0]);
+                }
+                show(&args[2], &args[3], args.get(4)).await
+            }
+            Some("diff") => {
+                if args.len() != 4 {
+                    anyhow::bail!("usage: {} diff OLD_DIR NEW_DIR", args[0]);
+                }
+                diff(&args[2], &args[3]).await
+            }
+            Some("refs") => {
+                if args.len() != 3 && args.len() != 5 {
+                    anyhow::bail!("usage: {} refs STACK [TYPE ID]", args[0]);
+                }
+                refs(&args[2], args.get(3).zip(args.get(4))).await
+            }
+            Some("disasm") => {
+                if args.len() != 5 {
+                    anyhow::bail!("usage: {} disasm STACK CARD|HSPT ID",
+                                  args[0]);
+                }
+                disasm(&args[2], &args[3], &args[4]).await
+            }
+            Some("asm") => match args.len() {
+                6 => asm(&args[2], &args[3], &args[4], None, &args[5]).await,
+                7 => asm(&args[2], &args[3], &args[4], Some(&args[5]),
+                         &args[6]).await,
+                _ => anyhow::bail!(
+                    "usage: {} asm STACK CARD ID FILE\n       \
+                     {} asm STACK HSPT ID BLST FILE", args[0], args[0]),
+            },
+            Some("inspect") => {
+                if args.len() < 3 {
+                    anyhow::bail!("usage: {} inspect FILE.MHK...", args[0]);
+                }
+                for path in &args[2..] {
+                    inspect::inspect(path).await?;
+                }
+                Ok(())
+            }
+            Some(cmd) => anyhow::bail!("unknown command: {}", cmd),
+        }
     })
 }
+
+type RivenMap = MhkMap<LayoutFilesystem<LocalFilesystem>, riven::Stack>;
+
+async fn open_map(layout: Option<&String>) -> anyhow::Result<RivenMap> {
+    open_map_at("/Users/agrif/vault/games/riven/", layout).await
+}
+
+async fn open_map_at(path: &str, layout: Option<&String>)
+                     -> anyhow::Result<RivenMap>
+{
+    let mut fs = LocalFilesystem::new(path);
+    let layout = if let Some(path) = layout {
+        let mut layout = MhkLayout::from_json(&std::fs::read(path)?)?;
+        layout.inherit(&riven::layouts())?;
+        layout
+    } else {
+        MhkLayout::detect(&riven::layouts(), &mut fs).await?.clone()
+    };
+    let mut map = riven::map_layout(fs, &layout).await?;
+    // export everything, even commands we don't understand yet
+    map.set_lenient_scripts(true);
+    Ok(map)
+}
+
+// how many conversions to run at once during exports, from -j N or
+// --jobs N anywhere on the command line. defaults to one per cpu
+fn take_jobs(args: &mut Vec<String>) -> anyhow::Result<usize> {
+    let pos = match args.iter().position(|a| a == "-j" || a == "--jobs") {
+        Some(pos) => pos,
+        None => return Ok(std::thread::available_parallelism()
+                          .map(|n| n.get())
+                          .unwrap_or(1)),
+    };
+    let jobs = args.get(pos + 1)
+        .and_then(|n| n.parse().ok())
+        .filter(|&n| n > 0)
+        .ok_or_else(|| anyhow::anyhow!("{} needs a number above 0",
+                                       args[pos]))?;
+    args.drain(pos..pos + 2);
+    Ok(jobs)
+}
+
+// only resources that changed since the manifest in outdir was written
+// are redone
+async fn write_all<Mw>(outmap: Mw, outdir: &str, layout: Option<&String>,
+                       jobs: usize) -> anyhow::Result<()>
+where
+    Mw: ResourceMapWrite<Stack = riven::Stack>,
+    Mw::Format: riven::RivenFormatWriteAll<
+        MhkFormat, smol::io::Cursor<Vec<u8>>> + Clone + Send + 'static,
+{
+    let mut rs = Resources::new(open_map(layout).await?);
+    let mut outrs = Resources::new(outmap);
+    let mut outfs = LocalFilesystem::new(outdir);
+    outrs.set_manifest(Some(Manifest::load(&mut outfs).await?));
+    let registry = riven::write_registry();
+    let report = rs.write_all_to(&mut outrs, &registry, jobs, |p| {
+        const WIDTH: usize = 30;
+        let filled = WIDTH * p.done / p.total.max(1);
+        eprint!("\r[{}{}] {}/{} {} {:<5}",
+                "#".repeat(filled), " ".repeat(WIDTH - filled),
+                p.done, p.total, p.typ, p.id);
+    }).await?;
+    eprintln!();
+    outrs.flush().await?;
+    if let Some(m) = outrs.manifest() {
+        m.save(&mut outfs).await?;
+    }
+
+    for e in &report.errors {
+        eprintln!("{} {} {}: {:#}", e.stack.name(), e.typ, e.id, e.error);
+    }
+    println!("wrote {} of {} resources, {} up to date",
+             report.written, report.total, report.skipped);
+    if !report.errors.is_empty() {
+        anyhow::bail!("{} resources failed", report.errors.len());
+    }
+    Ok(())
+}
+
+type ExportFormat = MixedFormat<
+    PngFormat, CurFormat, JsonFormat, RawFormat, RawFormat, RawFormat>;
+
+// sounds and movies are already in formats other tools can open
+fn export_format() -> ExportFormat {
+    MixedFormat::new(PngFormat, CurFormat::default(), JsonFormat(false))
+        .with_sound(RawFormat(".wav"))
+        .with_movie(RawFormat(".mov"))
+        .with_raw(RawFormat(".bin"))
+}
+
+async fn export(layout: Option<&String>, jobs: usize)
+                -> anyhow::Result<()>
+{
+    let outfs = LocalFilesystem::new("./local/riven/");
+    let outmap = DirectMap::new(outfs, export_format());
+
+    write_all(outmap, "./local/riven/", layout, jobs).await
+}
+
+// pre-decode everything, for the engine to load at startup
+async fn cache(layout: Option<&String>, jobs: usize)
+               -> anyhow::Result<()>
+{
+    let outfs = LocalFilesystem::new("./local/riven-cache/");
+    let outmap = DirectMap::new(outfs, BincodeFormat);
+
+    write_all(outmap, "./local/riven-cache/", layout, jobs).await?;
+    let mut outfs = LocalFilesystem::new("./local/riven-cache/");
+    BincodeFormat::mark_cache(&mut outfs).await
+}
+
+// print one resource, or list them all if there's no id
+async fn show(stack: &str, typ: &str, id: Option<&String>)
+              -> anyhow::Result<()>
+{
+    let stack = riven::Stack::from_name(stack)
+        .ok_or_else(|| anyhow::anyhow!("unknown stack: {}", stack))?;
+    let registry = riven::registry();
+    let mut rs = Resources::new(open_map(None).await?);
+
+    let id = match id {
+        Some(id) => id.parse()?,
+        None => {
+            for id in registry.list(&mut rs, stack, typ).await? {
+                println!("{}", id);
+            }
+            return Ok(());
+        }
+    };
+    match registry.open(&mut rs, stack, typ, id).await? {
+        AnyResource::Bitmap(bmp) => {
+            println!("bitmap {}x{}", bmp.width, bmp.height);
+        }
+        AnyResource::Cursor(cur) => {
+            println!("cursor {}x{}, hotspot {:?}",
+                     cur.width, cur.height, cur.hotspot);
+        }
+        AnyResource::Record(value) => {
+            println!("{}", serde_json::to_string_pretty(&value)?);
+        }
+        AnyResource::Sound(snd) => println!("sound, {} bytes", snd.0.len()),
+        AnyResource::Movie(mov) => println!("movie, {} bytes", mov.0.len()),
+        AnyResource::Raw(raw) => println!("{} bytes", raw.0.len()),
+    }
+    Ok(())
+}
+
+// check that everything survives a trip through fmt
+async fn verify<F>(fmt: F, layout: Option<&String>) -> anyhow::Result<()>
+where
+    F: riven::RivenFormatWriteAll<
+        MhkFormat, <RivenMap as ResourceMap>::Handle>
+        + riven::RivenFormatAll<smol::io::Cursor<Vec<u8>>>,
+{
+    let mut rs = Resources::new(open_map(layout).await?);
+    let registry = riven::verify_registry();
+    let mismatches = registry.verify_all(&mut rs, &fmt, |done, total| {
+        eprint!("\rverified {}/{}", done, total);
+    }).await?;
+    eprintln!();
+
+    for m in &mismatches {
+        println!("{} {} {}: {}", m.stack.name(), m.typ, m.id, m.problem);
+    }
+    if !mismatches.is_empty() {
+        anyhow::bail!("{} resources did not round-trip", mismatches.len());
+    }
+    Ok(())
+}
+
+// print a card's script, or the scripts of every hotspot on it
+async fn disasm(stack: &str, typ: &str, id: &str) -> anyhow::Result<()> {
+    let stack = riven::Stack::from_name(stack)
+        .ok_or_else(|| anyhow::anyhow!("unknown stack: {}", stack))?;
+    let id = id.parse()?;
+    let mut rs = Resources::new(open_map(None).await?);
+    let names = riven::ScriptNames::load(&mut rs, stack).await?;
+    match typ {
+        "CARD" => {
+            let card = rs.open(stack, riven::TCard, id).await?;
+            print!("{}", riven::disassemble(&card.script, &names));
+        }
+        "HSPT" => {
+            for h in rs.open(stack, riven::THspt, id).await?.iter() {
+                println!("# hotspot {}", h.blst_id);
+                print!("{}", riven::disassemble(&h.script, &names));
+            }
+        }
+        _ => anyhow::bail!("only CARD and HSPT have scripts"),
+    }
+    Ok(())
+}
+
+// replace a script with one assembled from FILE, saving the result where
+// the engine looks for mods
+async fn asm(stack: &str, typ: &str, id: &str, blst: Option<&String>,
+             file: &str) -> anyhow::Result<()>
+{
+    let stack = riven::Stack::from_name(stack)
+        .ok_or_else(|| anyhow::anyhow!("unknown stack: {}", stack))?;
+    let id = id.parse()?;
+    let text = std::fs::read_to_string(file)?;
+    let mut rs = Resources::new(open_map(None).await?);
+    let names = riven::ScriptNames::load(&mut rs, stack).await?;
+    let script = riven::assemble(&text, &names)?;
+
+    let data = match (typ, blst) {
+        ("CARD", None) => {
+            let mut card = Rc::unwrap_or_clone(
+                rs.open(stack, riven::TCard, id).await?);
+            card.script = script;
+            serde_json::to_vec(&*card)?
+        }
+        ("HSPT", Some(blst)) => {
+            let blst: u16 = blst.parse()?;
+            let mut hspt = Rc::unwrap_or_clone(
+                rs.open(stack, riven::THspt, id).await?);
+            let h = hspt.iter_mut().find(|h| h.blst_id == blst)
+                .ok_or_else(|| anyhow::anyhow!("no hotspot {}", blst))?;
+            h.script = script;
+            serde_json::to_vec(&*hspt)?
+        }
+        _ => anyhow::bail!("expected CARD ID or HSPT ID BLST"),
+    };
+
+    let outfs = LocalFilesystem::new("./local/riven-mods/");
+    let mut outmap = DirectMap::new(outfs, export_format());
+    outmap.write_raw(stack, typ, id, ".json", &data).await?;
+    outmap.flush().await?;
+    Ok(())
+}
+
+// what changed between two editions of the game, like CD and DVD
+async fn diff(old: &str, new: &str) -> anyhow::Result<()> {
+    // only this many lines per changed resource
+    const LINES: usize = 20;
+
+    let mut oldrs = Resources::new(open_map_at(old, None).await?);
+    let mut newrs = Resources::new(open_map_at(new, None).await?);
+    let registry = riven::registry();
+    let differences = registry.diff(
+        &mut oldrs, &riven::registry(), &mut newrs, |done, total| {
+            eprint!("\rcompared {}/{}", done, total);
+        }).await?;
+    eprintln!();
+
+    let mut group = None;
+    for d in &differences {
+        if group != Some((&d.stack, &d.typ)) {
+            group = Some((&d.stack, &d.typ));
+            println!("{} {}:", d.stack.name(), d.typ);
+        }
+        match &d.change {
+            Change::Added => println!("  + {}", d.id),
+            Change::Removed => println!("  - {}", d.id),
+            Change::Changed(lines) => {
+                println!("  ~ {}", d.id);
+                for line in lines.iter().take(LINES) {
+                    println!("      {}", line);
+                }
+                if lines.len() > LINES {
+                    println!("      ... {} more", lines.len() - LINES);
+                }
+            }
+        }
+    }
+
+    let count = |f: fn(&Change) -> bool| {
+        differences.iter().filter(|d| f(&d.change)).count()
+    };
+    println!("{} added, {} removed, {} changed",
+             count(|c| *c == Change::Added),
+             count(|c| *c == Change::Removed),
+             count(|c| matches!(c, Change::Changed(_))));
+    Ok(())
+}
+
+// who references a resource, or every reference that leads nowhere
+async fn refs(stack: &str, target: Option<(&String, &String)>)
+              -> anyhow::Result<()>
+{
+    let stack = riven::Stack::from_name(stack)
+        .ok_or_else(|| anyhow::anyhow!("unknown stack: {}", stack))?;
+    let mut rs = Resources::new(open_map(None).await?);
+    let xref = riven::CrossReference::build(&mut rs, stack).await?;
+
+    if let Some((typ, id)) = target {
+        for r in xref.referencing(stack, typ, id.parse()?) {
+            println!("{} ({} -> {})", r.from, r.via, r.to);
+        }
+        return Ok(());
+    }
+    let mut count = 0;
+    for r in xref.dangling() {
+        println!("{} ({}): missing {}", r.from, r.via, r.to);
+        count += 1;
+    }
+    println!("{} references, {} dangling", xref.references().len(), count);
+    Ok(())
+}